@@ -0,0 +1,187 @@
+//! Throughput of channels and SFTP against a real server, configured like the integration tests.
+//!
+//! $ SSH_SERVER_HOST=127.0.0.1 SSH_SERVER_PORT=22 SSH_USERNAME=root SSH_PASSWORD=xxxxxx cargo bench -p async-ssh2-lite --features tokio,async-io
+//!
+//! Authenticates with `SSH_PASSWORD`, otherwise with the key at `SSH_PRIVATEKEY_PATH`.
+//! `BENCH_MB` sets the size of each transfer, 64 by default.
+
+use std::{
+    env, error,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream};
+use futures_util::{future, AsyncReadExt as _, AsyncWriteExt as _};
+
+const CHANNELS: usize = 4;
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let addr = SocketAddr::from((
+        env::var("SSH_SERVER_HOST")
+            .expect("Missing SSH_SERVER_HOST")
+            .parse::<IpAddr>()?,
+        env::var("SSH_SERVER_PORT")
+            .expect("Missing SSH_SERVER_PORT")
+            .parse::<u16>()?,
+    ));
+    let len = env::var("BENCH_MB")
+        .ok()
+        .map(|x| x.parse::<usize>())
+        .transpose()?
+        .unwrap_or(64)
+        * 1024
+        * 1024;
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let session = AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(addr, None).await?;
+        run("tokio", session, len).await
+    })?;
+
+    futures_lite::future::block_on(async {
+        let session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(addr, None).await?;
+        run("async-io", session, len).await
+    })?;
+
+    Ok(())
+}
+
+async fn run<S: AsyncSessionStream + Send + Sync + 'static>(
+    backend: &str,
+    mut session: AsyncSession<S>,
+    len: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    session.handshake().await?;
+    let username = env::var("SSH_USERNAME").expect("Missing SSH_USERNAME");
+    match env::var("SSH_PASSWORD") {
+        Ok(password) => session.userauth_password(&username, &password).await?,
+        Err(_) => {
+            let privatekey = PathBuf::from(
+                env::var("SSH_PRIVATEKEY_PATH").expect("Missing SSH_PRIVATEKEY_PATH"),
+            );
+            session
+                .userauth_pubkey_file(&username, None, &privatekey, None)
+                .await?
+        }
+    }
+
+    let started = Instant::now();
+    channel_download(&session, len).await?;
+    report(backend, "channel download", len, started.elapsed());
+
+    let started = Instant::now();
+    channel_upload(&session, len).await?;
+    report(backend, "channel upload", len, started.elapsed());
+
+    let started = Instant::now();
+    future::try_join_all((0..CHANNELS).map(|_| channel_download(&session, len / CHANNELS))).await?;
+    report(
+        backend,
+        &format!("channel download x{CHANNELS}"),
+        len,
+        started.elapsed(),
+    );
+
+    let remote_path = PathBuf::from("/tmp").join(format!("bench_{}", uuid::Uuid::new_v4()));
+
+    let started = Instant::now();
+    sftp_upload(&session, &remote_path, len).await?;
+    report(backend, "sftp upload", len, started.elapsed());
+
+    let started = Instant::now();
+    sftp_download(&session, &remote_path, len).await?;
+    report(backend, "sftp download", len, started.elapsed());
+
+    session.sftp().await?.unlink(&remote_path).await?;
+
+    Ok(())
+}
+
+async fn channel_download<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+    len: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut channel = session.channel_session().await?;
+    channel.exec(&format!("head -c {len} /dev/zero")).await?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut n = 0;
+    loop {
+        match channel.read(&mut buf).await? {
+            0 => break,
+            x => n += x,
+        }
+    }
+    assert_eq!(n, len);
+    channel.close().await?;
+
+    Ok(())
+}
+
+async fn channel_upload<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+    len: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut channel = session.channel_session().await?;
+    channel.exec("cat > /dev/null").await?;
+    let buf = vec![0; 64 * 1024];
+    let mut n = 0;
+    while n < len {
+        let x = (len - n).min(buf.len());
+        channel.write_all(&buf[..x]).await?;
+        n += x;
+    }
+    channel.send_eof().await?;
+    channel.wait_eof().await?;
+    channel.close().await?;
+
+    Ok(())
+}
+
+async fn sftp_upload<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+    remote_path: &Path,
+    len: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    let sftp = session.sftp().await?;
+    let mut file = sftp.create(remote_path).await?;
+    let buf = vec![0; 32 * 1024];
+    let mut n = 0;
+    while n < len {
+        let x = (len - n).min(buf.len());
+        file.write_all(&buf[..x]).await?;
+        n += x;
+    }
+    file.close().await?;
+
+    Ok(())
+}
+
+async fn sftp_download<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+    remote_path: &Path,
+    len: usize,
+) -> Result<(), Box<dyn error::Error>> {
+    let sftp = session.sftp().await?;
+    let mut file = sftp.open(remote_path).await?;
+    let mut buf = vec![0; 32 * 1024];
+    let mut n = 0;
+    loop {
+        match file.read(&mut buf).await? {
+            0 => break,
+            x => n += x,
+        }
+    }
+    assert_eq!(n, len);
+    file.close().await?;
+
+    Ok(())
+}
+
+fn report(backend: &str, name: &str, len: usize, elapsed: Duration) {
+    println!(
+        "{backend:>8} {name:<24} {:>8.1} MiB/s ({elapsed:?})",
+        len as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64()
+    );
+}
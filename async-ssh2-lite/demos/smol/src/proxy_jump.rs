@@ -2,8 +2,6 @@
 cargo run -p async-ssh2-lite-demo-smol --bin proxy_jump 172.17.0.1:2224 user_intranet 127.0.0.1:2223 user_bastion
 */
 
-#![recursion_limit = "256"]
-
 use std::env;
 use std::error;
 use std::io;
@@ -14,18 +12,9 @@ use async_executor::{Executor, LocalExecutor, Task};
 use async_io::Async;
 use easy_parallel::Parallel;
 use futures::executor::block_on;
-use futures::future::FutureExt;
-use futures::select;
-use futures::{AsyncReadExt, AsyncWriteExt};
-
-#[cfg(not(unix))]
-use std::net::TcpListener;
-#[cfg(unix)]
-use std::os::unix::net::{UnixListener, UnixStream};
-#[cfg(unix)]
-use tempfile::tempdir;
+use futures::AsyncReadExt;
 
-use async_ssh2_lite::AsyncSession;
+use async_ssh2_lite::{util::ConnectInfo, AsyncSession};
 
 fn main() -> Result<(), Box<dyn error::Error>> {
     let ex = Executor::new();
@@ -71,8 +60,6 @@ async fn run(ex: Arc<Executor<'_>>) -> Result<(), Box<dyn error::Error>> {
     let mut receivers = vec![];
     let (sender_with_main, receiver) = async_channel::unbounded();
     receivers.push(receiver);
-    let (sender_with_forward, receiver) = async_channel::unbounded();
-    receivers.push(receiver);
 
     let task_with_main: Task<io::Result<()>> = ex.clone().spawn(async move {
         let bastion_stream = Async::<TcpStream>::connect(bastion_addr).await?;
@@ -88,11 +75,8 @@ async fn run(ex: Arc<Executor<'_>>) -> Result<(), Box<dyn error::Error>> {
         if !bastion_session.authenticated() {
             return Err(bastion_session
                 .last_error()
-                .map( io::Error::from)
-                .unwrap_or_else(||io::Error::new(
-                    io::ErrorKind::Other,
-                    "bastion unknown userauth error",
-                )));
+                .map(io::Error::from)
+                .unwrap_or_else(|| io::Error::other("bastion unknown userauth error")));
         }
 
         let mut channel = bastion_session.channel_session().await?;
@@ -103,98 +87,34 @@ async fn run(ex: Arc<Executor<'_>>) -> Result<(), Box<dyn error::Error>> {
         channel.close().await?;
         println!("bastion channel exit_status:{}", channel.exit_status()?);
 
-        let mut bastion_channel = bastion_session
-            .channel_direct_tcpip(addr.ip().to_string().as_ref(), addr.port(), None)
+        let forward = bastion_session
+            .local_port_forwarding(
+                ConnectInfo::with_tcp(([127, 0, 0, 1], 0)),
+                addr.ip().to_string().as_ref(),
+                addr.port(),
+                None,
+            )
             .await?;
-
-        //
-        let (forward_stream_s, mut forward_stream_r) = {
-            cfg_if::cfg_if! {
-                if #[cfg(unix)] {
-                    let dir = tempdir()?;
-                    let path = dir.path().join("ssh_channel_direct_tcpip");
-                    let listener = Async::<UnixListener>::bind(&path)?;
-                    let stream_s = Async::<UnixStream>::connect(&path).await?;
-                } else {
-                    let listen_addr = TcpListener::bind("localhost:0")
-                        .unwrap()
-                        .local_addr()
-                        .unwrap();
-                    let listener = Async::<TcpListener>::bind(listen_addr)?;
-                    let stream_s = Async::<TcpStream>::connect(listen_addr).await?;
-                }
-            }
-
-            let (stream_r,_) = listener.accept().await.unwrap();
-
-            (stream_s, stream_r)
+        let forward_addr = match forward.local_addr() {
+            ConnectInfo::Tcp(addr) => *addr,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
         };
-
-        let task_with_forward: Task<io::Result<()>> = ex.clone().spawn(async move {
-            let mut buf_bastion_channel = vec![0; 2048];
-            let mut buf_forward_stream_r = vec![0; 2048];
-
-            loop {
-                select! {
-                    ret_forward_stream_r = forward_stream_r.read(&mut buf_forward_stream_r).fuse() => match ret_forward_stream_r {
-                        Ok(0) => {
-                            println!("forward_stream_r read 0");
-                            break
-                        },
-                        Ok(n) => {
-                            println!("forward_stream_r read {n}");
-                            bastion_channel.write(&buf_forward_stream_r[..n]).await.map(|_| ()).map_err(|err| {
-                                eprintln!("bastion_channel write failed, err:{err:?}");
-                                err
-                            })?
-                        },
-                        Err(err) =>  {
-                            eprintln!("forward_stream_r read failed, err:{err:?}");
-
-                            return Err(err);
-                        }
-                    },
-                    ret_bastion_channel = bastion_channel.read(&mut buf_bastion_channel).fuse() => match ret_bastion_channel {
-                        Ok(0) => {
-                            println!("bastion_channel read 0");
-                            break
-                        },
-                        Ok(n) => {
-                            println!("bastion_channel read {n}");
-                            forward_stream_r.write(&buf_bastion_channel[..n]).await.map(|_| ()).map_err(|err| {
-                                eprintln!("forward_stream_r write failed, err:{err:?}");
-                                err
-                            })?
-                        },
-                        Err(err) => {
-                            eprintln!("bastion_channel read failed, err:{err:?}");
-
-                            return Err(err);
-                        }
-                    },
-                }
-            }
-
-            sender_with_forward.send("done_with_forward").await.unwrap();
-
-            Ok(())
-        });
-        task_with_forward.detach();
+        let forward_stream = Async::<TcpStream>::connect(forward_addr).await?;
 
         //
-        let mut session = AsyncSession::new(forward_stream_s, None)?;
+        let mut session = AsyncSession::new(forward_stream, None)?;
         session.handshake().await?;
 
-        session.userauth_agent_with_try_next(username.as_ref()).await?;
+        session
+            .userauth_agent_with_try_next(username.as_ref())
+            .await?;
 
         if !session.authenticated() {
             return Err(session
                 .last_error()
                 .map(io::Error::from)
-                .unwrap_or_else(||io::Error::new(
-                    io::ErrorKind::Other,
-                    "unknown userauth error",
-                )));
+                .unwrap_or_else(|| io::Error::other("unknown userauth error")));
         }
 
         let mut channel = session.channel_session().await?;
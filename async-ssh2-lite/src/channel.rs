@@ -1,14 +1,30 @@
-use std::sync::Arc;
-
+use core::{
+    task::{Context, Poll},
+    time::Duration,
+};
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind, Write as _},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures_util::{future, ready, task::AtomicWaker};
 use ssh2::{Channel, ExitSignal, ExtendedData, PtyModes, ReadWindow, Session, Stream, WriteWindow};
 
-use crate::{error::Error, session_stream::AsyncSessionStream};
+use crate::{
+    error::Error,
+    session_stream::{AsyncSessionStream, PollTimeout},
+};
 
 //
 pub struct AsyncChannel<S> {
     inner: Channel,
     sess: Session,
     stream: Arc<S>,
+    timeout: PollTimeout,
+    eof_sent: bool,
 }
 
 impl<S> AsyncChannel<S> {
@@ -17,8 +33,16 @@ impl<S> AsyncChannel<S> {
             inner,
             sess,
             stream,
+            timeout: PollTimeout::default(),
+            eof_sent: false,
         }
     }
+
+    /// How long a read or write through `AsyncRead`/`AsyncWrite` may stall, `None` falls back to
+    /// the session timeout. Streams from `stream` and `stderr` inherit it.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout.set_timeout(timeout);
+    }
 }
 
 impl<S> AsyncChannel<S>
@@ -75,6 +99,16 @@ where
             .await
     }
 
+    pub async fn exec_with_timeout(
+        &mut self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<(), Error> {
+        self.stream
+            .rw_with_timeout(|| self.inner.exec(command), &self.sess, Some(timeout))
+            .await
+    }
+
     pub async fn shell(&mut self) -> Result<(), Error> {
         self.stream.rw_with(|| self.inner.shell(), &self.sess).await
     }
@@ -96,15 +130,20 @@ where
     }
 
     pub fn stderr(&self) -> AsyncStream<S> {
-        AsyncStream::from_parts(self.inner.stderr(), self.sess.clone(), self.stream.clone())
+        let mut stream =
+            AsyncStream::from_parts(self.inner.stderr(), self.sess.clone(), self.stream.clone());
+        stream.set_timeout(self.timeout.timeout());
+        stream
     }
 
     pub fn stream(&self, stream_id: i32) -> AsyncStream<S> {
-        AsyncStream::from_parts(
+        let mut stream = AsyncStream::from_parts(
             self.inner.stream(stream_id),
             self.sess.clone(),
             self.stream.clone(),
-        )
+        );
+        stream.set_timeout(self.timeout.timeout());
+        stream
     }
 
     pub async fn handle_extended_data(&mut self, mode: ExtendedData) -> Result<(), Error> {
@@ -144,7 +183,9 @@ where
     pub async fn send_eof(&mut self) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.send_eof(), &self.sess)
-            .await
+            .await?;
+        self.eof_sent = true;
+        Ok(())
     }
 
     pub async fn wait_eof(&mut self) -> Result<(), Error> {
@@ -162,6 +203,251 @@ where
             .rw_with(|| self.inner.wait_close(), &self.sess)
             .await
     }
+
+    /// Splits into stdout, stdin and stderr, each usable from its own task. EOF is sent by
+    /// `AsyncChannelWriteHalf::send_eof` or closing the write half, see its docs for dropping.
+    pub fn into_split(
+        self,
+    ) -> (
+        AsyncChannelReadHalf<S>,
+        AsyncChannelWriteHalf<S>,
+        AsyncStream<S>,
+    ) {
+        let stderr = self.stderr();
+        let stdout = self.inner.stream(0);
+        let stdin = self.inner.stream(0);
+        let shared = Arc::new(SplitShared {
+            channel: Mutex::new(self.inner),
+            eof_pending: AtomicBool::new(false),
+            reader_waker: AtomicWaker::new(),
+        });
+
+        let read_half = AsyncChannelReadHalf {
+            inner: stdout,
+            shared: shared.clone(),
+            sess: self.sess.clone(),
+            stream: self.stream.clone(),
+            timeout: PollTimeout::new(self.timeout.timeout()),
+        };
+        let spawn_eof = spawn_eof(
+            shared.clone(),
+            self.sess.clone(),
+            self.stream.clone(),
+            self.timeout.timeout(),
+        );
+        let write_half = AsyncChannelWriteHalf {
+            inner: stdin,
+            shared,
+            sess: self.sess,
+            stream: self.stream,
+            timeout: PollTimeout::new(self.timeout.timeout()),
+            eof_sent: self.eof_sent,
+            spawn_eof: Some(spawn_eof),
+        };
+        (read_half, write_half, stderr)
+    }
+
+    /// Backs `poll_close` and `poll_shutdown`, so copies half-close the channel.
+    fn poll_send_eof(&mut self, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        if self.eof_sent {
+            return Poll::Ready(Ok(()));
+        }
+
+        let inner = &mut self.inner;
+        ready!(self.stream.poll_write_with_timeout(
+            cx,
+            || inner.send_eof().map_err(Into::into),
+            &self.sess,
+            &mut self.timeout
+        ))?;
+        self.eof_sent = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+//
+struct SplitShared {
+    channel: Mutex<Channel>,
+    /// Set when the write half was dropped while EOF could not be sent and no task could be
+    /// spawned to send it, the read half sends it then.
+    eof_pending: AtomicBool,
+    reader_waker: AtomicWaker,
+}
+
+impl SplitShared {
+    fn send_eof(&self) -> Result<(), IoError> {
+        let mut channel = self.channel.lock().expect("channel lock poisoned");
+        channel.send_eof().map_err(Into::into)
+    }
+}
+
+/// Sends EOF from a task on the current runtime, `false` when there is none to spawn it on.
+type SpawnEof = Box<dyn FnOnce() -> bool + Send + Sync>;
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+fn spawn_eof<S>(
+    shared: Arc<SplitShared>,
+    sess: Session,
+    stream: Arc<S>,
+    timeout: Option<Duration>,
+) -> SpawnEof
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    Box::new(move || {
+        let Ok(runtime) = crate::runtime::Runtime::current() else {
+            return false;
+        };
+        let fut = async move {
+            let mut timeout = PollTimeout::new(timeout);
+            let _ = future::poll_fn(|cx| {
+                stream.poll_write_with_timeout(cx, || shared.send_eof(), &sess, &mut timeout)
+            })
+            .await;
+        };
+        runtime.spawn(fut).is_ok()
+    })
+}
+
+#[cfg(not(any(feature = "tokio", feature = "async-io")))]
+fn spawn_eof<S>(
+    _shared: Arc<SplitShared>,
+    _sess: Session,
+    _stream: Arc<S>,
+    _timeout: Option<Duration>,
+) -> SpawnEof {
+    Box::new(|| false)
+}
+
+/// Reads stdout, see `AsyncChannel::into_split`.
+pub struct AsyncChannelReadHalf<S> {
+    inner: Stream,
+    shared: Arc<SplitShared>,
+    sess: Session,
+    stream: Arc<S>,
+    timeout: PollTimeout,
+}
+
+impl<S> AsyncChannelReadHalf<S> {
+    /// How long a read may stall, `None` falls back to the session timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout.set_timeout(timeout);
+    }
+
+    pub fn exit_status(&self) -> Result<i32, Error> {
+        let channel = self.shared.channel.lock().expect("channel lock poisoned");
+        channel.exit_status().map_err(Into::into)
+    }
+
+    pub async fn exit_signal(&self) -> Result<ExitSignal, Error> {
+        let channel = self.shared.channel.lock().expect("channel lock poisoned");
+        channel.exit_signal().map_err(Into::into)
+    }
+
+    pub fn eof(&self) -> bool {
+        let channel = self.shared.channel.lock().expect("channel lock poisoned");
+        channel.eof()
+    }
+}
+
+impl<S> AsyncChannelReadHalf<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    /// Sends the EOF a dropped write half left behind.
+    fn poll_pending_eof(&mut self, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        self.shared.reader_waker.register(cx.waker());
+        if !self.shared.eof_pending.load(Ordering::SeqCst) {
+            return Poll::Ready(Ok(()));
+        }
+
+        let shared = &self.shared;
+        ready!(self.stream.poll_write_with_timeout(
+            cx,
+            || shared.send_eof(),
+            &self.sess,
+            &mut self.timeout
+        ))?;
+        shared.eof_pending.store(false, Ordering::SeqCst);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Writes stdin, see `AsyncChannel::into_split`.
+///
+/// Await `send_eof`, or close it through `AsyncWrite`, before dropping it to see whether EOF
+/// went out. A drop tries to send EOF once, when that would block a task on the current runtime
+/// sends it. Without a runtime it is left to the next read of the read half.
+pub struct AsyncChannelWriteHalf<S> {
+    inner: Stream,
+    shared: Arc<SplitShared>,
+    sess: Session,
+    stream: Arc<S>,
+    timeout: PollTimeout,
+    eof_sent: bool,
+    spawn_eof: Option<SpawnEof>,
+}
+
+impl<S> AsyncChannelWriteHalf<S> {
+    /// How long a write may stall, `None` falls back to the session timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout.set_timeout(timeout);
+    }
+}
+
+impl<S> AsyncChannelWriteHalf<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    /// Flushes and sends EOF, later writes fail.
+    pub async fn send_eof(&mut self) -> Result<(), Error> {
+        future::poll_fn(|cx| {
+            ready!(self.poll_flush_inner(cx))?;
+            self.poll_send_eof(cx)
+        })
+        .await
+        .map_err(Into::into)
+    }
+
+    fn poll_flush_inner(&mut self, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        let inner = &mut self.inner;
+        self.stream
+            .poll_write_with_timeout(cx, || inner.flush(), &self.sess, &mut self.timeout)
+    }
+
+    fn poll_send_eof(&mut self, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        if self.eof_sent {
+            return Poll::Ready(Ok(()));
+        }
+
+        let shared = &self.shared;
+        ready!(self.stream.poll_write_with_timeout(
+            cx,
+            || shared.send_eof(),
+            &self.sess,
+            &mut self.timeout
+        ))?;
+        self.eof_sent = true;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> Drop for AsyncChannelWriteHalf<S> {
+    fn drop(&mut self) {
+        if self.eof_sent {
+            return;
+        }
+        match self.shared.send_eof() {
+            Err(err) if err.kind() == IoErrorKind::WouldBlock => {
+                if self.spawn_eof.take().is_some_and(|spawn_eof| spawn_eof()) {
+                    return;
+                }
+                self.shared.eof_pending.store(true, Ordering::SeqCst);
+                self.shared.reader_waker.wake();
+            }
+            _ => {}
+        }
+    }
 }
 
 //
@@ -169,6 +455,7 @@ pub struct AsyncStream<S> {
     inner: Stream,
     sess: Session,
     stream: Arc<S>,
+    timeout: PollTimeout,
 }
 
 impl<S> AsyncStream<S> {
@@ -177,8 +464,14 @@ impl<S> AsyncStream<S> {
             inner,
             sess,
             stream,
+            timeout: PollTimeout::default(),
         }
     }
+
+    /// How long a read or write may stall, `None` falls back to the session timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout.set_timeout(timeout);
+    }
 }
 
 mod impl_futures_util {
@@ -190,7 +483,9 @@ mod impl_futures_util {
 
     use futures_util::io::{AsyncRead, AsyncWrite};
 
-    use super::{AsyncChannel, AsyncStream};
+    use futures_util::ready;
+
+    use super::{AsyncChannel, AsyncChannelReadHalf, AsyncChannelWriteHalf, AsyncStream};
     use crate::session_stream::AsyncSessionStream;
 
     //
@@ -203,7 +498,12 @@ mod impl_futures_util {
             cx: &mut Context<'_>,
             buf: &mut [u8],
         ) -> Poll<Result<usize, IoError>> {
-            Pin::new(&mut self.stream(0)).poll_read(cx, buf)
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_read_with_timeout(cx, || inner.read(buf), &sess, &mut this.timeout)
         }
     }
 
@@ -216,15 +516,26 @@ mod impl_futures_util {
             cx: &mut Context,
             buf: &[u8],
         ) -> Poll<Result<usize, IoError>> {
-            Pin::new(&mut self.stream(0)).poll_write(cx, buf)
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
         }
 
         fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
-            Pin::new(&mut self.stream(0)).poll_flush(cx)
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
         }
 
-        fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
-            Pin::new(&mut self.stream(0)).poll_close(cx)
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            self.get_mut().poll_send_eof(cx)
         }
     }
 
@@ -242,7 +553,8 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_read_with(cx, || inner.read(buf), &sess)
+            this.stream
+                .poll_read_with_timeout(cx, || inner.read(buf), &sess, &mut this.timeout)
         }
     }
 
@@ -259,7 +571,8 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_write_with(cx, || inner.write(buf), &sess)
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
         }
 
         fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
@@ -267,13 +580,65 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_write_with(cx, || inner.flush(), &sess)
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
         }
 
         fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
             self.poll_flush(cx)
         }
     }
+    //
+    impl<S> AsyncRead for AsyncChannelReadHalf<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize, IoError>> {
+            let this = self.get_mut();
+            ready!(this.poll_pending_eof(cx))?;
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_read_with_timeout(cx, || inner.read(buf), &sess, &mut this.timeout)
+        }
+    }
+
+    impl<S> AsyncWrite for AsyncChannelWriteHalf<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context,
+            buf: &[u8],
+        ) -> Poll<Result<usize, IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
+        }
+
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            self.get_mut().poll_send_eof(cx)
+        }
+    }
 }
 
 #[cfg(feature = "tokio")]
@@ -284,9 +649,10 @@ mod impl_tokio {
     };
     use std::io::{Error as IoError, Read as _, Write as _};
 
+    use futures_util::ready;
     use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-    use super::{AsyncChannel, AsyncStream};
+    use super::{AsyncChannel, AsyncChannelReadHalf, AsyncChannelWriteHalf, AsyncStream};
     use crate::session_stream::AsyncSessionStream;
 
     //
@@ -299,7 +665,20 @@ mod impl_tokio {
             cx: &mut Context<'_>,
             buf: &mut ReadBuf<'_>,
         ) -> Poll<Result<(), IoError>> {
-            Pin::new(&mut self.stream(0)).poll_read(cx, buf)
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream.poll_read_with_timeout(
+                cx,
+                || {
+                    let size = inner.read(buf.initialize_unfilled())?;
+                    buf.advance(size);
+                    Ok(())
+                },
+                &sess,
+                &mut this.timeout,
+            )
         }
     }
 
@@ -312,15 +691,29 @@ mod impl_tokio {
             cx: &mut Context<'_>,
             buf: &[u8],
         ) -> Poll<Result<usize, IoError>> {
-            Pin::new(&mut self.stream(0)).poll_write(cx, buf)
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
         }
 
         fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
-            Pin::new(&mut self.stream(0)).poll_flush(cx)
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
         }
 
-        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
-            Pin::new(&mut self.stream(0)).poll_shutdown(cx)
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), IoError>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            self.get_mut().poll_send_eof(cx)
         }
     }
 
@@ -338,7 +731,7 @@ mod impl_tokio {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_read_with(
+            this.stream.poll_read_with_timeout(
                 cx,
                 || {
                     let size = inner.read(buf.initialize_unfilled());
@@ -351,6 +744,7 @@ mod impl_tokio {
                     }
                 },
                 &sess,
+                &mut this.timeout,
             )
         }
     }
@@ -368,7 +762,8 @@ mod impl_tokio {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_write_with(cx, || inner.write(buf), &sess)
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
         }
 
         fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
@@ -376,11 +771,75 @@ mod impl_tokio {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_write_with(cx, || inner.flush(), &sess)
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
         }
 
         fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
             self.poll_flush(cx)
         }
     }
+
+    //
+    impl<S> AsyncRead for AsyncChannelReadHalf<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<Result<(), IoError>> {
+            let this = self.get_mut();
+            ready!(this.poll_pending_eof(cx))?;
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream.poll_read_with_timeout(
+                cx,
+                || {
+                    let size = inner.read(buf.initialize_unfilled())?;
+                    buf.advance(size);
+                    Ok(())
+                },
+                &sess,
+                &mut this.timeout,
+            )
+        }
+    }
+
+    impl<S> AsyncWrite for AsyncChannelWriteHalf<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), IoError>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            self.get_mut().poll_send_eof(cx)
+        }
+    }
 }
@@ -0,0 +1,313 @@
+//! TCP connect to a host name, trying every resolved address Happy Eyeballs style (RFC 8305).
+
+use core::{future::Future, pin::Pin, task::Poll, time::Duration};
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    net::SocketAddr,
+};
+
+use futures_util::{future::poll_fn, stream::FuturesUnordered, FutureExt as _, StreamExt as _};
+
+/// How long an attempt runs alone before the next address is tried in parallel.
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+//
+#[derive(Debug)]
+pub struct ConnectError {
+    pub host: String,
+    pub port: u16,
+    /// Every address that was tried, in order, with why it failed.
+    pub attempts: Vec<(SocketAddr, IoError)>,
+}
+
+impl core::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "failed to connect to {}",
+            host_port(&self.host, self.port)
+        )?;
+        if self.attempts.is_empty() {
+            return write!(f, ", no addresses resolved");
+        }
+        for (i, (addr, err)) in self.attempts.iter().enumerate() {
+            write!(f, "{} {addr}: {err}", if i == 0 { ":" } else { "," })?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+impl From<ConnectError> for IoError {
+    fn from(err: ConnectError) -> Self {
+        let kind = if err.attempts.is_empty() {
+            IoErrorKind::NotFound
+        } else if err
+            .attempts
+            .iter()
+            .all(|(_, err)| err.kind() == IoErrorKind::TimedOut)
+        {
+            IoErrorKind::TimedOut
+        } else {
+            err.attempts
+                .iter()
+                .map(|(_, err)| err.kind())
+                .rfind(|kind| *kind != IoErrorKind::TimedOut)
+                .unwrap_or(IoErrorKind::Other)
+        };
+        IoError::new(kind, err)
+    }
+}
+
+//
+/// Splits `host:port`, `[v6]:port`, `host` or a bare IPv6 address, the port defaults to 22.
+pub(crate) fn split_host_port(s: &str) -> Result<(String, u16), IoError> {
+    let invalid = || IoError::new(IoErrorKind::InvalidInput, format!("invalid host {s:?}"));
+
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']').ok_or_else(invalid)?;
+        match rest {
+            "" => (host, None),
+            _ => (host, Some(rest.strip_prefix(':').ok_or_else(invalid)?)),
+        }
+    } else {
+        match s.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (s, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid())?,
+        None => 22,
+    };
+    Ok((host.to_owned(), port))
+}
+
+fn host_port(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+/// Alternates address families, starting with the family of the first address.
+pub(crate) fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = match addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return addrs,
+    };
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut ret = Vec::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => break,
+            (a, b) => ret.extend(a.into_iter().chain(b)),
+        }
+    }
+    ret
+}
+
+//
+/// Starts the next attempt when the previous one fails or has run for `CONNECTION_ATTEMPT_DELAY`,
+/// the first connected stream wins. `timeout` bounds the whole thing.
+pub(crate) async fn happy_eyeballs<S, C, CF, T, TF>(
+    host: &str,
+    port: u16,
+    addrs: Vec<SocketAddr>,
+    connect: C,
+    sleep: T,
+    timeout: Option<Duration>,
+) -> Result<S, ConnectError>
+where
+    C: Fn(SocketAddr) -> CF,
+    CF: Future<Output = Result<S, IoError>>,
+    T: Fn(Duration) -> TF,
+    TF: Future<Output = ()>,
+{
+    let mut pending = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    let mut in_flight = vec![];
+    let mut failed = vec![];
+    let mut delay: Option<Pin<Box<TF>>> = None;
+    let mut timeout = timeout.map(|dur| Box::pin(sleep(dur)));
+    let mut start_next = true;
+
+    let ret = poll_fn(|cx| loop {
+        if start_next {
+            start_next = false;
+            delay = None;
+            if let Some(addr) = pending.next() {
+                in_flight.push(addr);
+                attempts.push(connect(addr).map(move |ret| (addr, ret)));
+                delay = Some(Box::pin(sleep(CONNECTION_ATTEMPT_DELAY)));
+            }
+        }
+
+        match attempts.poll_next_unpin(cx) {
+            Poll::Ready(Some((_, Ok(stream)))) => return Poll::Ready(Some(stream)),
+            Poll::Ready(Some((addr, Err(err)))) => {
+                in_flight.retain(|x| *x != addr);
+                failed.push((addr, err));
+                start_next = true;
+                continue;
+            }
+            Poll::Ready(None) if pending.len() == 0 => return Poll::Ready(None),
+            Poll::Ready(None) | Poll::Pending => {}
+        }
+
+        if let Some(delay_fut) = delay.as_mut() {
+            if delay_fut.as_mut().poll(cx).is_ready() {
+                start_next = true;
+                continue;
+            }
+        }
+        if let Some(timeout_fut) = timeout.as_mut() {
+            if timeout_fut.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+        }
+
+        return Poll::Pending;
+    })
+    .await;
+
+    match ret {
+        Some(stream) => Ok(stream),
+        None => {
+            failed.extend(in_flight.into_iter().map(|addr| {
+                (
+                    addr,
+                    IoError::new(IoErrorKind::TimedOut, "connect timed out"),
+                )
+            }));
+            Err(ConnectError {
+                host: host.to_owned(),
+                port,
+                attempts: failed,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Instant;
+
+    fn sleep(dur: Duration) -> impl Future<Output = ()> {
+        blocking::unblock(move || std::thread::sleep(dur))
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("db1.internal:2222").unwrap(),
+            ("db1.internal".into(), 2222)
+        );
+        assert_eq!(
+            split_host_port("db1.internal").unwrap(),
+            ("db1.internal".into(), 22)
+        );
+        assert_eq!(split_host_port("[::1]:2222").unwrap(), ("::1".into(), 2222));
+        assert_eq!(split_host_port("[::1]").unwrap(), ("::1".into(), 22));
+        assert_eq!(split_host_port("fe80::1").unwrap(), ("fe80::1".into(), 22));
+        assert!(split_host_port("db1.internal:ssh").is_err());
+        assert!(split_host_port(":22").is_err());
+        assert!(split_host_port("[::1]22").is_err());
+    }
+
+    #[test]
+    fn test_interleave() {
+        let addrs: Vec<SocketAddr> = ["[::1]:22", "[::2]:22", "10.0.0.1:22", "[::3]:22"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        let addrs = interleave(addrs)
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, ["[::1]:22", "10.0.0.1:22", "[::2]:22", "[::3]:22"]);
+    }
+
+    #[test]
+    fn test_happy_eyeballs() {
+        let addrs: Vec<SocketAddr> = ["10.0.0.1:22", "10.0.0.2:22", "10.0.0.3:22"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        futures_lite::future::block_on(async {
+            // The first address hangs, the second fails, the third wins after the delay
+            let now = Instant::now();
+            let ret = happy_eyeballs(
+                "db1.internal",
+                22,
+                addrs.clone(),
+                |addr| async move {
+                    match addr.ip().to_string().as_str() {
+                        "10.0.0.1" => {
+                            sleep(Duration::from_secs(5)).await;
+                            Ok(1)
+                        }
+                        "10.0.0.2" => Err(IoError::new(IoErrorKind::ConnectionRefused, "refused")),
+                        _ => Ok(3),
+                    }
+                },
+                sleep,
+                None,
+            )
+            .await;
+            assert_eq!(ret.unwrap(), 3);
+            assert!(now.elapsed() < Duration::from_secs(1));
+
+            // Every address is named in the error
+            let err = happy_eyeballs(
+                "db1.internal",
+                22,
+                addrs.clone(),
+                |addr| async move {
+                    if addr.ip().to_string() == "10.0.0.3" {
+                        sleep(Duration::from_secs(5)).await;
+                    }
+                    Err::<(), _>(IoError::new(IoErrorKind::ConnectionRefused, "refused"))
+                },
+                sleep,
+                Some(Duration::from_millis(100)),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "failed to connect to db1.internal:22: 10.0.0.1:22: refused, 10.0.0.2:22: refused, 10.0.0.3:22: connect timed out"
+            );
+            assert_eq!(IoError::from(err).kind(), IoErrorKind::ConnectionRefused);
+
+            let err = happy_eyeballs(
+                "db1.internal",
+                22,
+                vec![],
+                |_| async { Ok(()) },
+                sleep,
+                None,
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "failed to connect to db1.internal:22, no addresses resolved"
+            );
+        });
+    }
+}
@@ -1,44 +1,264 @@
+use core::time::Duration;
 use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 
-use ssh2::Error as Ssh2Error;
+use ssh2::{Error as Ssh2Error, ErrorCode};
+
+use crate::host_key::HostKeyMismatch;
 
 //
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Ssh2(Ssh2Error),
     Io(IoError),
     Other(Box<dyn std::error::Error + Send + Sync + 'static>),
+    HostKeyMismatch(HostKeyMismatch),
+    /// The operation did not complete within the timeout.
+    Timeout(Duration),
+    /// Every identity or method tried was rejected.
+    AuthenticationFailed(String),
+    /// The ssh-agent cannot be reached or holds no identities.
+    AgentUnavailable(String),
+    /// The operation that failed and the remote it talked to, see `Error::context`. Operations
+    /// of `AsyncSession` and `AsyncSftp` wrap their errors, use `Error::kind` or e.g.
+    /// `Error::as_ssh2` to look at the cause.
+    Context {
+        context: ErrorContext,
+        source: Box<Error>,
+    },
 }
 
 impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            Self::Ssh2(err) => match err.code() {
+                ErrorCode::Session(code) => write!(f, "{} (libssh2 error {code})", err.message()),
+                ErrorCode::SFTP(code) => write!(f, "{} (sftp status {code})", err.message()),
+            },
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Other(err) => write!(f, "{err}"),
+            Self::HostKeyMismatch(err) => write!(f, "{err}"),
+            Self::Timeout(dur) => write!(f, "timed out after {dur:?}"),
+            Self::AuthenticationFailed(msg) => write!(f, "authentication failed: {msg}"),
+            Self::AgentUnavailable(msg) => write!(f, "ssh-agent unavailable: {msg}"),
+            Self::Context { context, source } => write!(f, "{context} failed: {source}"),
+        }
     }
 }
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            // Displayed as the inner error, so its source comes next
+            Self::Ssh2(err) => err.source(),
+            Self::Io(err) => err.source(),
+            Self::Other(err) => err.source(),
+            Self::Context { source, .. } => Some(source.as_ref()),
+            Self::HostKeyMismatch(_)
+            | Self::Timeout(_)
+            | Self::AuthenticationFailed(_)
+            | Self::AgentUnavailable(_) => None,
+        }
+    }
+}
+
+//
+/// Where an error happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    /// e.g. `exec_output` or `userauth_with_ssh_config`.
+    pub op: &'static str,
+    /// `host:port`, when known, see `AsyncSession::remote_host`.
+    pub remote: Option<String>,
+}
+
+impl core::fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.remote.as_deref() {
+            Some(remote) => write!(f, "{} with {remote}", self.op),
+            None => write!(f, "{}", self.op),
+        }
+    }
+}
+
+//
+/// What went wrong, independent of where the error came from, see `Error::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    AuthenticationFailed,
+    HostKeyMismatch,
+    ChannelOpenFailed(ChannelOpenFailure),
+    /// With the SFTP status code, e.g. 2 for no such file.
+    SftpStatus(i32),
+    Timeout,
+    /// The connection is gone, also after the keepalive task gave up on the peer.
+    Disconnected,
+    AgentUnavailable,
+    /// The operation still would block when it gave up.
+    WouldBlockExhausted,
+    Other,
+}
+
+/// Why the server refused to open a channel, RFC 4254 section 5.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ChannelOpenFailure {
+    AdministrativelyProhibited,
+    ConnectFailed,
+    UnknownChannelType,
+    ResourceShortage,
+    Unknown,
+}
 
 //
 impl Error {
-    pub fn as_ssh2(&self) -> Option<&Ssh2Error> {
+    pub fn kind(&self) -> ErrorKind {
         match self {
+            Self::Ssh2(err) => ssh2_error_kind(err),
+            Self::Io(err) => io_error_kind(err),
+            Self::Other(err) => {
+                #[cfg(unix)]
+                if err.is::<crate::proxy_command::ProxyCommandError>() {
+                    return ErrorKind::Disconnected;
+                }
+                if let Some(err) = err.downcast_ref::<Error>() {
+                    return err.kind();
+                }
+                ErrorKind::Other
+            }
+            Self::HostKeyMismatch(_) => ErrorKind::HostKeyMismatch,
+            Self::Timeout(_) => ErrorKind::Timeout,
+            Self::AuthenticationFailed(_) => ErrorKind::AuthenticationFailed,
+            Self::AgentUnavailable(_) => ErrorKind::AgentUnavailable,
+            Self::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// The outermost context, see `Error::Context`.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error without its context.
+    pub fn without_context(&self) -> &Error {
+        match self {
+            Self::Context { source, .. } => source.without_context(),
+            err => err,
+        }
+    }
+
+    /// Keeps an existing context, it names the innermost operation.
+    pub(crate) fn with_context(self, op: &'static str, remote: Option<(&str, u16)>) -> Self {
+        if matches!(self, Self::Context { .. }) {
+            return self;
+        }
+        let remote = remote.map(|(host, port)| {
+            if host.contains(':') {
+                format!("[{host}]:{port}")
+            } else {
+                format!("{host}:{port}")
+            }
+        });
+        Self::Context {
+            context: ErrorContext { op, remote },
+            source: Box::new(self),
+        }
+    }
+
+    pub fn as_ssh2(&self) -> Option<&Ssh2Error> {
+        match self.without_context() {
             Self::Ssh2(err) => Some(err),
             _ => None,
         }
     }
 
     pub fn as_io(&self) -> Option<&IoError> {
-        match self {
+        match self.without_context() {
             Self::Io(err) => Some(err),
             _ => None,
         }
     }
 
     pub fn as_other(&self) -> Option<&(dyn std::error::Error + Send + Sync + 'static)> {
-        match self {
+        match self.without_context() {
             Self::Other(err) => Some(err.as_ref()),
             _ => None,
         }
     }
+
+    pub fn as_host_key_mismatch(&self) -> Option<&HostKeyMismatch> {
+        match self.without_context() {
+            Self::HostKeyMismatch(err) => Some(err),
+            _ => None,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.kind() == ErrorKind::Timeout
+    }
+}
+
+fn ssh2_error_kind(err: &Ssh2Error) -> ErrorKind {
+    let code = match err.code() {
+        ErrorCode::Session(code) => code,
+        ErrorCode::SFTP(code) => return ErrorKind::SftpStatus(code),
+    };
+    match code {
+        libssh2_sys::LIBSSH2_ERROR_AUTHENTICATION_FAILED
+        | libssh2_sys::LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED
+        | libssh2_sys::LIBSSH2_ERROR_PASSWORD_EXPIRED
+        | libssh2_sys::LIBSSH2_ERROR_KEYFILE_AUTH_FAILED => ErrorKind::AuthenticationFailed,
+        libssh2_sys::LIBSSH2_ERROR_CHANNEL_FAILURE => {
+            // libssh2 only keeps the reason in the message
+            let msg = err.message();
+            let reason = if msg.contains("administratively prohibited") {
+                ChannelOpenFailure::AdministrativelyProhibited
+            } else if msg.contains("connect failed") {
+                ChannelOpenFailure::ConnectFailed
+            } else if msg.contains("unknown channel type") {
+                ChannelOpenFailure::UnknownChannelType
+            } else if msg.contains("resource shortage") {
+                ChannelOpenFailure::ResourceShortage
+            } else {
+                ChannelOpenFailure::Unknown
+            };
+            ErrorKind::ChannelOpenFailed(reason)
+        }
+        libssh2_sys::LIBSSH2_ERROR_TIMEOUT | libssh2_sys::LIBSSH2_ERROR_SOCKET_TIMEOUT => {
+            ErrorKind::Timeout
+        }
+        libssh2_sys::LIBSSH2_ERROR_SOCKET_SEND
+        | libssh2_sys::LIBSSH2_ERROR_SOCKET_RECV
+        | libssh2_sys::LIBSSH2_ERROR_SOCKET_DISCONNECT
+        | libssh2_sys::LIBSSH2_ERROR_BAD_SOCKET => ErrorKind::Disconnected,
+        libssh2_sys::LIBSSH2_ERROR_AGENT_PROTOCOL => ErrorKind::AgentUnavailable,
+        libssh2_sys::LIBSSH2_ERROR_EAGAIN => ErrorKind::WouldBlockExhausted,
+        _ => ErrorKind::Other,
+    }
+}
+
+fn io_error_kind(err: &IoError) -> ErrorKind {
+    if let Some(inner) = err.get_ref() {
+        if let Some(inner) = inner.downcast_ref::<Error>() {
+            return inner.kind();
+        }
+        if let Some(inner) = inner.downcast_ref::<Ssh2Error>() {
+            return ssh2_error_kind(inner);
+        }
+    }
+    match err.kind() {
+        IoErrorKind::TimedOut => ErrorKind::Timeout,
+        IoErrorKind::BrokenPipe
+        | IoErrorKind::ConnectionReset
+        | IoErrorKind::ConnectionAborted
+        | IoErrorKind::NotConnected
+        | IoErrorKind::UnexpectedEof => ErrorKind::Disconnected,
+        IoErrorKind::WouldBlock => ErrorKind::WouldBlockExhausted,
+        _ => ErrorKind::Other,
+    }
 }
 
 //
@@ -58,9 +278,86 @@ impl From<IoError> for Error {
 impl From<Error> for IoError {
     fn from(err: Error) -> Self {
         match err {
-            Error::Ssh2(err) => IoError::new(IoErrorKind::Other, err),
+            Error::Ssh2(err) => IoError::other(err),
             Error::Io(err) => err,
-            Error::Other(err) => IoError::new(IoErrorKind::Other, err),
+            Error::Other(err) => IoError::other(err),
+            err @ Error::Timeout(_) => IoError::new(IoErrorKind::TimedOut, err),
+            err @ (Error::HostKeyMismatch(_)
+            | Error::AuthenticationFailed(_)
+            | Error::AgentUnavailable(_)) => IoError::new(IoErrorKind::PermissionDenied, err),
+            err @ Error::Context { .. } => {
+                let kind = match err.without_context() {
+                    Error::Io(inner) => inner.kind(),
+                    Error::Timeout(_) => IoErrorKind::TimedOut,
+                    Error::HostKeyMismatch(_)
+                    | Error::AuthenticationFailed(_)
+                    | Error::AgentUnavailable(_) => IoErrorKind::PermissionDenied,
+                    _ => IoErrorKind::Other,
+                };
+                IoError::new(kind, err)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_and_display() {
+        let err = Error::Ssh2(Ssh2Error::new(
+            ErrorCode::Session(libssh2_sys::LIBSSH2_ERROR_CHANNEL_FAILURE),
+            "Channel open failure (administratively prohibited)",
+        ))
+        .with_context("channel_direct_tcpip", Some(("db1.internal", 22)));
+        assert_eq!(
+            err.kind(),
+            ErrorKind::ChannelOpenFailed(ChannelOpenFailure::AdministrativelyProhibited)
+        );
+        assert_eq!(err.context().unwrap().op, "channel_direct_tcpip");
+        assert_eq!(
+            err.to_string(),
+            "channel_direct_tcpip with db1.internal:22 failed: Channel open failure (administratively prohibited) (libssh2 error -21)"
+        );
+        assert!(err.as_ssh2().is_some());
+
+        let err = Error::Ssh2(Ssh2Error::new(ErrorCode::SFTP(2), "no such file"));
+        assert_eq!(err.kind(), ErrorKind::SftpStatus(2));
+        assert_eq!(err.to_string(), "no such file (sftp status 2)");
+
+        let err = Error::Timeout(Duration::from_secs(1)).with_context("handshake", None);
+        assert!(err.is_timeout());
+        assert_eq!(err.to_string(), "handshake failed: timed out after 1s");
+        assert_eq!(IoError::from(err).kind(), IoErrorKind::TimedOut);
+
+        let err = Error::Io(IoError::new(
+            IoErrorKind::TimedOut,
+            Error::AuthenticationFailed("all identities were rejected".into()),
+        ));
+        assert_eq!(err.kind(), ErrorKind::AuthenticationFailed);
+
+        let err = Error::Io(IoError::from(IoErrorKind::ConnectionReset))
+            .with_context("sftp", Some(("::1", 2222)));
+        assert_eq!(err.kind(), ErrorKind::Disconnected);
+        assert_eq!(err.context().unwrap().remote.as_deref(), Some("[::1]:2222"));
+
+        let err = Error::HostKeyMismatch(HostKeyMismatch {
+            host: "db1.internal".into(),
+            port: 22,
+            key_type: ssh2::HostKeyType::Ed25519,
+            fingerprint: "SHA256:AAAA".into(),
+            result: ssh2::CheckResult::Mismatch,
+        });
+        let err = IoError::from(err);
+        assert_eq!(err.kind(), IoErrorKind::PermissionDenied);
+        let inner = err.get_ref().unwrap().downcast_ref::<Error>().unwrap();
+        assert_eq!(inner.as_host_key_mismatch().unwrap().port, 22);
+        assert_eq!(Error::Io(err).kind(), ErrorKind::HostKeyMismatch);
+
+        let err = Error::Timeout(Duration::from_secs(1)).with_context("exec_output", None);
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.to_string(), "timed out after 1s");
+        assert!(source.source().is_none());
+    }
+}
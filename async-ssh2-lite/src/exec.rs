@@ -0,0 +1,608 @@
+//! Running remote commands, see `RemoteCommand` and `AsyncSession::exec_output`.
+
+use core::{fmt, mem, time::Duration};
+use std::{borrow::Cow, io::Error as IoError};
+
+use futures_util::{
+    future,
+    io::{self, AsyncRead, AsyncWrite},
+    AsyncReadExt as _,
+};
+
+use crate::{
+    channel::{AsyncChannel, AsyncStream},
+    error::Error,
+    session::AsyncSession,
+    session_stream::AsyncSessionStream,
+};
+
+//
+#[derive(Debug, Clone, Default)]
+pub struct ExecConfiguration {
+    output_limit: Option<usize>,
+    timeout: Option<Duration>,
+}
+impl ExecConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Keeps at most `limit` bytes of stdout and of stderr, the rest is read and dropped.
+    pub fn set_output_limit(&mut self, limit: usize) {
+        self.output_limit = Some(limit);
+    }
+
+    /// Bounds the whole command, it fails with `Error::Timeout` and its channel is closed.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    pub(crate) fn output_limit(&self) -> Option<usize> {
+        self.output_limit
+    }
+
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+}
+
+//
+/// Like `std::process::Output`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecOutput {
+    pub exit_status: i32,
+    /// e.g. `TERM`, when the command was killed by a signal.
+    pub exit_signal: Option<String>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Some output was dropped, see `ExecConfiguration::set_output_limit`.
+    pub truncated: bool,
+}
+
+impl ExecOutput {
+    pub fn success(&self) -> bool {
+        self.exit_status == 0 && self.exit_signal.is_none()
+    }
+}
+
+//
+/// Builds the command line of an exec request like `tokio::process::Command`, every part is
+/// quoted for a POSIX shell.
+///
+/// ```no_run
+/// # async fn f<S: async_ssh2_lite::AsyncSessionStream + Send + Sync + 'static>(
+/// #     session: &async_ssh2_lite::AsyncSession<S>,
+/// # ) -> Result<(), async_ssh2_lite::Error> {
+/// use async_ssh2_lite::exec::RemoteCommand;
+///
+/// let output = RemoteCommand::new("ls")
+///     .arg("-l")
+///     .arg("my dir; rm -rf /")
+///     .output(session)
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RemoteCommand {
+    program: String,
+    /// `program` is a command line of its own, see `from_command_line`.
+    raw: bool,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    current_dir: Option<String>,
+    pty: Option<String>,
+    stdin: Stdio,
+    stdout: Stdio,
+    stderr: Stdio,
+}
+
+impl RemoteCommand {
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            raw: false,
+            args: vec![],
+            envs: vec![],
+            current_dir: None,
+            pty: None,
+            stdin: Stdio::null(),
+            stdout: Stdio::piped(),
+            stderr: Stdio::piped(),
+        }
+    }
+
+    /// Sent as is, for a line that already is a shell command like `ls -l | wc -l`. Arguments
+    /// are still quoted.
+    pub fn from_command_line(command_line: impl Into<String>) -> Self {
+        Self {
+            raw: true,
+            ..Self::new(command_line)
+        }
+    }
+
+    pub fn arg(&mut self, arg: impl Into<String>) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sent as an env request, the server has to accept the name, see AcceptEnv of sshd.
+    pub fn env(&mut self, key: impl Into<String>, val: impl Into<String>) -> &mut Self {
+        self.envs.push((key.into(), val.into()));
+        self
+    }
+
+    /// Runs `cd DIR && ...`, a command line of `from_command_line` is grouped so none of it runs
+    /// when the directory cannot be entered.
+    pub fn current_dir(&mut self, dir: impl Into<String>) -> &mut Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Requests a pty with the terminal type `term`, stderr is then merged into stdout.
+    pub fn pty(&mut self, term: impl Into<String>) -> &mut Self {
+        self.pty = Some(term.into());
+        self
+    }
+
+    /// Defaults to `Stdio::null`.
+    pub fn stdin(&mut self, stdin: Stdio) -> &mut Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Defaults to `Stdio::piped`.
+    pub fn stdout(&mut self, stdout: Stdio) -> &mut Self {
+        self.stdout = stdout;
+        self
+    }
+
+    /// Defaults to `Stdio::piped`.
+    pub fn stderr(&mut self, stderr: Stdio) -> &mut Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// What is sent in the exec request.
+    pub fn command_line(&self) -> String {
+        let mut line = String::new();
+        // A raw command line may hold several commands, none of them runs when cd fails
+        let group = self.raw && self.current_dir.is_some();
+        if let Some(dir) = &self.current_dir {
+            line.push_str("cd ");
+            line.push_str(&quote(dir));
+            line.push_str(" && ");
+        }
+        if group {
+            line.push_str("{ ");
+        }
+        if self.raw {
+            line.push_str(&self.program);
+        } else {
+            line.push_str(&quote(&self.program));
+        }
+        for arg in &self.args {
+            line.push(' ');
+            line.push_str(&quote(arg));
+        }
+        if group {
+            // On its own line, the raw part may end in a comment
+            line.push_str("\n}");
+        }
+        line
+    }
+
+    /// Readers and writers given with `Stdio::from_reader` and `Stdio::from_writer` are moved into
+    /// the child, the next spawn falls back to the defaults for them.
+    pub async fn spawn<S>(&mut self, session: &AsyncSession<S>) -> Result<RemoteChild<S>, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        self.spawn_inner(session)
+            .await
+            .map_err(session.context("spawn"))
+    }
+
+    async fn spawn_inner<S>(&mut self, session: &AsyncSession<S>) -> Result<RemoteChild<S>, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        let stdin = match self.stdin.take() {
+            StdioInner::Writer(_) => return Err(Error::Other("stdin takes a reader".into())),
+            stdin => stdin,
+        };
+        let stdout = match self.stdout.take() {
+            StdioInner::Reader(_) => return Err(Error::Other("stdout takes a writer".into())),
+            stdout => stdout,
+        };
+        let stderr = match self.stderr.take() {
+            StdioInner::Reader(_) => return Err(Error::Other("stderr takes a writer".into())),
+            stderr => stderr,
+        };
+
+        let mut channel = session.channel_session().await?;
+        if let Some(term) = &self.pty {
+            channel.request_pty(term, None, None).await?;
+        }
+        for (key, val) in &self.envs {
+            channel.setenv(key, val).await?;
+        }
+        channel.exec(&self.command_line()).await?;
+
+        let mut child = RemoteChild {
+            stdin: None,
+            stdout: None,
+            stderr: None,
+            stdin_from: None,
+            stdout_to: None,
+            stderr_to: None,
+            eof_sent: false,
+            killed: false,
+            channel,
+        };
+        match stdin {
+            StdioInner::Piped => child.stdin = Some(child.channel.stream(0)),
+            StdioInner::Reader(reader) => child.stdin_from = Some(reader),
+            _ => {
+                child.channel.send_eof().await?;
+                child.eof_sent = true;
+            }
+        }
+        match stdout {
+            StdioInner::Piped => child.stdout = Some(child.channel.stream(0)),
+            StdioInner::Writer(writer) => child.stdout_to = Some(writer),
+            _ => child.stdout_to = Some(Box::new(io::sink())),
+        }
+        match stderr {
+            StdioInner::Piped => child.stderr = Some(child.channel.stderr()),
+            StdioInner::Writer(writer) => child.stderr_to = Some(writer),
+            _ => child.stderr_to = Some(Box::new(io::sink())),
+        }
+        Ok(child)
+    }
+
+    /// Runs to completion, collecting piped stdout and stderr.
+    pub async fn output<S>(&mut self, session: &AsyncSession<S>) -> Result<ExecOutput, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        self.spawn(session).await?.wait_with_output().await
+    }
+
+    /// Runs to completion.
+    pub async fn status<S>(&mut self, session: &AsyncSession<S>) -> Result<RemoteExitStatus, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        self.spawn(session).await?.wait().await
+    }
+}
+
+//
+type BoxReader = Box<dyn AsyncRead + Send + Unpin>;
+type BoxWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Where stdin, stdout or stderr of a `RemoteCommand` goes.
+pub struct Stdio(StdioInner);
+
+enum StdioInner {
+    Piped,
+    Null,
+    Reader(BoxReader),
+    Writer(BoxWriter),
+}
+
+impl Stdio {
+    /// Available on the `RemoteChild`.
+    pub fn piped() -> Self {
+        Self(StdioInner::Piped)
+    }
+
+    /// Empty stdin, discarded output.
+    pub fn null() -> Self {
+        Self(StdioInner::Null)
+    }
+
+    /// Fed into stdin while `RemoteChild::wait` runs.
+    pub fn from_reader(reader: impl AsyncRead + Send + Unpin + 'static) -> Self {
+        Self(StdioInner::Reader(Box::new(reader)))
+    }
+
+    /// Fed from stdout or stderr while `RemoteChild::wait` runs.
+    pub fn from_writer(writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        Self(StdioInner::Writer(Box::new(writer)))
+    }
+
+    fn take(&mut self) -> StdioInner {
+        match &self.0 {
+            StdioInner::Piped => StdioInner::Piped,
+            StdioInner::Null => StdioInner::Null,
+            _ => mem::replace(&mut self.0, StdioInner::Null),
+        }
+    }
+}
+
+impl fmt::Debug for Stdio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.0 {
+            StdioInner::Piped => "piped",
+            StdioInner::Null => "null",
+            StdioInner::Reader(_) => "reader",
+            StdioInner::Writer(_) => "writer",
+        };
+        f.debug_tuple("Stdio").field(&name).finish()
+    }
+}
+
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteExitStatus {
+    pub code: i32,
+    /// e.g. `TERM`, when the command was killed by a signal.
+    pub signal: Option<String>,
+}
+
+impl RemoteExitStatus {
+    pub fn success(&self) -> bool {
+        self.code == 0 && self.signal.is_none()
+    }
+}
+
+//
+/// A command started by `RemoteCommand::spawn`, like `tokio::process::Child`.
+pub struct RemoteChild<S> {
+    pub stdin: Option<AsyncStream<S>>,
+    pub stdout: Option<AsyncStream<S>>,
+    pub stderr: Option<AsyncStream<S>>,
+    stdin_from: Option<BoxReader>,
+    stdout_to: Option<BoxWriter>,
+    stderr_to: Option<BoxWriter>,
+    eof_sent: bool,
+    killed: bool,
+    channel: AsyncChannel<S>,
+}
+
+impl<S> RemoteChild<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    /// Sends EOF on stdin, so finish writing to a taken `stdin` first, then waits for the command
+    /// to exit. Piped stdout and stderr still have to be read meanwhile, the command stalls once
+    /// the channel window is full.
+    pub async fn wait(&mut self) -> Result<RemoteExitStatus, Error> {
+        self.wait_inner(false, None)
+            .await
+            .map(|(status, _, _)| status)
+    }
+
+    /// Like `wait`, also collecting piped stdout and stderr that were not taken.
+    pub async fn wait_with_output(mut self) -> Result<ExecOutput, Error> {
+        self.wait_with_output_limit(None).await
+    }
+
+    /// Keeps at most `limit` bytes of each, see `ExecConfiguration::set_output_limit`.
+    pub(crate) async fn wait_with_output_limit(
+        &mut self,
+        limit: Option<usize>,
+    ) -> Result<ExecOutput, Error> {
+        let (status, (stdout, stdout_truncated), (stderr, stderr_truncated)) =
+            self.wait_inner(true, limit).await?;
+        Ok(ExecOutput {
+            exit_status: status.code,
+            exit_signal: status.signal,
+            stdout,
+            stderr,
+            truncated: stdout_truncated || stderr_truncated,
+        })
+    }
+
+    /// Closes the channel. libssh2 cannot send signals, so the command gets SIGHUP when a pty was
+    /// requested and otherwise notices on its next read or write. `wait` then reports whatever the
+    /// server sent before the channel closed.
+    pub async fn kill(&mut self) -> Result<(), Error> {
+        self.stdin = None;
+        self.killed = true;
+        self.channel.close().await
+    }
+
+    async fn wait_inner(
+        &mut self,
+        collect: bool,
+        limit: Option<usize>,
+    ) -> Result<(RemoteExitStatus, (Vec<u8>, bool), (Vec<u8>, bool)), Error> {
+        if !self.killed {
+            self.stdin = None;
+            let stdin_to = self.channel.stream(0);
+            let stdout_from = match self.stdout_to.take() {
+                Some(writer) => Some((self.channel.stream(0), Some(writer))),
+                None if collect => self.stdout.take().map(|stream| (stream, None)),
+                None => None,
+            };
+            let stderr_from = match self.stderr_to.take() {
+                Some(writer) => Some((self.channel.stderr(), Some(writer))),
+                None if collect => self.stderr.take().map(|stream| (stream, None)),
+                None => None,
+            };
+
+            let stdin_from = self.stdin_from.take();
+            let channel = &mut self.channel;
+            let eof_sent = &mut self.eof_sent;
+            let stdin = async move {
+                if let Some(mut reader) = stdin_from {
+                    let mut writer = stdin_to;
+                    io::copy(&mut reader, &mut writer).await?;
+                }
+                if !*eof_sent {
+                    channel.send_eof().await?;
+                    *eof_sent = true;
+                }
+                Result::<_, Error>::Ok(())
+            };
+
+            let ((), stdout, stderr) =
+                future::try_join3(stdin, drain(stdout_from, limit), drain(stderr_from, limit))
+                    .await?;
+
+            self.channel.wait_eof().await?;
+            self.channel.close().await?;
+            self.channel.wait_close().await?;
+
+            let status = self.exit_status().await?;
+            return Ok((status, stdout, stderr));
+        }
+
+        self.channel.wait_close().await?;
+        let status = self.exit_status().await?;
+        Ok((status, (vec![], false), (vec![], false)))
+    }
+
+    async fn exit_status(&self) -> Result<RemoteExitStatus, Error> {
+        Ok(RemoteExitStatus {
+            code: self.channel.exit_status()?,
+            signal: self.channel.exit_signal().await?.exit_signal,
+        })
+    }
+}
+
+/// Copies into the writer when there is one, otherwise collects up to `limit` bytes.
+async fn drain<S>(
+    from: Option<(AsyncStream<S>, Option<BoxWriter>)>,
+    limit: Option<usize>,
+) -> Result<(Vec<u8>, bool), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    match from {
+        Some((mut reader, Some(mut writer))) => {
+            io::copy(&mut reader, &mut writer).await?;
+            Ok((vec![], false))
+        }
+        Some((reader, None)) => Ok(read_to_end_limited(reader, limit).await?),
+        None => Ok((vec![], false)),
+    }
+}
+
+//
+/// Quotes `s` as one word for a POSIX shell.
+pub fn quote(s: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(format!("'{}'", s.replace('\'', r"'\''")))
+}
+
+//
+/// Reads to EOF, keeping at most `limit` bytes. The bool is whether anything was dropped.
+pub(crate) async fn read_to_end_limited<R>(
+    mut reader: R,
+    limit: Option<usize>,
+) -> Result<(Vec<u8>, bool), IoError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut output = vec![];
+    let mut truncated = false;
+    let mut buf = vec![0; 32 * 1024];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return Ok((output, truncated));
+        }
+        let keep = match limit {
+            Some(limit) => n.min(limit.saturating_sub(output.len())),
+            None => n,
+        };
+        truncated |= keep < n;
+        output.extend_from_slice(&buf[..keep]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("ls"), "ls");
+        assert_eq!(quote("/tmp/a-b_c.txt"), "/tmp/a-b_c.txt");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("my dir"), "'my dir'");
+        assert_eq!(quote("$(reboot)"), "'$(reboot)'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("a\nb"), "'a\nb'");
+    }
+
+    #[test]
+    fn test_command_line() {
+        let mut command = RemoteCommand::new("grep");
+        command
+            .args(["-r", "foo bar"])
+            .arg("; rm -rf /")
+            .current_dir("/home/it's");
+        assert_eq!(
+            command.command_line(),
+            r"cd '/home/it'\''s' && grep -r 'foo bar' '; rm -rf /'"
+        );
+
+        let mut command = RemoteCommand::from_command_line("ls -l | wc -l");
+        assert_eq!(command.command_line(), "ls -l | wc -l");
+        command.arg("a b");
+        assert_eq!(command.command_line(), "ls -l | wc -l 'a b'");
+
+        let mut command = RemoteCommand::from_command_line("touch a; touch b # done");
+        command.current_dir("/nonexistent");
+        assert_eq!(
+            command.command_line(),
+            "cd /nonexistent && { touch a; touch b # done\n}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_line_with_failing_cd() -> Result<(), Box<dyn std::error::Error>> {
+        let mut command = RemoteCommand::from_command_line("echo a; echo b");
+        command.current_dir("/nonexistent");
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command.command_line())
+            .output()?;
+        assert!(!output.status.success());
+        assert_eq!(output.stdout, b"");
+
+        command.current_dir("/");
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command.command_line())
+            .output()?;
+        assert_eq!(output.stdout, b"a\nb\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_to_end_limited() {
+        futures_lite::future::block_on(async {
+            let data = vec![1; 100 * 1024];
+
+            let (output, truncated) = read_to_end_limited(&data[..], None).await.unwrap();
+            assert_eq!(output.len(), 100 * 1024);
+            assert!(!truncated);
+
+            let (output, truncated) = read_to_end_limited(&data[..], Some(40 * 1024))
+                .await
+                .unwrap();
+            assert_eq!(output.len(), 40 * 1024);
+            assert!(truncated);
+        });
+    }
+}
@@ -0,0 +1,700 @@
+//! Port forwarding like `ssh -L`, `ssh -D` and `ssh -R`, see
+//! `AsyncSession::local_port_forwarding`, `AsyncSession::dynamic_port_forwarding` and
+//! `AsyncSession::remote_port_forwarding`.
+
+use core::{
+    fmt,
+    future::Future,
+    task::{Context, Poll},
+    time::Duration,
+};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use futures_util::{
+    future::{self, BoxFuture, Either},
+    io::{AsyncRead, AsyncWrite, BufReader},
+    lock::Mutex as AsyncMutex,
+    pin_mut,
+    task::AtomicWaker,
+};
+use ssh2::Session;
+
+use crate::{
+    channel::AsyncChannel,
+    error::{Error, ErrorKind},
+    listener::AsyncListener,
+    runtime::{Listener, Runtime, Task},
+    session_stream::AsyncSessionStream,
+    socks,
+    util::{copy_bidirectional, ConnectInfo},
+};
+
+//
+/// Settings of `AsyncSession::local_port_forwarding`.
+#[derive(Clone, Default)]
+pub struct LocalForwardConfiguration {
+    on_error: Option<OnError>,
+}
+
+impl LocalForwardConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Called for every connection that fails, e.g. when the server refuses to open the channel,
+    /// and for accept errors.
+    pub fn set_on_error<F>(&mut self, on_error: F)
+    where
+        F: Fn(&ConnectionError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(on_error));
+    }
+}
+
+impl fmt::Debug for LocalForwardConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalForwardConfiguration")
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+/// Settings of `AsyncSession::dynamic_port_forwarding`.
+#[derive(Clone, Default)]
+pub struct DynamicForwardConfiguration {
+    auth: Option<(String, String)>,
+    on_error: Option<OnError>,
+}
+
+type OnError = Arc<dyn Fn(&ConnectionError) + Send + Sync>;
+
+impl DynamicForwardConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Requires SOCKS5 username/password or HTTP Basic auth, SOCKS4 clients are then refused.
+    pub fn set_auth(&mut self, username: impl Into<String>, password: impl Into<String>) {
+        self.auth = Some((username.into(), password.into()));
+    }
+
+    /// Called for every connection that fails, from spawning its task to the end of the copy, and
+    /// for accept errors.
+    pub fn set_on_error<F>(&mut self, on_error: F)
+    where
+        F: Fn(&ConnectionError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(on_error));
+    }
+}
+
+impl fmt::Debug for DynamicForwardConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynamicForwardConfiguration")
+            .field("auth", &self.auth.as_ref().map(|(username, _)| username))
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+/// Settings of `AsyncSession::remote_port_forwarding`.
+#[derive(Clone, Default)]
+pub struct RemoteForwardConfiguration {
+    max_connections: Option<usize>,
+    on_error: Option<OnError>,
+}
+
+impl RemoteForwardConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Connections beyond the limit wait in the server's queue until an active one ends. At
+    /// least 1.
+    pub fn set_max_connections(&mut self, max_connections: usize) {
+        self.max_connections = Some(max_connections.max(1));
+    }
+
+    /// Called for every connection that fails and for accept errors.
+    pub fn set_on_error<F>(&mut self, on_error: F)
+    where
+        F: Fn(&ConnectionError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(on_error));
+    }
+}
+
+impl fmt::Debug for RemoteForwardConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteForwardConfiguration")
+            .field("max_connections", &self.max_connections)
+            .field("on_error", &self.on_error.is_some())
+            .finish()
+    }
+}
+
+/// A forwarded connection that failed.
+#[derive(Debug)]
+pub struct ConnectionError {
+    /// `None` for Unix sockets and remote forwarding.
+    pub peer: Option<SocketAddr>,
+    /// `None` when the client did not get as far as asking, or the local Unix socket of remote
+    /// forwarding.
+    pub target: Option<(String, u16)>,
+    pub error: Error,
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.target {
+            Some((host, port)) => write!(f, "forwarding to {host}:{port} failed: {}", self.error),
+            None => write!(f, "forwarding failed: {}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+//
+/// Forwards the connections to a local address through the session, stops when dropped.
+#[derive(Debug)]
+pub struct LocalForward {
+    local_addr: ConnectInfo,
+    accept_task: Task,
+    connections: Arc<Connections>,
+    unix_path: Option<PathBuf>,
+}
+
+impl LocalForward {
+    pub(crate) async fn bind<S>(bind: ConnectInfo, forwarder: Forwarder<S>) -> Result<Self, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        let runtime = Runtime::current()?;
+        let connections = Arc::new(Connections::default());
+
+        let (listener, local_addr) = runtime.bind(&bind).await?;
+        let unix_path = match &bind {
+            ConnectInfo::Tcp(_) => None,
+            #[cfg(unix)]
+            ConnectInfo::Unix(path) => Some(path.to_path_buf()),
+        };
+        let accept_task = runtime.spawn(accept_local(
+            listener,
+            forwarder,
+            runtime.clone(),
+            connections.clone(),
+        ))?;
+
+        Ok(Self {
+            local_addr,
+            accept_task,
+            connections,
+            unix_path,
+        })
+    }
+
+    /// The bound address, with the actual port when binding port 0.
+    pub fn local_addr(&self) -> &ConnectInfo {
+        &self.local_addr
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Stops accepting and closes the active connections.
+    pub fn shutdown(&self) {
+        self.accept_task.abort();
+        self.connections.abort_all();
+        if let Some(path) = &self.unix_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for LocalForward {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+//
+/// Forwards the connections to a port on the server to a local address, stops when dropped.
+pub struct RemoteForward {
+    remote_port: u16,
+    accept_task: Task,
+    connections: Arc<Connections>,
+    cancel: Option<Cancel>,
+}
+
+type Cancel = Box<dyn FnOnce() -> BoxFuture<'static, Result<(), Error>> + Send + Sync>;
+
+impl RemoteForward {
+    pub(crate) fn spawn<S>(
+        listener: AsyncListener<S>,
+        remote_port: u16,
+        local: ConnectInfo,
+        configuration: RemoteForwardConfiguration,
+    ) -> Result<Self, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        let runtime = Runtime::current()?;
+        let connections = Arc::new(Connections::default());
+        // Held by the accept task until it is aborted
+        let listener = Arc::new(AsyncMutex::new(Some(listener)));
+
+        let accept_task = runtime.spawn(accept_remote(
+            listener.clone(),
+            local,
+            configuration,
+            runtime.clone(),
+            connections.clone(),
+        ))?;
+        let cancel: Cancel = Box::new(move || {
+            Box::pin(async move {
+                match listener.lock().await.take() {
+                    Some(listener) => listener.cancel().await,
+                    None => Ok(()),
+                }
+            })
+        });
+
+        Ok(Self {
+            remote_port,
+            accept_task,
+            connections,
+            cancel: Some(cancel),
+        })
+    }
+
+    /// The port bound on the server, the actual one when asking for port 0.
+    pub fn remote_port(&self) -> u16 {
+        self.remote_port
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Stops accepting and closes the active connections. The cancel-tcpip-forward request is
+    /// sent without waiting for the socket, use `cancel` to make sure it goes out.
+    pub fn shutdown(&self) {
+        self.accept_task.abort();
+        self.connections.abort_all();
+    }
+
+    /// Stops accepting, sends the cancel-tcpip-forward request once the socket allows, see
+    /// `AsyncListener::cancel`, then closes the active connections.
+    pub async fn cancel(mut self) -> Result<(), Error> {
+        self.accept_task.abort();
+        let cancel = self.cancel.take().expect("only taken here");
+        let ret = cancel().await;
+        self.connections.abort_all();
+        ret
+    }
+}
+
+impl fmt::Debug for RemoteForward {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteForward")
+            .field("remote_port", &self.remote_port)
+            .field("accept_task", &self.accept_task)
+            .field("connections", &self.connections)
+            .finish()
+    }
+}
+
+impl Drop for RemoteForward {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+async fn accept_remote<S>(
+    listener: Arc<AsyncMutex<Option<AsyncListener<S>>>>,
+    local: ConnectInfo,
+    configuration: RemoteForwardConfiguration,
+    runtime: Runtime,
+    connections: Arc<Connections>,
+) where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut listener = listener.lock().await;
+    let listener = match listener.as_mut() {
+        Some(listener) => listener,
+        None => return,
+    };
+    let limit = configuration.max_connections.map(Limit::new);
+    let target = match &local {
+        ConnectInfo::Tcp(addr) => Some((addr.ip().to_string(), addr.port())),
+        #[cfg(unix)]
+        ConnectInfo::Unix(_) => None,
+    };
+    let report = |error| {
+        if let Some(on_error) = &configuration.on_error {
+            on_error(&ConnectionError {
+                peer: None,
+                target: target.clone(),
+                error,
+            });
+        }
+    };
+
+    loop {
+        let permit = match &limit {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        };
+
+        match listener.accept().await {
+            Ok(mut channel) => {
+                let local = local.clone();
+                let target = target.clone();
+                let on_error = configuration.on_error.clone();
+                let connection_runtime = runtime.clone();
+                let ret = connections.spawn(&runtime, async move {
+                    let ret = connect_local(&mut channel, &connection_runtime, &local).await;
+                    drop(permit);
+                    if let (Err(error), Some(on_error)) = (ret, on_error) {
+                        on_error(&ConnectionError {
+                            peer: None,
+                            target,
+                            error,
+                        });
+                    }
+                });
+                if let Err(err) = ret {
+                    report(err.into());
+                }
+            }
+            Err(error) => {
+                let disconnected = error.kind() == ErrorKind::Disconnected;
+                report(error);
+                if disconnected {
+                    break;
+                }
+                runtime.sleep(ACCEPT_RETRY).await;
+            }
+        }
+    }
+}
+
+async fn connect_local<S>(
+    channel: &mut AsyncChannel<S>,
+    runtime: &Runtime,
+    local: &ConnectInfo,
+) -> Result<(), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut stream = runtime.connect(local).await?;
+    copy_bidirectional(channel, &mut stream).await?;
+    Ok(())
+}
+
+//
+/// Opens a direct-tcpip channel per connection, holding the session open.
+pub(crate) struct Forwarder<S> {
+    pub(crate) sess: Session,
+    pub(crate) stream: Arc<S>,
+    pub(crate) target: ForwardTarget,
+    pub(crate) on_error: Option<OnError>,
+}
+
+pub(crate) enum ForwardTarget {
+    Fixed(String, u16),
+    Dynamic(DynamicForwardConfiguration),
+}
+
+impl<S> Forwarder<S> {
+    pub(crate) fn local(
+        sess: Session,
+        stream: Arc<S>,
+        host: String,
+        port: u16,
+        configuration: LocalForwardConfiguration,
+    ) -> Self {
+        Self {
+            sess,
+            stream,
+            target: ForwardTarget::Fixed(host, port),
+            on_error: configuration.on_error,
+        }
+    }
+
+    pub(crate) fn dynamic(
+        sess: Session,
+        stream: Arc<S>,
+        configuration: DynamicForwardConfiguration,
+    ) -> Self {
+        Self {
+            sess,
+            stream,
+            on_error: configuration.on_error.clone(),
+            target: ForwardTarget::Dynamic(configuration),
+        }
+    }
+}
+
+impl<S> Forwarder<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    async fn forward<L>(&self, local: L, peer: Option<SocketAddr>)
+    where
+        L: AsyncRead + AsyncWrite + Unpin,
+    {
+        match &self.target {
+            ForwardTarget::Fixed(host, port) => {
+                let mut local = local;
+                let ret = self.forward_fixed(&mut local, peer, host, *port).await;
+                // Reported before the local connection is closed
+                if let Err(error) = ret {
+                    self.report(peer, Some((host.clone(), *port)), error);
+                }
+            }
+            ForwardTarget::Dynamic(configuration) => {
+                let mut target = None;
+                let ret = self
+                    .forward_dynamic(local, peer, configuration, &mut target)
+                    .await;
+                if let Err(error) = ret {
+                    self.report(peer, target, error);
+                }
+            }
+        }
+    }
+
+    fn report(&self, peer: Option<SocketAddr>, target: Option<(String, u16)>, error: Error) {
+        if let Some(on_error) = &self.on_error {
+            on_error(&ConnectionError {
+                peer,
+                target,
+                error,
+            });
+        }
+    }
+
+    async fn forward_fixed<L>(
+        &self,
+        local: &mut L,
+        peer: Option<SocketAddr>,
+        host: &str,
+        port: u16,
+    ) -> Result<(), Error>
+    where
+        L: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut channel = self.open(host, port, peer).await?;
+        copy_bidirectional(&mut channel, local).await?;
+        Ok(())
+    }
+
+    async fn forward_dynamic<L>(
+        &self,
+        local: L,
+        peer: Option<SocketAddr>,
+        configuration: &DynamicForwardConfiguration,
+        target: &mut Option<(String, u16)>,
+    ) -> Result<(), Error>
+    where
+        L: AsyncRead + AsyncWrite + Unpin,
+    {
+        // Buffered for the handshake, what the client sends early stays in the buffer
+        let mut local = BufReader::new(local);
+        let request = {
+            let handshake = socks::accept(&mut local, configuration.auth.as_ref());
+            pin_mut!(handshake);
+            match future::select(handshake, self.stream.sleep(HANDSHAKE_TIMEOUT)).await {
+                Either::Left((ret, _)) => ret?,
+                Either::Right(_) => return Err(Error::Timeout(HANDSHAKE_TIMEOUT)),
+            }
+        };
+        *target = Some((request.host.clone(), request.port));
+
+        let mut channel = match self.open(&request.host, request.port, peer).await {
+            Ok(channel) => channel,
+            Err(err) => {
+                let _ = request.reply(&mut local, Err(&err)).await;
+                return Err(err);
+            }
+        };
+        request.reply(&mut local, Ok(())).await?;
+        copy_bidirectional(&mut channel, &mut local).await?;
+        Ok(())
+    }
+
+    async fn open(
+        &self,
+        host: &str,
+        port: u16,
+        peer: Option<SocketAddr>,
+    ) -> Result<AsyncChannel<S>, Error> {
+        let src = peer.map(|addr| (addr.ip().to_string(), addr.port()));
+        let channel = self
+            .stream
+            .rw_with(
+                || {
+                    self.sess.channel_direct_tcpip(
+                        host,
+                        port,
+                        src.as_ref().map(|(host, port)| (host.as_str(), *port)),
+                    )
+                },
+                &self.sess,
+            )
+            .await?;
+        Ok(AsyncChannel::from_parts(
+            channel,
+            self.sess.clone(),
+            self.stream.clone(),
+        ))
+    }
+}
+
+/// How long a client may take to say where it wants to go.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Backs off on accept errors like running out of file descriptors.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+async fn accept_local<S>(
+    listener: Listener,
+    forwarder: Forwarder<S>,
+    runtime: Runtime,
+    connections: Arc<Connections>,
+) where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let forwarder = Arc::new(forwarder);
+    loop {
+        match listener.accept().await {
+            Ok((local, peer)) => {
+                let ret = connections.spawn(&runtime, {
+                    let forwarder = forwarder.clone();
+                    async move { forwarder.forward(local, peer).await }
+                });
+                // The local connection is just closed
+                if let Err(err) = ret {
+                    forwarder.report(peer, None, err.into());
+                }
+            }
+            Err(err) => {
+                forwarder.report(None, None, err.into());
+                runtime.sleep(ACCEPT_RETRY).await;
+            }
+        }
+    }
+}
+
+//
+/// The tasks of the active connections.
+#[derive(Debug, Default)]
+pub(crate) struct Connections {
+    tasks: Mutex<Vec<Task>>,
+}
+
+impl Connections {
+    pub(crate) fn spawn<F>(&self, runtime: &Runtime, fut: F) -> Result<(), std::io::Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task = runtime.spawn(fut)?;
+        let mut tasks = self.tasks.lock().expect("connections lock poisoned");
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(task);
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let mut tasks = self.tasks.lock().expect("connections lock poisoned");
+        tasks.retain(|task| !task.is_finished());
+        tasks.len()
+    }
+
+    pub(crate) fn abort_all(&self) {
+        let tasks = self.tasks.lock().expect("connections lock poisoned");
+        for task in tasks.iter() {
+            task.abort();
+        }
+    }
+}
+
+//
+/// Counts the connections against `RemoteForwardConfiguration::set_max_connections`, only the
+/// accept loop waits for a permit.
+struct Limit {
+    max: usize,
+    active: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+impl Limit {
+    fn new(max: usize) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            active: AtomicUsize::new(0),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    async fn acquire(self: &Arc<Self>) -> Permit {
+        future::poll_fn(|cx| self.poll_acquire(cx)).await
+    }
+
+    fn poll_acquire(self: &Arc<Self>, cx: &mut Context<'_>) -> Poll<Permit> {
+        self.waker.register(cx.waker());
+        match self
+            .active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                (active < self.max).then_some(active + 1)
+            }) {
+            Ok(_) => Poll::Ready(Permit(self.clone())),
+            Err(_) => Poll::Pending,
+        }
+    }
+}
+
+struct Permit(Arc<Limit>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+        self.0.waker.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_connections() {
+        let mut configuration = RemoteForwardConfiguration::new();
+        configuration.set_max_connections(0);
+        assert_eq!(configuration.max_connections, Some(1));
+
+        let limit = Limit::new(configuration.max_connections.unwrap());
+        futures_lite::future::block_on(async {
+            let permit = limit.acquire().await;
+            assert!(future::poll_fn(|cx| Poll::Ready(limit.poll_acquire(cx)))
+                .await
+                .is_pending());
+            drop(permit);
+            let _permit = limit.acquire().await;
+        });
+    }
+}
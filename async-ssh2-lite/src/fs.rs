@@ -0,0 +1,283 @@
+//! Helpers like `tokio::fs` on remote paths, see `AsyncSftp::read`.
+
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind, SeekFrom},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
+    TryStreamExt as _,
+};
+use libssh2_sys::LIBSSH2_FX_NO_SUCH_FILE;
+use ssh2::{FileStat, FileType, OpenFlags, OpenType};
+
+use crate::{
+    error::{Error, ErrorKind},
+    session_stream::AsyncSessionStream,
+    sftp::AsyncSftp,
+};
+
+const FILE_MODE: i32 = 0o644;
+const DIR_MODE: i32 = 0o755;
+const COPY_BUF_SIZE: usize = 256 * 1024;
+// The size comes from the server, `read_to_end` grows the buffer past it
+const READ_CAPACITY_MAX: u64 = 1024 * 1024;
+
+//
+/// See `AsyncSftp::metadata`.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    stat: FileStat,
+}
+
+impl Metadata {
+    pub fn file_type(&self) -> FileType {
+        self.stat.file_type()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    /// 0 when the server did not send the size.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.stat.size.unwrap_or(0)
+    }
+
+    /// The permission bits of the mode, e.g. `0o644`.
+    pub fn permissions(&self) -> Option<u32> {
+        self.stat.perm.map(|perm| perm & 0o7777)
+    }
+
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.stat.mtime.map(system_time)
+    }
+
+    pub fn accessed(&self) -> Option<SystemTime> {
+        self.stat.atime.map(system_time)
+    }
+
+    pub fn uid(&self) -> Option<u32> {
+        self.stat.uid
+    }
+
+    pub fn gid(&self) -> Option<u32> {
+        self.stat.gid
+    }
+
+    pub fn stat(&self) -> &FileStat {
+        &self.stat
+    }
+}
+
+impl From<FileStat> for Metadata {
+    fn from(stat: FileStat) -> Self {
+        Self { stat }
+    }
+}
+
+fn system_time(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+//
+pub(crate) async fn read<S>(sftp: &AsyncSftp<S>, path: &Path) -> Result<Vec<u8>, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut file = sftp.open(path).await?;
+    let len = file.stat().await?.size.unwrap_or(0);
+    let mut buf = Vec::with_capacity(len.min(READ_CAPACITY_MAX) as usize);
+    file.read_to_end(&mut buf).await?;
+    file.close().await?;
+
+    Ok(buf)
+}
+
+pub(crate) async fn read_to_string<S>(sftp: &AsyncSftp<S>, path: &Path) -> Result<String, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    String::from_utf8(read(sftp, path).await?)
+        .map_err(|err| Error::Io(IoError::new(IoErrorKind::InvalidData, err)))
+}
+
+pub(crate) async fn write<S>(
+    sftp: &AsyncSftp<S>,
+    path: &Path,
+    contents: &[u8],
+    append: bool,
+) -> Result<(), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let flags = if append {
+        OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::APPEND
+    } else {
+        OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE
+    };
+    let mut file = sftp
+        .open_mode(path, flags, FILE_MODE, OpenType::File)
+        .await?;
+    if append {
+        // Not every server honors the flag, libssh2 writes from the offset of the handle
+        let len = file.stat().await?.size.unwrap_or(0);
+        file.seek(SeekFrom::Start(len)).await?;
+    }
+    file.write_all(contents).await?;
+    file.close().await?;
+
+    Ok(())
+}
+
+pub(crate) async fn try_exists<S>(sftp: &AsyncSftp<S>, path: &Path) -> Result<bool, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    match sftp.stat(path).await {
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::SftpStatus(LIBSSH2_FX_NO_SUCH_FILE) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) async fn create_dir_all<S>(sftp: &AsyncSftp<S>, path: &Path) -> Result<(), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut missing = vec![];
+    for ancestor in path.ancestors().filter(|x| !x.as_os_str().is_empty()) {
+        match sftp.stat(ancestor).await {
+            Ok(stat) if stat.is_dir() => break,
+            Ok(_) => {
+                return Err(Error::Other(
+                    format!("{} is not a directory", ancestor.display()).into(),
+                ))
+            }
+            Err(err) if err.kind() == ErrorKind::SftpStatus(LIBSSH2_FX_NO_SUCH_FILE) => {
+                missing.push(ancestor)
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    for dir in missing.into_iter().rev() {
+        if let Err(err) = sftp.mkdir(dir, DIR_MODE).await {
+            // Created by someone else in the meantime
+            if !matches!(sftp.stat(dir).await, Ok(stat) if stat.is_dir()) {
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn remove_dir_all<S>(sftp: &AsyncSftp<S>, path: &Path) -> Result<(), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    // Removes the link and not what it points to, like `std::fs::remove_dir_all`
+    if sftp.lstat(path).await?.file_type().is_symlink() {
+        return sftp.unlink(path).await;
+    }
+
+    // Parents come before their children
+    let entries = sftp
+        .walk(path, None)
+        .map_ok(|entry| (entry.path, entry.stat.is_dir()))
+        .try_collect::<Vec<_>>()
+        .await?;
+    for (path, is_dir) in entries.into_iter().rev() {
+        if is_dir {
+            sftp.rmdir(&path).await?;
+        } else {
+            sftp.unlink(&path).await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn copy<S>(sftp: &AsyncSftp<S>, from: &Path, to: &Path) -> Result<u64, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut src = sftp.open(from).await?;
+    let perm = src.stat().await?.perm;
+    let mut dst = sftp
+        .open_mode(
+            to,
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            FILE_MODE,
+            OpenType::File,
+        )
+        .await?;
+
+    let mut buf = vec![0; COPY_BUF_SIZE];
+    let mut n = 0;
+    loop {
+        match src.read(&mut buf).await? {
+            0 => break,
+            x => {
+                dst.write_all(&buf[..x]).await?;
+                n += x as u64;
+            }
+        }
+    }
+    src.close().await?;
+
+    // Like `std::fs::copy`, the permissions come along
+    if let Some(perm) = perm {
+        dst.setstat(FileStat {
+            size: None,
+            uid: None,
+            gid: None,
+            perm: Some(perm & 0o7777),
+            atime: None,
+            mtime: None,
+        })
+        .await?;
+    }
+    dst.close().await?;
+
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let metadata = Metadata::from(FileStat {
+            size: Some(3),
+            uid: Some(1000),
+            gid: None,
+            perm: Some(0o100644),
+            atime: None,
+            mtime: Some(10),
+        });
+        assert!(metadata.is_file());
+        assert!(!metadata.is_dir());
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(metadata.permissions(), Some(0o644));
+        assert_eq!(
+            metadata.modified(),
+            Some(UNIX_EPOCH + Duration::from_secs(10))
+        );
+        assert_eq!(metadata.accessed(), None);
+        assert_eq!(metadata.uid(), Some(1000));
+    }
+}
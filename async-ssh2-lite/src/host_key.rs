@@ -0,0 +1,282 @@
+use std::{
+    fs::OpenOptions,
+    io::{Error as IoError, ErrorKind as IoErrorKind, Write as _},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use base64::{
+    engine::general_purpose::{STANDARD, STANDARD_NO_PAD},
+    Engine as _,
+};
+use hmac::{Hmac, Mac as _};
+use sha1::Sha1;
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, Session};
+
+use crate::{error::Error, util::home_dir};
+
+//
+/// How the server host key is verified right after `AsyncSession::handshake`.
+#[derive(Clone)]
+pub enum HostKeyPolicy {
+    /// The key must already be in one of the known_hosts files.
+    Strict,
+    /// Unknown hosts are accepted and appended to the first known_hosts file, changed keys are rejected.
+    AcceptNew,
+    /// Any key is accepted, known_hosts files are not read.
+    Insecure,
+    /// The callback decides, it receives the result of the known_hosts lookup.
+    Callback(Arc<dyn Fn(&HostKeyCheck<'_>) -> bool + Send + Sync>),
+}
+
+impl core::fmt::Debug for HostKeyPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Strict => write!(f, "Strict"),
+            Self::AcceptNew => write!(f, "AcceptNew"),
+            Self::Insecure => write!(f, "Insecure"),
+            Self::Callback(_) => write!(f, "Callback"),
+        }
+    }
+}
+
+impl HostKeyPolicy {
+    pub fn with_callback<F>(f: F) -> Self
+    where
+        F: Fn(&HostKeyCheck<'_>) -> bool + Send + Sync + 'static,
+    {
+        Self::Callback(Arc::new(f))
+    }
+}
+
+//
+#[derive(Debug, Clone)]
+pub struct HostKeyCheck<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub key: &'a [u8],
+    pub key_type: HostKeyType,
+    pub fingerprint: &'a str,
+    pub result: CheckResult,
+}
+
+//
+#[derive(Debug, Clone)]
+pub struct HostKeyMismatch {
+    pub host: String,
+    pub port: u16,
+    pub key_type: HostKeyType,
+    /// OpenSSH style, e.g. `SHA256:...`.
+    pub fingerprint: String,
+    /// `Mismatch` when the known key differs, `NotFound` when the host is unknown.
+    pub result: CheckResult,
+}
+
+impl core::fmt::Display for HostKeyMismatch {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.result {
+            CheckResult::NotFound => write!(
+                f,
+                "host key for {} is not known, key_type:{:?} fingerprint:{}",
+                known_hosts_host_pattern(&self.host, self.port),
+                self.key_type,
+                self.fingerprint
+            ),
+            _ => write!(
+                f,
+                "host key for {} has changed, key_type:{:?} fingerprint:{}",
+                known_hosts_host_pattern(&self.host, self.port),
+                self.key_type,
+                self.fingerprint
+            ),
+        }
+    }
+}
+
+//
+#[derive(Debug, Clone)]
+pub(crate) struct HostKeyVerification {
+    pub(crate) policy: HostKeyPolicy,
+    pub(crate) known_hosts_files: Vec<PathBuf>,
+    pub(crate) hash_known_hosts: bool,
+}
+
+impl HostKeyVerification {
+    pub(crate) fn default_known_hosts_files() -> Vec<PathBuf> {
+        let mut files = vec![];
+        if let Some(home) = home_dir() {
+            files.push(home.join(".ssh").join("known_hosts"));
+        }
+        #[cfg(unix)]
+        files.push(PathBuf::from("/etc/ssh/ssh_known_hosts"));
+        files
+    }
+
+    pub(crate) async fn verify(&self, sess: &Session, host: &str, port: u16) -> Result<(), Error> {
+        if matches!(self.policy, HostKeyPolicy::Insecure) {
+            return Ok(());
+        }
+
+        let (key, key_type) = match sess.host_key() {
+            Some((key, key_type)) => (key.to_vec(), key_type),
+            None => return Err(Error::Other("host key not available".into())),
+        };
+        let fingerprint = sess
+            .host_key_hash(HashType::Sha256)
+            .map(|hash| format!("SHA256:{}", STANDARD_NO_PAD.encode(hash)))
+            .unwrap_or_default();
+
+        let mut contents = vec![];
+        for file in &self.known_hosts_files {
+            match read_to_string(file.to_owned()).await {
+                Ok(content) => contents.push(content),
+                Err(err) if err.kind() == IoErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // KnownHosts is not Send, keep it out of await points
+        let result = {
+            let mut known_hosts = sess.known_hosts()?;
+            for line in contents.iter().flat_map(|content| content.lines()) {
+                // Markers such as @cert-authority and @revoked are not supported by libssh2
+                let _ = known_hosts.read_str(line, KnownHostFileKind::OpenSSH);
+            }
+            known_hosts.check_port(host, port, &key)
+        };
+
+        let accepted = match (&self.policy, result) {
+            (HostKeyPolicy::Insecure, _) => true,
+            (HostKeyPolicy::Callback(cb), _) => cb(&HostKeyCheck {
+                host,
+                port,
+                key: &key,
+                key_type,
+                fingerprint: &fingerprint,
+                result,
+            }),
+            (_, CheckResult::Match) => true,
+            (HostKeyPolicy::AcceptNew, CheckResult::NotFound) => {
+                if let Some(file) = self.known_hosts_files.first() {
+                    let line = known_hosts_line(host, port, &key, self.hash_known_hosts)?;
+                    append_line(file.to_owned(), line).await?;
+                }
+                true
+            }
+            (_, _) => false,
+        };
+
+        if accepted {
+            Ok(())
+        } else {
+            Err(Error::HostKeyMismatch(HostKeyMismatch {
+                host: host.to_owned(),
+                port,
+                key_type,
+                fingerprint,
+                result,
+            }))
+        }
+    }
+}
+
+//
+fn known_hosts_host_pattern(host: &str, port: u16) -> String {
+    if port == 22 {
+        host.to_owned()
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+fn known_hosts_line(host: &str, port: u16, key: &[u8], hash: bool) -> Result<String, Error> {
+    // The key blob starts with the key type name, e.g. ssh-ed25519
+    let key_type_name = key
+        .get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .and_then(|len| key.get(4..4 + len))
+        .and_then(|name| core::str::from_utf8(name).ok())
+        .ok_or_else(|| Error::Other("invalid host key".into()))?;
+
+    let pattern = known_hosts_host_pattern(host, port);
+    let pattern = if hash {
+        let salt: [u8; 20] = rand::random();
+        let mut mac = Hmac::<Sha1>::new_from_slice(&salt)
+            .map_err(|err| Error::Other(err.to_string().into()))?;
+        mac.update(pattern.as_bytes());
+        format!(
+            "|1|{}|{}",
+            STANDARD.encode(salt),
+            STANDARD.encode(mac.finalize().into_bytes())
+        )
+    } else {
+        pattern
+    };
+
+    Ok(format!(
+        "{pattern} {key_type_name} {}\n",
+        STANDARD.encode(key)
+    ))
+}
+
+async fn read_to_string(path: PathBuf) -> Result<String, IoError> {
+    blocking::unblock(move || std::fs::read_to_string(path)).await
+}
+
+async fn append_line(path: PathBuf, line: String) -> Result<(), IoError> {
+    blocking::unblock(move || {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(line.as_bytes())
+    })
+    .await
+}
+
+fn create_dir_all(dir: &Path) -> Result<(), IoError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt as _;
+
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+    }
+    #[cfg(not(unix))]
+    {
+        std::fs::create_dir_all(dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_hosts_line() {
+        let key = [&[0, 0, 0, 11][..], b"ssh-ed25519", &[0, 0, 0, 1, 0xff]].concat();
+
+        assert_eq!(
+            known_hosts_line("example.com", 22, &key, false).unwrap(),
+            format!("example.com ssh-ed25519 {}\n", STANDARD.encode(&key))
+        );
+        assert_eq!(
+            known_hosts_line("127.0.0.1", 2222, &key, false).unwrap(),
+            format!("[127.0.0.1]:2222 ssh-ed25519 {}\n", STANDARD.encode(&key))
+        );
+
+        let line = known_hosts_line("example.com", 22, &key, true).unwrap();
+        assert!(line.starts_with("|1|"));
+        let pattern = line.split(' ').next().unwrap();
+        let mut parts = pattern.split('|').skip(2);
+        let salt = STANDARD.decode(parts.next().unwrap()).unwrap();
+        let hash = STANDARD.decode(parts.next().unwrap()).unwrap();
+        let mut mac = Hmac::<Sha1>::new_from_slice(&salt).unwrap();
+        mac.update(b"example.com");
+        assert_eq!(mac.finalize().into_bytes().as_slice(), hash.as_slice());
+
+        assert!(known_hosts_line("example.com", 22, &[0, 0, 0, 9], false).is_err());
+    }
+}
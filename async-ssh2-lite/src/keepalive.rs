@@ -0,0 +1,252 @@
+//! Sends keepalives on a schedule and marks the session broken when the peer stops answering,
+//! like OpenSSH ServerAliveInterval and ServerAliveCountMax.
+//!
+//! libssh2 only reads the replies when some other operation runs, so a reply is detected as new
+//! bytes arriving on the socket.
+
+use core::{future::Future, time::Duration};
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use futures_util::{
+    future::{self, Either},
+    pin_mut,
+};
+use ssh2::Session;
+
+use crate::{
+    error::Error,
+    session_stream::{session_timeout, AsyncSessionStream},
+};
+
+//
+/// Stops the keepalive task when dropped, within one interval.
+#[derive(Debug)]
+pub struct KeepaliveHandle {
+    stop: Arc<AtomicBool>,
+    detached: bool,
+}
+
+impl KeepaliveHandle {
+    pub(crate) fn new() -> (Self, Arc<AtomicBool>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        (
+            Self {
+                stop: stop.clone(),
+                detached: false,
+            },
+            stop,
+        )
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// Keeps the task running until the session and everything opened from it are dropped.
+    pub fn detach(mut self) {
+        self.detached = true;
+    }
+}
+
+impl Drop for KeepaliveHandle {
+    fn drop(&mut self) {
+        if !self.detached {
+            self.stop();
+        }
+    }
+}
+
+//
+/// Fails when keepalive is not configured, see `SessionConfiguration::set_keepalive`.
+pub(crate) fn check_configured(sess: &Session) -> Result<(), Error> {
+    match sess.keepalive_send() {
+        Ok(0) => Err(Error::Other(
+            "keepalive is not configured, see SessionConfiguration::set_keepalive".into(),
+        )),
+        Ok(_) => Ok(()),
+        Err(err) if crate::util::ssh2_error_is_would_block(&err) => Ok(()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Runs until stopped or until `count_max` keepalives in a row go unanswered, the session is
+/// then marked broken.
+pub(crate) async fn run<S, T, TF>(
+    sess: Session,
+    stream: Arc<S>,
+    broken: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    count_max: u32,
+    sleep: T,
+) where
+    S: AsyncSessionStream + Send + Sync + 'static,
+    T: Fn(Duration) -> TF,
+    TF: Future<Output = ()>,
+{
+    let mut interval = None;
+    let mut missed = 0;
+
+    loop {
+        // Also stops once the task holds the last reference to the stream
+        if stop.load(Ordering::SeqCst)
+            || broken.load(Ordering::SeqCst)
+            || Arc::strong_count(&stream) == 1
+        {
+            return;
+        }
+
+        let timeout = interval.or_else(|| session_timeout(&sess));
+        let answered = match stream
+            .rw_with_timeout(|| sess.keepalive_send(), &sess, timeout)
+            .await
+        {
+            Ok(secs) => {
+                let dur = Duration::from_secs(secs.max(1) as u64);
+                interval = Some(dur);
+                match wait_reply(&*stream, sleep(dur)).await {
+                    Ok(answered) => answered,
+                    Err(_) => break,
+                }
+            }
+            // The peer does not even take our data
+            Err(Error::Timeout(_)) => false,
+            Err(_) => break,
+        };
+
+        if answered {
+            missed = 0;
+        } else {
+            missed += 1;
+            if missed >= count_max.max(1) {
+                break;
+            }
+        }
+    }
+
+    // Wakes every pending operation, they fail on the closed socket
+    broken.store(true, Ordering::SeqCst);
+    let _ = stream.shutdown_socket();
+}
+
+/// Whether the peer sent anything before `timer` fired.
+async fn wait_reply<S>(stream: &S, timer: impl Future<Output = ()>) -> Result<bool, IoError>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let closed = || IoError::new(IoErrorKind::UnexpectedEof, "peer closed the connection");
+
+    let before = stream.peek_len()?.ok_or_else(closed)?;
+    pin_mut!(timer);
+
+    let readable = stream.wait_readable();
+    pin_mut!(readable);
+    let stale = match future::select(readable, timer.as_mut()).await {
+        Either::Left((ret, _)) => {
+            ret?;
+            // Anything but the same unread bytes is new data, also when someone consumed it
+            let n = stream.peek_len()?.ok_or_else(closed)?;
+            n != 0 && n == before
+        }
+        Either::Right(_) => return Ok(false),
+    };
+
+    timer.await;
+    if !stale {
+        return Ok(true);
+    }
+    // Nobody reads the session, the unread bytes grow with every reply
+    let after = stream.peek_len()?.ok_or_else(closed)?;
+    Ok(after != before)
+}
+
+#[cfg(all(test, unix, feature = "async-io"))]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{Read as _, Write as _},
+        os::unix::net::UnixStream,
+    };
+
+    use async_io::{Async, Timer};
+
+    async fn sleep(dur: Duration) {
+        Timer::after(dur).await;
+    }
+
+    #[test]
+    fn test_wait_reply() -> Result<(), Box<dyn std::error::Error>> {
+        futures_lite::future::block_on(async {
+            let (stream, peer) = UnixStream::pair()?;
+            let stream = Async::new(stream)?;
+            let dur = Duration::from_millis(100);
+
+            // Silent peer
+            assert!(!wait_reply(&stream, sleep(dur)).await?);
+
+            // A reply nobody reads yet
+            let reply_later = |mut peer: UnixStream| {
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(20));
+                    peer.write_all(b"reply").unwrap();
+                })
+            };
+            reply_later(peer.try_clone()?);
+            assert!(wait_reply(&stream, sleep(dur)).await?);
+
+            // The same unread bytes are no reply
+            assert!(!wait_reply(&stream, sleep(dur)).await?);
+
+            // More unread bytes are
+            reply_later(peer.try_clone()?);
+            assert!(wait_reply(&stream, sleep(dur)).await?);
+
+            // Closed, once the replies are read
+            drop(peer);
+            let mut buf = [0; 16];
+            assert_eq!(stream.get_ref().read(&mut buf)?, 10);
+            assert_eq!(
+                wait_reply(&stream, sleep(dur)).await.unwrap_err().kind(),
+                IoErrorKind::UnexpectedEof
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_wait_reply_with_many_unread() -> Result<(), Box<dyn std::error::Error>> {
+        const UNREAD: usize = 256 * 1024;
+
+        futures_lite::future::block_on(async {
+            let (stream, mut peer) = UnixStream::pair()?;
+            socket2::SockRef::from(&stream).set_recv_buffer_size(UNREAD * 2)?;
+            socket2::SockRef::from(&peer).set_send_buffer_size(UNREAD * 2)?;
+            let stream = Async::new(stream)?;
+            let dur = Duration::from_millis(100);
+
+            // Far more than one peek used to see
+            let mut writer = peer.try_clone()?;
+            std::thread::spawn(move || writer.write_all(&[0; UNREAD]).unwrap());
+            while stream.peek_len()? != Some(UNREAD) {
+                sleep(Duration::from_millis(10)).await;
+            }
+            assert!(!wait_reply(&stream, sleep(dur)).await?);
+
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                peer.write_all(b"reply").unwrap();
+            });
+            assert!(wait_reply(&stream, sleep(dur)).await?);
+            assert_eq!(stream.peek_len()?, Some(UNREAD + 5));
+
+            Ok(())
+        })
+    }
+}
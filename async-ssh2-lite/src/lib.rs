@@ -9,28 +9,66 @@ pub type AsyncIoTcpStream = async_io::Async<std::net::TcpStream>;
 #[cfg(all(unix, feature = "async-io"))]
 pub type AsyncIoUnixStream = async_io::Async<std::os::unix::net::UnixStream>;
 
+#[cfg(all(unix, feature = "async-io"))]
+pub type AsyncIoSshConfigStream = session_stream::MaybeProxied<AsyncIoTcpStream, AsyncIoUnixStream>;
+#[cfg(all(windows, feature = "async-io"))]
+pub type AsyncIoSshConfigStream = AsyncIoTcpStream;
+
 #[cfg(all(unix, feature = "tokio"))]
 pub use tokio::net::UnixStream as TokioUnixStream;
 #[cfg(feature = "tokio")]
 pub use tokio::{self, net::TcpStream as TokioTcpStream};
+#[cfg(all(unix, feature = "tokio"))]
+pub type TokioSshConfigStream = session_stream::MaybeProxied<TokioTcpStream, TokioUnixStream>;
+#[cfg(all(windows, feature = "tokio"))]
+pub type TokioSshConfigStream = TokioTcpStream;
 
 //
 pub mod agent;
 pub mod channel;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub mod connect;
+pub mod exec;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub mod forward;
+pub mod fs;
+pub mod host_key;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub mod keepalive;
 pub mod listener;
+#[cfg(unix)]
+pub mod proxy_command;
 pub mod session;
 pub mod sftp;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+mod socks;
+pub mod ssh_config;
+pub mod transfer;
+pub mod walk;
 
 pub use agent::AsyncAgent;
-pub use channel::{AsyncChannel, AsyncStream};
+pub use channel::{AsyncChannel, AsyncChannelReadHalf, AsyncChannelWriteHalf, AsyncStream};
+pub use exec::{ExecConfiguration, ExecOutput, RemoteChild, RemoteCommand};
+pub use fs::Metadata;
+pub use host_key::HostKeyPolicy;
 pub use listener::AsyncListener;
-pub use session::{AsyncSession, SessionConfiguration};
-pub use sftp::{AsyncFile, AsyncSftp};
+pub use session::{AsyncSession, JumpHost, SessionConfiguration};
+pub use sftp::{AsyncFile, AsyncSftp, ReadDirStream};
+pub use ssh_config::{ResolvedSshConfig, SshConfig};
+pub use transfer::{
+    DirTransferConfiguration, DirTransferReport, SymlinkPolicy, TransferConfiguration,
+    TransferProgress,
+};
+pub use walk::{WalkConfiguration, WalkEntry, WalkStream};
 
 //
 pub mod error;
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub(crate) mod runtime;
 pub mod session_stream;
+#[cfg(all(unix, any(feature = "tokio", feature = "async-io")))]
+pub(crate) mod transport;
 pub mod util;
 
-pub use error::Error;
+pub use error::{Error, ErrorKind};
 pub use session_stream::AsyncSessionStream;
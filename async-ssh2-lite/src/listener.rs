@@ -1,9 +1,12 @@
-use core::time::Duration;
 use std::sync::Arc;
 
-use ssh2::{BlockDirections, Listener, Session};
+use ssh2::{BlockDirections, Error as Ssh2Error, ErrorCode, Listener, Session};
 
-use crate::{channel::AsyncChannel, error::Error, session_stream::AsyncSessionStream};
+use crate::{
+    channel::AsyncChannel,
+    error::Error,
+    session_stream::{AsyncSessionStream, BlockDirectionsExt as _},
+};
 
 //
 pub struct AsyncListener<S> {
@@ -29,12 +32,7 @@ where
     pub async fn accept(&mut self) -> Result<AsyncChannel<S>, Error> {
         let channel = self
             .stream
-            .x_with(
-                || self.inner.accept(),
-                &self.sess,
-                BlockDirections::Both,
-                Some(Duration::from_millis(10)),
-            )
+            .x_with(|| self.inner.accept(), &self.sess, BlockDirections::Both)
             .await?;
 
         Ok(AsyncChannel::from_parts(
@@ -43,4 +41,34 @@ where
             self.stream.clone(),
         ))
     }
+
+    /// Sends cancel-tcpip-forward and frees the listener, connections not accepted yet are
+    /// closed. Dropping the listener does the same without waiting.
+    ///
+    /// libssh2 gets a single attempt, from the drop of `ssh2::Listener`, so it is made once no
+    /// other packet is half sent and the socket is writable.
+    pub async fn cancel(self) -> Result<(), Error> {
+        let Self {
+            inner,
+            sess,
+            stream,
+        } = self;
+        let mut inner = Some(inner);
+
+        stream
+            .write_with(
+                || {
+                    if sess.block_directions().is_writable() {
+                        return Err(Ssh2Error::new(
+                            ErrorCode::Session(libssh2_sys::LIBSSH2_ERROR_EAGAIN),
+                            "would block",
+                        ));
+                    }
+                    drop(inner.take());
+                    Ok(())
+                },
+                &sess,
+            )
+            .await
+    }
 }
@@ -0,0 +1,159 @@
+//! Runs a session over a local process's stdin and stdout, like OpenSSH ProxyCommand.
+//!
+//! The process gets one end of a socketpair as its stdin and stdout, the session gets the other end.
+
+use core::time::Duration;
+use std::{
+    process::{Child, Command, ExitStatus},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Instant,
+};
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+const STDERR_LIMIT: usize = 64 * 1024;
+const EXIT_WAIT: Duration = Duration::from_millis(500);
+
+//
+/// `sh -c <command>`, for commands from ssh_config.
+pub fn shell_command(command: &str) -> Command {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(command);
+    cmd
+}
+
+//
+#[derive(Debug, Clone)]
+pub struct ProxyCommandError {
+    /// `None` when the process is still running.
+    pub status: Option<ExitStatus>,
+    /// The first 64KiB written to stderr.
+    pub stderr: String,
+}
+
+impl core::fmt::Display for ProxyCommandError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "proxy command exited with {status}")?,
+            None => write!(f, "proxy command failed")?,
+        }
+        let stderr = self.stderr.trim();
+        if !stderr.is_empty() {
+            write!(f, ", stderr: {stderr}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ProxyCommandError {}
+
+//
+pub(crate) struct ProxyCommandChild {
+    child: Mutex<Child>,
+    stderr: Arc<Mutex<Vec<u8>>>,
+    stderr_done: Mutex<mpsc::Receiver<()>>,
+}
+
+impl core::fmt::Debug for ProxyCommandChild {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ProxyCommandChild")
+            .field("stderr", &self.stderr())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProxyCommandChild {
+    pub(crate) fn stderr(&self) -> String {
+        let stderr = self.stderr.lock().expect("stderr lock poisoned");
+        String::from_utf8_lossy(&stderr).into_owned()
+    }
+
+    /// The error to report instead of the ssh one when the process has exited.
+    pub(crate) async fn exit_error(self: &Arc<Self>) -> Option<ProxyCommandError> {
+        let this = self.clone();
+        blocking::unblock(move || {
+            let deadline = Instant::now() + EXIT_WAIT;
+            let status = loop {
+                match this.child.lock().expect("child lock poisoned").try_wait() {
+                    Ok(Some(status)) => break status,
+                    Ok(None) if Instant::now() < deadline => {}
+                    Ok(None) | Err(_) => return None,
+                }
+                thread::sleep(Duration::from_millis(10));
+            };
+
+            // Let the stderr thread catch up, grandchildren may still hold the pipe
+            let _ = this
+                .stderr_done
+                .lock()
+                .expect("stderr_done lock poisoned")
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()));
+
+            Some(ProxyCommandError {
+                status: Some(status),
+                stderr: this.stderr(),
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for ProxyCommandChild {
+    fn drop(&mut self) {
+        if let Ok(child) = self.child.get_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+//
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub(crate) fn spawn(
+    mut command: Command,
+) -> Result<(std::os::unix::net::UnixStream, ProxyCommandChild), std::io::Error> {
+    use std::{
+        io::Read as _,
+        os::{fd::OwnedFd, unix::net::UnixStream as StdUnixStream},
+        process::Stdio,
+    };
+
+    let (stream, peer) = StdUnixStream::pair()?;
+
+    command
+        .stdin(Stdio::from(OwnedFd::from(peer.try_clone()?)))
+        .stdout(Stdio::from(OwnedFd::from(peer)))
+        .stderr(Stdio::piped());
+    let mut child = command.spawn()?;
+    // The command owns the peer fds, the process must be the only holder
+    drop(command);
+
+    let pipe = child.stderr.take();
+    let (stderr_done_tx, stderr_done_rx) = mpsc::channel::<()>();
+    // Killed on drop, also when the stderr thread cannot be spawned
+    let child = ProxyCommandChild {
+        child: Mutex::new(child),
+        stderr: Arc::new(Mutex::new(vec![])),
+        stderr_done: Mutex::new(stderr_done_rx),
+    };
+
+    if let Some(mut pipe) = pipe {
+        let stderr = child.stderr.clone();
+        thread::Builder::new()
+            .name("async-ssh2-lite-proxy-command".into())
+            .spawn(move || {
+                let mut buf = [0; 4096];
+                while let Ok(n) = pipe.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    let mut stderr = stderr.lock().expect("stderr lock poisoned");
+                    let n = n.min(STDERR_LIMIT.saturating_sub(stderr.len()));
+                    stderr.extend_from_slice(&buf[..n]);
+                }
+                drop(stderr_done_tx);
+            })?;
+    }
+
+    Ok((stream, child))
+}
@@ -0,0 +1,244 @@
+//! Where the forwarding helpers run their tasks and local sockets, on tokio or async-io.
+
+use core::{future::Future, time::Duration};
+use std::{
+    io::Error as IoError,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(feature = "async-io")]
+use async_io::Async;
+use futures_util::{
+    future::{self, AbortHandle},
+    io::{AsyncRead, AsyncWrite},
+};
+
+use crate::util::ConnectInfo;
+
+//
+/// A local connection, as futures I/O.
+pub(crate) trait LocalStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> LocalStream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+pub(crate) type BoxLocalStream = Box<dyn LocalStream>;
+
+//
+#[derive(Debug, Clone)]
+pub(crate) enum Runtime {
+    #[cfg(feature = "tokio")]
+    Tokio(tokio::runtime::Handle),
+    /// async-io has no executor, the tasks run on a shared one, see `executor`.
+    #[cfg(feature = "async-io")]
+    AsyncIo,
+}
+
+impl Runtime {
+    /// The current tokio runtime, otherwise async-io when enabled.
+    pub(crate) fn current() -> Result<Self, IoError> {
+        #[cfg(feature = "tokio")]
+        {
+            let ret = tokio::runtime::Handle::try_current();
+            #[cfg(feature = "async-io")]
+            if ret.is_err() {
+                return Ok(Self::AsyncIo);
+            }
+            ret.map(Self::Tokio).map_err(IoError::other)
+        }
+        #[cfg(not(feature = "tokio"))]
+        Ok(Self::AsyncIo)
+    }
+
+    pub(crate) fn spawn<F>(&self, fut: F) -> Result<Task, IoError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (fut, abort) = future::abortable(fut);
+        let finished = Arc::new(AtomicBool::new(false));
+        let guard = FinishedGuard(finished.clone());
+        let fut = async move {
+            let _guard = guard;
+            let _ = fut.await;
+        };
+
+        match self {
+            #[cfg(feature = "tokio")]
+            Self::Tokio(handle) => {
+                handle.spawn(fut);
+            }
+            #[cfg(feature = "async-io")]
+            Self::AsyncIo => {
+                executor()?.spawn(fut).detach();
+            }
+        }
+
+        Ok(Task { abort, finished })
+    }
+
+    pub(crate) async fn sleep(&self, dur: Duration) {
+        match self {
+            #[cfg(feature = "tokio")]
+            Self::Tokio(_) => tokio::time::sleep(dur).await,
+            #[cfg(feature = "async-io")]
+            Self::AsyncIo => {
+                async_io::Timer::after(dur).await;
+            }
+        }
+    }
+
+    /// Returns the bound address too, with the actual port when binding port 0.
+    pub(crate) async fn bind(
+        &self,
+        addr: &ConnectInfo,
+    ) -> Result<(Listener, ConnectInfo), IoError> {
+        let listener = match (self, addr) {
+            #[cfg(feature = "tokio")]
+            (Self::Tokio(_), ConnectInfo::Tcp(addr)) => {
+                Listener::TokioTcp(tokio::net::TcpListener::bind(addr).await?)
+            }
+            #[cfg(all(unix, feature = "tokio"))]
+            (Self::Tokio(_), ConnectInfo::Unix(path)) => {
+                Listener::TokioUnix(tokio::net::UnixListener::bind(path)?)
+            }
+            #[cfg(feature = "async-io")]
+            (Self::AsyncIo, ConnectInfo::Tcp(addr)) => {
+                Listener::AsyncIoTcp(Async::<std::net::TcpListener>::bind(*addr)?)
+            }
+            #[cfg(all(unix, feature = "async-io"))]
+            (Self::AsyncIo, ConnectInfo::Unix(path)) => {
+                Listener::AsyncIoUnix(Async::<std::os::unix::net::UnixListener>::bind(path)?)
+            }
+        };
+
+        let local_addr = match &listener {
+            #[cfg(feature = "tokio")]
+            Listener::TokioTcp(listener) => ConnectInfo::Tcp(listener.local_addr()?),
+            #[cfg(feature = "async-io")]
+            Listener::AsyncIoTcp(listener) => ConnectInfo::Tcp(listener.get_ref().local_addr()?),
+            #[cfg(unix)]
+            #[allow(unreachable_patterns)]
+            _ => addr.clone(),
+        };
+
+        Ok((listener, local_addr))
+    }
+
+    pub(crate) async fn connect(&self, addr: &ConnectInfo) -> Result<BoxLocalStream, IoError> {
+        Ok(match (self, addr) {
+            #[cfg(feature = "tokio")]
+            (Self::Tokio(_), ConnectInfo::Tcp(addr)) => Box::new(crate::util::TokioCompat(
+                tokio::net::TcpStream::connect(addr).await?,
+            )),
+            #[cfg(all(unix, feature = "tokio"))]
+            (Self::Tokio(_), ConnectInfo::Unix(path)) => Box::new(crate::util::TokioCompat(
+                tokio::net::UnixStream::connect(path).await?,
+            )),
+            #[cfg(feature = "async-io")]
+            (Self::AsyncIo, ConnectInfo::Tcp(addr)) => {
+                Box::new(Async::<std::net::TcpStream>::connect(*addr).await?)
+            }
+            #[cfg(all(unix, feature = "async-io"))]
+            (Self::AsyncIo, ConnectInfo::Unix(path)) => {
+                Box::new(Async::<std::os::unix::net::UnixStream>::connect(path).await?)
+            }
+        })
+    }
+}
+
+//
+#[cfg(feature = "async-io")]
+static EXECUTOR: async_executor::Executor<'static> = async_executor::Executor::new();
+
+/// The executor of `Runtime::AsyncIo`, run by one thread per CPU that are started on first use.
+/// Fails only when not a single thread could be started.
+#[cfg(feature = "async-io")]
+fn executor() -> Result<&'static async_executor::Executor<'static>, IoError> {
+    static THREADS: std::sync::Mutex<usize> = std::sync::Mutex::new(0);
+
+    let max = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = THREADS.lock().expect("executor lock poisoned");
+    while *threads < max {
+        let ret = std::thread::Builder::new()
+            .name("async-ssh2-lite-executor".into())
+            .spawn(|| async_io::block_on(EXECUTOR.run(future::pending::<()>())));
+        match ret {
+            Ok(_) => *threads += 1,
+            // Tried again on the next spawn
+            Err(_) if *threads > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(&EXECUTOR)
+}
+
+//
+pub(crate) enum Listener {
+    #[cfg(feature = "tokio")]
+    TokioTcp(tokio::net::TcpListener),
+    #[cfg(all(unix, feature = "tokio"))]
+    TokioUnix(tokio::net::UnixListener),
+    #[cfg(feature = "async-io")]
+    AsyncIoTcp(Async<std::net::TcpListener>),
+    #[cfg(all(unix, feature = "async-io"))]
+    AsyncIoUnix(Async<std::os::unix::net::UnixListener>),
+}
+
+impl Listener {
+    /// The peer address is `None` for Unix sockets.
+    pub(crate) async fn accept(&self) -> Result<(BoxLocalStream, Option<SocketAddr>), IoError> {
+        Ok(match self {
+            #[cfg(feature = "tokio")]
+            Self::TokioTcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                (Box::new(crate::util::TokioCompat(stream)), Some(peer))
+            }
+            #[cfg(all(unix, feature = "tokio"))]
+            Self::TokioUnix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                (Box::new(crate::util::TokioCompat(stream)), None)
+            }
+            #[cfg(feature = "async-io")]
+            Self::AsyncIoTcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                (Box::new(stream), Some(peer))
+            }
+            #[cfg(all(unix, feature = "async-io"))]
+            Self::AsyncIoUnix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                (Box::new(stream), None)
+            }
+        })
+    }
+}
+
+//
+/// A spawned task, detached, stops at its next wakeup once aborted.
+#[derive(Debug)]
+pub(crate) struct Task {
+    abort: AbortHandle,
+    finished: Arc<AtomicBool>,
+}
+
+impl Task {
+    pub(crate) fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
+
+/// Also marks tasks dropped by a runtime that shuts down.
+struct FinishedGuard(Arc<AtomicBool>);
+
+impl Drop for FinishedGuard {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
@@ -1,25 +1,59 @@
+#[cfg(all(unix, any(feature = "tokio", feature = "async-io")))]
+use core::future::Future;
 use core::time::Duration;
-use std::{path::Path, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 #[cfg(unix)]
 use std::os::unix::io::AsRawFd;
 #[cfg(windows)]
 use std::os::windows::io::{AsRawSocket, BorrowedSocket};
+#[cfg(all(unix, any(feature = "tokio", feature = "async-io")))]
+use std::process::Command;
 
+use futures_util::{
+    future::{self, Either},
+    pin_mut,
+};
 use ssh2::{
     BlockDirections, DisconnectCode, Error as Ssh2Error, HashType, HostKeyType,
     KeyboardInteractivePrompt, KnownHosts, MethodType, PublicKey, ScpFileStat, Session, TraceFlags,
 };
 
+#[cfg(unix)]
+use crate::proxy_command::ProxyCommandChild;
+#[cfg(all(unix, any(feature = "tokio", feature = "async-io")))]
+use crate::session_stream::MaybeProxied;
 use crate::{
-    agent::AsyncAgent, channel::AsyncChannel, error::Error, listener::AsyncListener,
-    session_stream::AsyncSessionStream, sftp::AsyncSftp,
+    agent::AsyncAgent,
+    channel::AsyncChannel,
+    error::Error,
+    exec::{ExecConfiguration, ExecOutput, RemoteCommand},
+    host_key::{HostKeyPolicy, HostKeyVerification},
+    listener::AsyncListener,
+    session_stream::AsyncSessionStream,
+    sftp::AsyncSftp,
+    ssh_config::ResolvedSshConfig,
+    util::{home_dir, local_user},
 };
 
+const KEEPALIVE_COUNT_MAX_DEFAULT: u32 = 3;
+
 //
 pub struct AsyncSession<S> {
     inner: Session,
     stream: Arc<S>,
+    host_key_verification: Option<HostKeyVerification>,
+    remote_host: Option<(String, u16)>,
+    #[cfg(unix)]
+    proxy_command: Option<Arc<ProxyCommandChild>>,
+    broken: Arc<AtomicBool>,
+    keepalive_count_max: u32,
 }
 
 impl<S> Clone for AsyncSession<S> {
@@ -27,6 +61,12 @@ impl<S> Clone for AsyncSession<S> {
         Self {
             inner: self.inner.clone(),
             stream: self.stream.clone(),
+            host_key_verification: self.host_key_verification.clone(),
+            remote_host: self.remote_host.clone(),
+            #[cfg(unix)]
+            proxy_command: self.proxy_command.clone(),
+            broken: self.broken.clone(),
+            keepalive_count_max: self.keepalive_count_max,
         }
     }
 }
@@ -40,6 +80,15 @@ where
         stream: S,
         configuration: impl Into<Option<SessionConfiguration>>,
     ) -> Result<Self, Error> {
+        let configuration = configuration.into();
+        let host_key_verification = configuration
+            .as_ref()
+            .and_then(SessionConfiguration::host_key_verification);
+        let keepalive_count_max = configuration
+            .as_ref()
+            .and_then(|x| x.keepalive_count_max)
+            .unwrap_or(KEEPALIVE_COUNT_MAX_DEFAULT);
+
         let mut session = get_session(configuration)?;
         session.set_tcp_stream(stream.as_raw_fd());
 
@@ -48,6 +97,11 @@ where
         Ok(Self {
             inner: session,
             stream,
+            host_key_verification,
+            remote_host: None,
+            proxy_command: None,
+            broken: Arc::new(AtomicBool::new(false)),
+            keepalive_count_max,
         })
     }
 }
@@ -61,6 +115,15 @@ where
         stream: S,
         configuration: impl Into<Option<SessionConfiguration>>,
     ) -> Result<Self, Error> {
+        let configuration = configuration.into();
+        let host_key_verification = configuration
+            .as_ref()
+            .and_then(SessionConfiguration::host_key_verification);
+        let keepalive_count_max = configuration
+            .as_ref()
+            .and_then(|x| x.keepalive_count_max)
+            .unwrap_or(KEEPALIVE_COUNT_MAX_DEFAULT);
+
         let mut session = get_session(configuration)?;
         session.set_tcp_stream(unsafe { BorrowedSocket::borrow_raw(stream.as_raw_socket()) });
 
@@ -69,6 +132,10 @@ where
         Ok(Self {
             inner: session,
             stream,
+            host_key_verification,
+            remote_host: None,
+            broken: Arc::new(AtomicBool::new(false)),
+            keepalive_count_max,
         })
     }
 }
@@ -79,9 +146,83 @@ impl AsyncSession<crate::AsyncIoTcpStream> {
         addr: A,
         configuration: impl Into<Option<SessionConfiguration>>,
     ) -> Result<Self, Error> {
-        let stream = crate::AsyncIoTcpStream::connect(addr).await?;
+        let addr = addr.into();
+
+        Self::connect_addrs(
+            &addr.ip().to_string(),
+            addr.port(),
+            vec![addr],
+            configuration,
+        )
+        .await
+    }
+    /// Resolves `host` (`host:port`, `[v6]:port` or `host`, port 22 by default) and tries every
+    /// address Happy Eyeballs style, within `SessionConfiguration::set_connect_timeout`.
+    pub async fn connect_host(
+        host: &str,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let (host, port) = crate::connect::split_host_port(host)?;
 
-        Self::new(stream, configuration)
+        Self::connect_host_port(&host, port, configuration).await
+    }
+    /// ProxyJump and ProxyCommand need unix.
+    #[cfg(windows)]
+    pub async fn connect_with_ssh_config(resolved: &ResolvedSshConfig) -> Result<Self, Error> {
+        check_ssh_config_proxies(resolved)?;
+
+        Self::connect_host_port(
+            &resolved.host_name,
+            resolved.port,
+            resolved.session_configuration(),
+        )
+        .await
+    }
+
+    async fn connect_host_port(
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        use std::net::ToSocketAddrs as _;
+
+        let host_and_port = (host.to_owned(), port);
+        let addrs = blocking::unblock(move || {
+            host_and_port
+                .to_socket_addrs()
+                .map(|addrs| addrs.collect::<Vec<_>>())
+        })
+        .await
+        .map_err(|err| resolve_error(host, err))?;
+
+        Self::connect_addrs(host, port, addrs, configuration).await
+    }
+
+    async fn connect_addrs(
+        host: &str,
+        port: u16,
+        addrs: Vec<std::net::SocketAddr>,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let configuration = configuration.into();
+        let connect_timeout = configuration.as_ref().and_then(|x| x.connect_timeout);
+
+        let stream = crate::connect::happy_eyeballs(
+            host,
+            port,
+            addrs,
+            crate::AsyncIoTcpStream::connect,
+            |dur| async move {
+                async_io::Timer::after(dur).await;
+            },
+            connect_timeout,
+        )
+        .await
+        .map_err(std::io::Error::from)?;
+
+        let mut session = Self::new(stream, configuration)?;
+        session.set_remote_host(host, port);
+        Ok(session)
     }
 }
 
@@ -96,6 +237,100 @@ impl AsyncSession<crate::AsyncIoUnixStream> {
 
         Self::new(stream, configuration)
     }
+    /// Runs the session over any futures I/O object, e.g. a TLS or WebSocket stream.
+    ///
+    /// libssh2 gets one end of a socketpair, a background thread copies between the other end and `io`.
+    pub fn new_with_io<IO>(
+        io: IO,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error>
+    where
+        IO: futures_util::io::AsyncRead + futures_util::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let stream = crate::transport::spawn_with_async_io(io)?;
+
+        Self::new(stream, configuration)
+    }
+    /// Connects through a `direct-tcpip` channel of an authenticated bastion session, like `ssh -J`.
+    ///
+    /// The returned session is another hop's bastion as well, so chains of any length can be built.
+    /// The bridging task keeps the bastion channel open until this session and everything created
+    /// from it are dropped.
+    pub async fn connect_via<B>(
+        bastion: &AsyncSession<B>,
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error>
+    where
+        B: AsyncSessionStream + Send + Sync + 'static,
+    {
+        let channel = bastion.channel_direct_tcpip(host, port, None).await?;
+
+        let mut session = Self::new_with_io(channel, configuration)?;
+        session.set_remote_host(host, port);
+        Ok(session)
+    }
+    /// Like `connect_via` through every host of `jump_hosts` in turn, like `ssh -J a,b,c`.
+    ///
+    /// Each jump host is handshaken and passed with its index to `authenticate` before the next
+    /// one is connected through it. The session to `host` and `port` is returned as `connect_via`
+    /// returns it.
+    pub async fn connect_via_chain<B, F, Fut>(
+        bastion: &AsyncSession<B>,
+        jump_hosts: &[JumpHost],
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+        mut authenticate: F,
+    ) -> Result<Self, Error>
+    where
+        B: AsyncSessionStream + Send + Sync + 'static,
+        F: FnMut(usize, Self) -> Fut,
+        Fut: Future<Output = Result<Self, Error>>,
+    {
+        let mut hop: Option<Self> = None;
+        for (i, jump_host) in jump_hosts.iter().enumerate() {
+            let configuration = jump_host.configuration.to_owned();
+            let mut session = match hop.as_ref() {
+                Some(hop) => {
+                    Self::connect_via(hop, &jump_host.host, jump_host.port, configuration).await?
+                }
+                None => {
+                    Self::connect_via(bastion, &jump_host.host, jump_host.port, configuration)
+                        .await?
+                }
+            };
+            session.handshake().await?;
+            // Dropping a hop is fine, the channel of the next one holds on to its session
+            hop = Some(authenticate(i, session).await?);
+        }
+
+        match hop.as_ref() {
+            Some(hop) => Self::connect_via(hop, host, port, configuration).await,
+            None => Self::connect_via(bastion, host, port, configuration).await,
+        }
+    }
+    /// Runs `command` and speaks ssh over its stdin and stdout, like OpenSSH ProxyCommand.
+    ///
+    /// `host` and `port` are the server the command reaches, checked against known_hosts like
+    /// with `connect`. The process is killed once the session and its clones are dropped. If it
+    /// exits during the handshake, the handshake fails with a `ProxyCommandError` carrying its
+    /// stderr.
+    pub async fn connect_with_proxy_command(
+        command: Command,
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let (stream, child) = crate::proxy_command::spawn(command)?;
+        let stream = crate::AsyncIoUnixStream::new(stream)?;
+
+        let mut session = Self::new(stream, configuration)?;
+        session.set_remote_host(host, port);
+        session.proxy_command = Some(Arc::new(child));
+        Ok(session)
+    }
 }
 
 #[cfg(feature = "tokio")]
@@ -104,9 +339,75 @@ impl AsyncSession<crate::TokioTcpStream> {
         addr: A,
         configuration: impl Into<Option<SessionConfiguration>>,
     ) -> Result<Self, Error> {
-        let stream = crate::TokioTcpStream::connect(addr.into()).await?;
+        let addr = addr.into();
+
+        Self::connect_addrs(
+            &addr.ip().to_string(),
+            addr.port(),
+            vec![addr],
+            configuration,
+        )
+        .await
+    }
+    /// Resolves `host` (`host:port`, `[v6]:port` or `host`, port 22 by default) and tries every
+    /// address Happy Eyeballs style, within `SessionConfiguration::set_connect_timeout`.
+    pub async fn connect_host(
+        host: &str,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let (host, port) = crate::connect::split_host_port(host)?;
 
-        Self::new(stream, configuration)
+        Self::connect_host_port(&host, port, configuration).await
+    }
+    /// ProxyJump and ProxyCommand need unix.
+    #[cfg(windows)]
+    pub async fn connect_with_ssh_config(resolved: &ResolvedSshConfig) -> Result<Self, Error> {
+        check_ssh_config_proxies(resolved)?;
+
+        Self::connect_host_port(
+            &resolved.host_name,
+            resolved.port,
+            resolved.session_configuration(),
+        )
+        .await
+    }
+
+    async fn connect_host_port(
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let addrs = tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|err| resolve_error(host, err))?
+            .collect();
+
+        Self::connect_addrs(host, port, addrs, configuration).await
+    }
+
+    async fn connect_addrs(
+        host: &str,
+        port: u16,
+        addrs: Vec<std::net::SocketAddr>,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let configuration = configuration.into();
+        let connect_timeout = configuration.as_ref().and_then(|x| x.connect_timeout);
+
+        let stream = crate::connect::happy_eyeballs(
+            host,
+            port,
+            addrs,
+            crate::TokioTcpStream::connect,
+            tokio::time::sleep,
+            connect_timeout,
+        )
+        .await
+        .map_err(std::io::Error::from)?;
+
+        let mut session = Self::new(stream, configuration)?;
+        session.set_remote_host(host, port);
+        Ok(session)
     }
 }
 
@@ -121,6 +422,276 @@ impl AsyncSession<crate::TokioUnixStream> {
 
         Self::new(stream, configuration)
     }
+    /// Runs the session over any tokio I/O object, e.g. a TLS or WebSocket stream.
+    ///
+    /// libssh2 gets one end of a socketpair, a task spawned on the current runtime copies between
+    /// the other end and `io`.
+    pub fn new_with_io<IO>(
+        io: IO,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error>
+    where
+        IO: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::new_with_futures_io(crate::util::TokioCompat(io), configuration)
+    }
+    /// Like `new_with_io`, for futures I/O objects.
+    pub fn new_with_futures_io<IO>(
+        io: IO,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error>
+    where
+        IO: futures_util::io::AsyncRead + futures_util::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        let stream = crate::transport::spawn_with_tokio(io)?;
+
+        Self::new(stream, configuration)
+    }
+    /// Connects through a `direct-tcpip` channel of an authenticated bastion session, like `ssh -J`.
+    ///
+    /// The returned session is another hop's bastion as well, so chains of any length can be built.
+    /// The bridging task keeps the bastion channel open until this session and everything created
+    /// from it are dropped.
+    pub async fn connect_via<B>(
+        bastion: &AsyncSession<B>,
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error>
+    where
+        B: AsyncSessionStream + Send + Sync + 'static,
+    {
+        let channel = bastion.channel_direct_tcpip(host, port, None).await?;
+
+        let mut session = Self::new_with_futures_io(channel, configuration)?;
+        session.set_remote_host(host, port);
+        Ok(session)
+    }
+    /// Like `connect_via` through every host of `jump_hosts` in turn, like `ssh -J a,b,c`.
+    ///
+    /// Each jump host is handshaken and passed with its index to `authenticate` before the next
+    /// one is connected through it. The session to `host` and `port` is returned as `connect_via`
+    /// returns it.
+    pub async fn connect_via_chain<B, F, Fut>(
+        bastion: &AsyncSession<B>,
+        jump_hosts: &[JumpHost],
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+        mut authenticate: F,
+    ) -> Result<Self, Error>
+    where
+        B: AsyncSessionStream + Send + Sync + 'static,
+        F: FnMut(usize, Self) -> Fut,
+        Fut: Future<Output = Result<Self, Error>>,
+    {
+        let mut hop: Option<Self> = None;
+        for (i, jump_host) in jump_hosts.iter().enumerate() {
+            let configuration = jump_host.configuration.to_owned();
+            let mut session = match hop.as_ref() {
+                Some(hop) => {
+                    Self::connect_via(hop, &jump_host.host, jump_host.port, configuration).await?
+                }
+                None => {
+                    Self::connect_via(bastion, &jump_host.host, jump_host.port, configuration)
+                        .await?
+                }
+            };
+            session.handshake().await?;
+            // Dropping a hop is fine, the channel of the next one holds on to its session
+            hop = Some(authenticate(i, session).await?);
+        }
+
+        match hop.as_ref() {
+            Some(hop) => Self::connect_via(hop, host, port, configuration).await,
+            None => Self::connect_via(bastion, host, port, configuration).await,
+        }
+    }
+    /// Runs `command` and speaks ssh over its stdin and stdout, like OpenSSH ProxyCommand.
+    ///
+    /// `host` and `port` are the server the command reaches, checked against known_hosts like
+    /// with `connect`. The process is killed once the session and its clones are dropped. If it
+    /// exits during the handshake, the handshake fails with a `ProxyCommandError` carrying its
+    /// stderr.
+    pub async fn connect_with_proxy_command(
+        command: Command,
+        host: &str,
+        port: u16,
+        configuration: impl Into<Option<SessionConfiguration>>,
+    ) -> Result<Self, Error> {
+        let (stream, child) = crate::proxy_command::spawn(command)?;
+        stream.set_nonblocking(true)?;
+        let stream = crate::TokioUnixStream::from_std(stream)?;
+
+        let mut session = Self::new(stream, configuration)?;
+        session.set_remote_host(host, port);
+        session.proxy_command = Some(Arc::new(child));
+        Ok(session)
+    }
+}
+
+#[cfg(all(unix, feature = "async-io"))]
+impl AsyncSession<crate::AsyncIoSshConfigStream> {
+    /// Connects to `resolved.host_name` through `resolved.proxy_command`, or through the hosts of
+    /// `resolved.proxy_jump_configs` in turn if there are any. Jump hosts are authenticated with
+    /// `userauth_with_ssh_config`, the returned session still has to be.
+    pub async fn connect_with_ssh_config(resolved: &ResolvedSshConfig) -> Result<Self, Error> {
+        if let Some(proxy_command) = resolved.proxy_command.as_deref() {
+            let session = AsyncSession::<crate::AsyncIoUnixStream>::connect_with_proxy_command(
+                crate::proxy_command::shell_command(proxy_command),
+                &resolved.host_name,
+                resolved.port,
+                resolved.session_configuration(),
+            )
+            .await?;
+            return Ok(session.map_stream(MaybeProxied::Proxied));
+        }
+
+        let (first, rest) = match resolved.proxy_jump_configs.split_first() {
+            Some(x) => x,
+            None => {
+                let session = AsyncSession::<crate::AsyncIoTcpStream>::connect_host_port(
+                    &resolved.host_name,
+                    resolved.port,
+                    resolved.session_configuration(),
+                )
+                .await?;
+                return Ok(session.map_stream(MaybeProxied::Direct));
+            }
+        };
+
+        let mut bastion = AsyncSession::<crate::AsyncIoTcpStream>::connect_host_port(
+            &first.host_name,
+            first.port,
+            first.session_configuration(),
+        )
+        .await?;
+        bastion.handshake().await?;
+        bastion.userauth_with_ssh_config(first).await?;
+
+        let jump_hosts = rest
+            .iter()
+            .map(|jump| JumpHost {
+                host: jump.host_name.to_owned(),
+                port: jump.port,
+                configuration: Some(jump.session_configuration()),
+            })
+            .collect::<Vec<_>>();
+        let session = AsyncSession::<crate::AsyncIoUnixStream>::connect_via_chain(
+            &bastion,
+            &jump_hosts,
+            &resolved.host_name,
+            resolved.port,
+            resolved.session_configuration(),
+            |i, session| async move {
+                session.userauth_with_ssh_config(&rest[i]).await?;
+                Ok(session)
+            },
+        )
+        .await?;
+        Ok(session.map_stream(MaybeProxied::Proxied))
+    }
+}
+
+#[cfg(all(unix, feature = "tokio"))]
+impl AsyncSession<crate::TokioSshConfigStream> {
+    /// Connects to `resolved.host_name` through `resolved.proxy_command`, or through the hosts of
+    /// `resolved.proxy_jump_configs` in turn if there are any. Jump hosts are authenticated with
+    /// `userauth_with_ssh_config`, the returned session still has to be.
+    pub async fn connect_with_ssh_config(resolved: &ResolvedSshConfig) -> Result<Self, Error> {
+        if let Some(proxy_command) = resolved.proxy_command.as_deref() {
+            let session = AsyncSession::<crate::TokioUnixStream>::connect_with_proxy_command(
+                crate::proxy_command::shell_command(proxy_command),
+                &resolved.host_name,
+                resolved.port,
+                resolved.session_configuration(),
+            )
+            .await?;
+            return Ok(session.map_stream(MaybeProxied::Proxied));
+        }
+
+        let (first, rest) = match resolved.proxy_jump_configs.split_first() {
+            Some(x) => x,
+            None => {
+                let session = AsyncSession::<crate::TokioTcpStream>::connect_host_port(
+                    &resolved.host_name,
+                    resolved.port,
+                    resolved.session_configuration(),
+                )
+                .await?;
+                return Ok(session.map_stream(MaybeProxied::Direct));
+            }
+        };
+
+        let mut bastion = AsyncSession::<crate::TokioTcpStream>::connect_host_port(
+            &first.host_name,
+            first.port,
+            first.session_configuration(),
+        )
+        .await?;
+        bastion.handshake().await?;
+        bastion.userauth_with_ssh_config(first).await?;
+
+        let jump_hosts = rest
+            .iter()
+            .map(|jump| JumpHost {
+                host: jump.host_name.to_owned(),
+                port: jump.port,
+                configuration: Some(jump.session_configuration()),
+            })
+            .collect::<Vec<_>>();
+        let session = AsyncSession::<crate::TokioUnixStream>::connect_via_chain(
+            &bastion,
+            &jump_hosts,
+            &resolved.host_name,
+            resolved.port,
+            resolved.session_configuration(),
+            |i, session| async move {
+                session.userauth_with_ssh_config(&rest[i]).await?;
+                Ok(session)
+            },
+        )
+        .await?;
+        Ok(session.map_stream(MaybeProxied::Proxied))
+    }
+}
+
+/// A bastion after the first one, see `AsyncSession::connect_via_chain`.
+#[derive(Debug, Clone)]
+pub struct JumpHost {
+    pub host: String,
+    pub port: u16,
+    pub configuration: Option<SessionConfiguration>,
+}
+
+impl JumpHost {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            configuration: None,
+        }
+    }
+}
+
+#[cfg(all(windows, any(feature = "tokio", feature = "async-io")))]
+fn check_ssh_config_proxies(resolved: &ResolvedSshConfig) -> Result<(), Error> {
+    if !resolved.proxy_jump.is_empty() {
+        return Err(Error::Other(
+            "ProxyJump is not supported by connect_with_ssh_config".into(),
+        ));
+    }
+    if resolved.proxy_command.is_some() {
+        return Err(Error::Other(
+            "ProxyCommand is not supported by connect_with_ssh_config, use connect_with_proxy_command".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+fn resolve_error(host: &str, err: std::io::Error) -> std::io::Error {
+    std::io::Error::new(err.kind(), format!("failed to resolve {host}: {err}"))
 }
 
 impl<S> AsyncSession<S> {
@@ -140,9 +711,53 @@ impl<S> AsyncSession<S> {
         self.inner.timeout()
     }
 
+    /// See `SessionConfiguration::set_timeout`.
+    pub fn set_timeout(&self, timeout_ms: u32) {
+        self.inner.set_timeout(timeout_ms)
+    }
+
     pub fn trace(&self, bitmask: TraceFlags) {
         self.inner.trace(bitmask)
     }
+
+    /// The host and port checked against known_hosts, `connect` sets it to the peer address.
+    pub fn set_remote_host(&mut self, host: impl Into<String>, port: u16) {
+        self.remote_host = Some((host.into(), port));
+    }
+
+    pub fn remote_host(&self) -> Option<(&str, u16)> {
+        self.remote_host
+            .as_ref()
+            .map(|(host, port)| (host.as_str(), *port))
+    }
+
+    /// Set by the keepalive task once the peer stops answering, see `spawn_keepalive_with_tokio`.
+    pub fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::SeqCst)
+    }
+
+    #[cfg(all(unix, any(feature = "tokio", feature = "async-io")))]
+    fn map_stream<T>(self, f: impl FnOnce(Arc<S>) -> T) -> AsyncSession<T> {
+        AsyncSession {
+            inner: self.inner,
+            stream: Arc::new(f(self.stream)),
+            host_key_verification: self.host_key_verification,
+            remote_host: self.remote_host,
+            proxy_command: self.proxy_command,
+            broken: self.broken,
+            keepalive_count_max: self.keepalive_count_max,
+        }
+    }
+
+    pub(crate) fn context(&self, op: &'static str) -> impl FnOnce(Error) -> Error + '_ {
+        move |err| err.with_context(op, self.remote_host())
+    }
+
+    /// What the ProxyCommand process has written to stderr so far.
+    #[cfg(unix)]
+    pub fn proxy_command_stderr(&self) -> Option<String> {
+        self.proxy_command.as_ref().map(|child| child.stderr())
+    }
 }
 
 impl<S> AsyncSession<S>
@@ -150,8 +765,38 @@ where
     S: AsyncSessionStream + Send + Sync + 'static,
 {
     pub async fn handshake(&mut self) -> Result<(), Error> {
+        self.handshake_inner()
+            .await
+            .map_err(self.context("handshake"))
+    }
+
+    async fn handshake_inner(&mut self) -> Result<(), Error> {
         let sess = self.inner.clone();
-        self.stream.rw_with(|| self.inner.handshake(), &sess).await
+        let ret = self.stream.rw_with(|| self.inner.handshake(), &sess).await;
+        if let Err(err) = ret {
+            #[cfg(unix)]
+            if let Some(child) = self.proxy_command.as_ref() {
+                if let Some(proxy_err) = child.exit_error().await {
+                    return Err(Error::Other(Box::new(proxy_err)));
+                }
+            }
+            return Err(err);
+        }
+
+        if let Some(host_key_verification) = self.host_key_verification.as_ref() {
+            if matches!(host_key_verification.policy, HostKeyPolicy::Insecure) {
+                return Ok(());
+            }
+
+            let (host, port) = self.remote_host().ok_or_else(|| {
+                Error::Other("remote host is required by the host key policy".into())
+            })?;
+            host_key_verification
+                .verify(&self.inner, host, port)
+                .await?;
+        }
+
+        Ok(())
     }
 
     pub async fn userauth_password(&self, username: &str, password: &str) -> Result<(), Error> {
@@ -161,6 +806,7 @@ where
                 &self.inner,
             )
             .await
+            .map_err(self.context("userauth_password"))
     }
 
     #[allow(unknown_lints)]
@@ -176,16 +822,27 @@ where
                 &self.inner,
             )
             .await
+            .map_err(self.context("userauth_keyboard_interactive"))
     }
 
     pub async fn userauth_agent(&self, username: &str) -> Result<(), Error> {
+        self.userauth_agent_inner(username)
+            .await
+            .map_err(self.context("userauth_agent"))
+    }
+
+    async fn userauth_agent_inner(&self, username: &str) -> Result<(), Error> {
         let mut agent = self.agent()?;
         agent.connect().await?;
         agent.list_identities().await?;
         let identities = agent.identities()?;
         let identity = match identities.first() {
             Some(identity) => identity,
-            None => return Err(Error::Other("no identities found in the ssh agent".into())),
+            None => {
+                return Err(Error::AgentUnavailable(
+                    "no identities found in the ssh agent".into(),
+                ))
+            }
         };
         agent.userauth(username, identity).await
     }
@@ -206,6 +863,7 @@ where
                 &self.inner,
             )
             .await
+            .map_err(self.context("userauth_pubkey_file"))
     }
 
     #[cfg(any(unix, feature = "vendored-openssl", feature = "openssl-on-win32"))]
@@ -229,6 +887,7 @@ where
                 &self.inner,
             )
             .await
+            .map_err(self.context("userauth_pubkey_memory"))
     }
 
     pub async fn userauth_hostbased_file(
@@ -255,22 +914,25 @@ where
                 &self.inner,
             )
             .await
+            .map_err(self.context("userauth_hostbased_file"))
     }
 
     pub fn authenticated(&self) -> bool {
         self.inner.authenticated()
     }
 
-    pub async fn auth_methods<'a>(&'a self, username: &'a str) -> Result<&str, Error> {
+    pub async fn auth_methods<'a>(&'a self, username: &'a str) -> Result<&'a str, Error> {
         self.stream
             .rw_with(|| self.inner.auth_methods(username), &self.inner)
             .await
+            .map_err(self.context("auth_methods"))
     }
 
     pub async fn method_pref(&self, method_type: MethodType, prefs: &str) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.method_pref(method_type, prefs), &self.inner)
             .await
+            .map_err(self.context("method_pref"))
     }
 
     pub fn methods(&self, method_type: MethodType) -> Option<&str> {
@@ -284,6 +946,7 @@ where
         self.stream
             .rw_with(|| self.inner.supported_algs(method_type), &self.inner)
             .await
+            .map_err(self.context("supported_algs"))
     }
 
     pub fn agent(&self) -> Result<AsyncAgent<S>, Error> {
@@ -304,7 +967,25 @@ where
         let channel = self
             .stream
             .rw_with(|| self.inner.channel_session(), &self.inner)
-            .await?;
+            .await
+            .map_err(self.context("channel_session"))?;
+
+        Ok(AsyncChannel::from_parts(
+            channel,
+            self.inner.clone(),
+            self.stream.clone(),
+        ))
+    }
+
+    pub async fn channel_session_with_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<AsyncChannel<S>, Error> {
+        let channel = self
+            .stream
+            .rw_with_timeout(|| self.inner.channel_session(), &self.inner, Some(timeout))
+            .await
+            .map_err(self.context("channel_session"))?;
 
         Ok(AsyncChannel::from_parts(
             channel,
@@ -325,7 +1006,8 @@ where
                 || self.inner.channel_direct_tcpip(host, port, src),
                 &self.inner,
             )
-            .await?;
+            .await
+            .map_err(self.context("channel_direct_tcpip"))?;
 
         Ok(AsyncChannel::from_parts(
             channel,
@@ -349,7 +1031,8 @@ where
                 },
                 &self.inner,
             )
-            .await?;
+            .await
+            .map_err(self.context("channel_forward_listen"))?;
 
         Ok((
             AsyncListener::from_parts(listener, self.inner.clone(), self.stream.clone()),
@@ -361,7 +1044,8 @@ where
         let (channel, scp_file_stat) = self
             .stream
             .rw_with(|| self.inner.scp_recv(path), &self.inner)
-            .await?;
+            .await
+            .map_err(self.context("scp_recv"))?;
 
         Ok((
             AsyncChannel::from_parts(channel, self.inner.clone(), self.stream.clone()),
@@ -382,7 +1066,8 @@ where
                 || self.inner.scp_send(remote_path, mode, size, times),
                 &self.inner,
             )
-            .await?;
+            .await
+            .map_err(self.context("scp_send"))?;
 
         Ok(AsyncChannel::from_parts(
             channel,
@@ -395,12 +1080,15 @@ where
         let sftp = self
             .stream
             .rw_with(|| self.inner.sftp(), &self.inner)
-            .await?;
+            .await
+            .map_err(self.context("sftp"))?;
 
         Ok(AsyncSftp::from_parts(
             sftp,
             self.inner.clone(),
             self.stream.clone(),
+            self.broken.clone(),
+            self.remote_host.clone(),
         ))
     }
 
@@ -420,7 +1108,8 @@ where
                 },
                 &self.inner,
             )
-            .await?;
+            .await
+            .map_err(self.context("channel_open"))?;
 
         Ok(AsyncChannel::from_parts(
             channel,
@@ -441,6 +1130,7 @@ where
         self.stream
             .rw_with(|| self.inner.keepalive_send(), &self.inner)
             .await
+            .map_err(self.context("keepalive_send"))
     }
 
     pub async fn disconnect(
@@ -455,6 +1145,7 @@ where
                 &self.inner,
             )
             .await
+            .map_err(self.context("disconnect"))
     }
 
     pub fn block_directions(&self) -> BlockDirections {
@@ -462,165 +1153,184 @@ where
     }
 }
 
-#[cfg(feature = "tokio")]
+#[cfg(any(feature = "tokio", feature = "async-io"))]
 impl<S> AsyncSession<S>
 where
     S: AsyncSessionStream + Send + Sync + 'static,
 {
+    /// Like `ssh -R`, asks the server to listen on `remote_port` and forwards each connection to
+    /// `local`. Runs until the handle is dropped, on the current tokio runtime, otherwise on threads
+    /// of its own with async-io.
     pub async fn remote_port_forwarding(
         &self,
         remote_port: u16,
         host: Option<&str>,
         queue_maxsize: Option<u32>,
         local: crate::util::ConnectInfo,
-    ) -> Result<(), Error> {
-        use std::io::Error as IoError;
-
-        use futures_util::{select, FutureExt as _};
-        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
-
-        #[cfg(unix)]
-        use crate::TokioUnixStream;
-        use crate::{util::ConnectInfo, TokioTcpStream};
-
-        match local {
-            ConnectInfo::Tcp(addr) => {
-                let (mut listener, _remote_port) = self
-                    .channel_forward_listen(remote_port, host, queue_maxsize)
-                    .await?;
-
-                // TODO, tokio::io::copy_bidirectional not working
-
-                loop {
-                    match listener.accept().await {
-                        Ok(mut channel) => {
-                            let join_handle: tokio::task::JoinHandle<Result<(), IoError>> =
-                                tokio::task::spawn(async move {
-                                    let mut stream = TokioTcpStream::connect(addr).await?;
-
-                                    let mut buf_channel = vec![0; 2048];
-                                    let mut buf_stream = vec![0; 2048];
-
-                                    loop {
-                                        select! {
-                                            ret_channel_read = futures_util::AsyncReadExt::read(&mut channel, &mut buf_channel).fuse() => match ret_channel_read {
-                                                Ok(0)  => {
-                                                    break
-                                                },
-                                                Ok(n) => {
-                                                    #[allow(clippy::map_identity)]
-                                                    stream.write(&buf_channel[..n]).await.map(|_| ()).map_err(|err| {
-                                                        // TODO, log
-                                                        err
-                                                    })?
-                                                },
-                                                Err(err) =>  {
-                                                    return Err(err);
-                                                }
-                                            },
-                                            ret_stream_read = stream.read(&mut buf_stream).fuse() => match ret_stream_read {
-                                                Ok(0)  => {
-                                                    break
-                                                },
-                                                Ok(n) => {
-                                                    #[allow(clippy::map_identity)]
-                                                    futures_util::AsyncWriteExt::write(&mut channel,&buf_stream[..n]).await.map(|_| ()).map_err(|err| {
-                                                        // TODO, log
-                                                        err
-                                                    })?
-                                                },
-                                                Err(err) => {
-                                                    return Err(err);
-                                                }
-                                            },
-                                        }
-                                    }
-
-                                    Result::<_, IoError>::Ok(())
-                                });
-                            match join_handle.await {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    eprintln!("join_handle failed, err:{err:?}");
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            eprintln!("listener.accept failed, err:{err:?}");
-                        }
-                    }
-                }
-            }
-            #[cfg(unix)]
-            ConnectInfo::Unix(path) => {
-                let (mut listener, _remote_port) = self
-                    .channel_forward_listen(remote_port, host, queue_maxsize)
-                    .await?;
-
-                // TODO, tokio::io::copy_bidirectional not working
-
-                loop {
-                    match listener.accept().await {
-                        Ok(mut channel) => {
-                            let path = path.clone();
-                            let join_handle: tokio::task::JoinHandle<Result<(), IoError>> =
-                                tokio::task::spawn(async move {
-                                    let mut stream = TokioUnixStream::connect(path).await?;
-
-                                    let mut buf_channel = vec![0; 2048];
-                                    let mut buf_stream = vec![0; 2048];
-
-                                    loop {
-                                        select! {
-                                            ret_channel_read = futures_util::AsyncReadExt::read(&mut channel, &mut buf_channel).fuse() => match ret_channel_read {
-                                                Ok(0)  => {
-                                                    break
-                                                },
-                                                Ok(n) => {
-                                                    #[allow(clippy::map_identity)]
-                                                    stream.write(&buf_channel[..n]).await.map(|_| ()).map_err(|err| {
-                                                        // TODO, log
-                                                        err
-                                                    })?
-                                                },
-                                                Err(err) =>  {
-                                                    return Err(err);
-                                                }
-                                            },
-                                            ret_stream_read = stream.read(&mut buf_stream).fuse() => match ret_stream_read {
-                                                Ok(0)  => {
-                                                    break
-                                                },
-                                                Ok(n) => {
-                                                    #[allow(clippy::map_identity)]
-                                                    futures_util::AsyncWriteExt::write(&mut channel,&buf_stream[..n]).await.map(|_| ()).map_err(|err| {
-                                                        // TODO, log
-                                                        err
-                                                    })?
-                                                },
-                                                Err(err) => {
-                                                    return Err(err);
-                                                }
-                                            },
-                                        }
-                                    }
-
-                                    Result::<_, IoError>::Ok(())
-                                });
-                            match join_handle.await {
-                                Ok(_) => {}
-                                Err(err) => {
-                                    eprintln!("join_handle failed, err:{err:?}");
-                                }
-                            }
-                        }
-                        Err(err) => {
-                            eprintln!("listener.accept failed, err:{err:?}");
-                        }
+        configuration: impl Into<Option<crate::forward::RemoteForwardConfiguration>>,
+    ) -> Result<crate::forward::RemoteForward, Error> {
+        let (listener, remote_port) = self
+            .channel_forward_listen(remote_port, host, queue_maxsize)
+            .await?;
+
+        crate::forward::RemoteForward::spawn(
+            listener,
+            remote_port,
+            local,
+            configuration.into().unwrap_or_default(),
+        )
+        .map_err(self.context("remote_port_forwarding"))
+    }
+
+    /// Like `ssh -L`, listens on `bind` and forwards each connection to `remote_host:remote_port`
+    /// as seen from the server. Runs like `remote_port_forwarding`.
+    pub async fn local_port_forwarding(
+        &self,
+        bind: crate::util::ConnectInfo,
+        remote_host: &str,
+        remote_port: u16,
+        configuration: impl Into<Option<crate::forward::LocalForwardConfiguration>>,
+    ) -> Result<crate::forward::LocalForward, Error> {
+        let forwarder = crate::forward::Forwarder::local(
+            self.inner.clone(),
+            self.stream.clone(),
+            remote_host.into(),
+            remote_port,
+            configuration.into().unwrap_or_default(),
+        );
+        crate::forward::LocalForward::bind(bind, forwarder)
+            .await
+            .map_err(self.context("local_port_forwarding"))
+    }
+
+    /// Like `ssh -D`, a SOCKS5, SOCKS4a and HTTP CONNECT proxy on `bind` that opens a channel to
+    /// wherever each client asks, resolved by the server. Runs like `remote_port_forwarding`.
+    pub async fn dynamic_port_forwarding(
+        &self,
+        bind: crate::util::ConnectInfo,
+        configuration: impl Into<Option<crate::forward::DynamicForwardConfiguration>>,
+    ) -> Result<crate::forward::LocalForward, Error> {
+        let forwarder = crate::forward::Forwarder::dynamic(
+            self.inner.clone(),
+            self.stream.clone(),
+            configuration.into().unwrap_or_default(),
+        );
+        crate::forward::LocalForward::bind(bind, forwarder)
+            .await
+            .map_err(self.context("dynamic_port_forwarding"))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S> AsyncSession<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    /// Sends keepalives at the interval of `SessionConfiguration::set_keepalive`, which needs
+    /// `want_reply`. After `count_max` unanswered ones in a row the session is marked broken and
+    /// pending operations fail. `None` takes `SessionConfiguration::set_keepalive_count_max`.
+    pub fn spawn_keepalive_with_tokio(
+        &self,
+        count_max: impl Into<Option<u32>>,
+    ) -> Result<crate::keepalive::KeepaliveHandle, Error> {
+        let count_max = count_max.into().unwrap_or(self.keepalive_count_max);
+        let handle = tokio::runtime::Handle::try_current().map_err(std::io::Error::other)?;
+        crate::keepalive::check_configured(&self.inner)?;
+
+        let (keepalive_handle, stop) = crate::keepalive::KeepaliveHandle::new();
+        handle.spawn(crate::keepalive::run(
+            self.inner.clone(),
+            self.stream.clone(),
+            self.broken.clone(),
+            stop,
+            count_max,
+            tokio::time::sleep,
+        ));
+
+        Ok(keepalive_handle)
+    }
+}
+
+#[cfg(feature = "async-io")]
+impl<S> AsyncSession<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    /// See `spawn_keepalive_with_tokio`, runs on the executor shared by async-io tasks of this
+    /// crate.
+    pub fn spawn_keepalive_with_async_io(
+        &self,
+        count_max: impl Into<Option<u32>>,
+    ) -> Result<crate::keepalive::KeepaliveHandle, Error> {
+        let count_max = count_max.into().unwrap_or(self.keepalive_count_max);
+        crate::keepalive::check_configured(&self.inner)?;
+
+        let (keepalive_handle, stop) = crate::keepalive::KeepaliveHandle::new();
+        let fut = crate::keepalive::run(
+            self.inner.clone(),
+            self.stream.clone(),
+            self.broken.clone(),
+            stop,
+            count_max,
+            |dur| crate::runtime::Runtime::AsyncIo.sleep(dur),
+        );
+        crate::runtime::Runtime::AsyncIo.spawn(fut)?;
+
+        Ok(keepalive_handle)
+    }
+}
+
+/// How long `exec_output` waits for the server to confirm closing a timed out command.
+const EXEC_KILL_GRACE: Duration = Duration::from_secs(1);
+
+impl<S> AsyncSession<S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    /// Runs `command` to completion, draining stdout and stderr together so neither can stall the
+    /// other. Like `RemoteCommand::from_command_line(command).output(..)`, with the limit and
+    /// timeout of `configuration`. On timeout the channel is closed, see `RemoteChild::kill` for
+    /// what the command sees. The close waits at most a second for the server to confirm it, the
+    /// channel is dropped unconfirmed after that.
+    pub async fn exec_output(
+        &self,
+        command: &str,
+        configuration: impl Into<Option<ExecConfiguration>>,
+    ) -> Result<ExecOutput, Error> {
+        let configuration = configuration.into().unwrap_or_default();
+
+        let mut child = None;
+        let ret = {
+            let fut = async {
+                let child = child.insert(
+                    RemoteCommand::from_command_line(command)
+                        .spawn(self)
+                        .await?,
+                );
+                child
+                    .wait_with_output_limit(configuration.output_limit())
+                    .await
+            };
+            match configuration.timeout() {
+                Some(dur) => {
+                    let timer = self.stream.sleep(dur);
+                    pin_mut!(fut);
+                    match future::select(fut, timer).await {
+                        Either::Left((ret, _)) => ret,
+                        Either::Right(_) => Err(Error::Timeout(dur)),
                     }
                 }
+                None => fut.await,
             }
+        };
+        if let (Err(Error::Timeout(_)), Some(child)) = (&ret, child.as_mut()) {
+            // Closing waits for the server to confirm, which a stalled server never does
+            let kill = child.kill();
+            let timer = self.stream.sleep(EXEC_KILL_GRACE);
+            pin_mut!(kill);
+            let _ = future::select(kill, timer).await;
         }
+        ret.map_err(self.context("exec_output"))
     }
 }
 
@@ -656,7 +1366,10 @@ where
         let identities = agent.identities()?;
 
         if identities.is_empty() {
-            return Err(Error::Other("no identities found in the ssh agent".into()));
+            return Err(
+                Error::AgentUnavailable("no identities found in the ssh agent".into())
+                    .with_context("userauth_agent", self.remote_host()),
+            );
         }
 
         let identities = cb(identities);
@@ -674,7 +1387,57 @@ where
             }
         }
 
-        Err(Error::Other("all identities cannot authenticated".into()))
+        Err(Error::AuthenticationFailed(format!(
+            "all agent identities were rejected for {username}"
+        ))
+        .with_context("userauth_agent", self.remote_host()))
+    }
+
+    /// Tries the agent (unless `IdentitiesOnly`), then each `IdentityFile`.
+    pub async fn userauth_with_ssh_config(
+        &self,
+        resolved: &ResolvedSshConfig,
+    ) -> Result<(), Error> {
+        let username = resolved.user.to_owned().unwrap_or_else(local_user);
+
+        if !resolved.identities_only {
+            match self.userauth_agent_with_try_next(&username).await {
+                Ok(_) if self.authenticated() => return Ok(()),
+                _ => {}
+            }
+        }
+
+        let identity_files = if resolved.identity_files.is_empty() {
+            home_dir()
+                .map(|home| {
+                    ["id_ed25519", "id_ecdsa", "id_rsa"]
+                        .iter()
+                        .map(|name| home.join(".ssh").join(name))
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            resolved.identity_files.to_owned()
+        };
+
+        for identity_file in identity_files {
+            if !identity_file.exists() {
+                continue;
+            }
+
+            match self
+                .userauth_pubkey_file(&username, None, &identity_file, None)
+                .await
+            {
+                Ok(_) if self.authenticated() => return Ok(()),
+                _ => continue,
+            }
+        }
+
+        Err(
+            Error::AuthenticationFailed(format!("all identities were rejected for {username}"))
+                .with_context("userauth_with_ssh_config", self.remote_host()),
+        )
     }
 }
 
@@ -687,7 +1450,13 @@ pub struct SessionConfiguration {
     allow_sigpipe: Option<bool>,
     compress: Option<bool>,
     timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
     keepalive: Option<SessionKeepaliveConfiguration>,
+    keepalive_count_max: Option<u32>,
+    method_prefs: Vec<SessionMethodPrefConfiguration>,
+    host_key_policy: Option<HostKeyPolicy>,
+    known_hosts_files: Option<Vec<PathBuf>>,
+    hash_known_hosts: Option<bool>,
 }
 impl SessionConfiguration {
     pub fn new() -> Self {
@@ -706,16 +1475,72 @@ impl SessionConfiguration {
         self.compress = Some(compress);
     }
 
+    /// Default timeout of every operation, including reads and writes that stall, 0 disables it.
+    ///
+    /// Timed out operations fail with `Error::Timeout`, or `IoErrorKind::TimedOut` through
+    /// `AsyncRead`/`AsyncWrite`.
     pub fn set_timeout(&mut self, timeout_ms: u32) {
         self.timeout = Some(Duration::from_millis(timeout_ms as u64));
     }
 
+    /// Bounds the TCP connect of `connect`, `connect_host` and `connect_with_ssh_config`.
+    pub fn set_connect_timeout(&mut self, dur: Duration) {
+        self.connect_timeout = Some(dur);
+    }
+
     pub fn set_keepalive(&mut self, want_reply: bool, interval: u32) {
         self.keepalive = Some(SessionKeepaliveConfiguration {
             want_reply,
             interval,
         });
     }
+
+    /// Unanswered keepalives in a row before the keepalive task gives up on the peer, 3 by
+    /// default, see `AsyncSession::spawn_keepalive_with_tokio`.
+    pub fn set_keepalive_count_max(&mut self, count_max: u32) {
+        self.keepalive_count_max = Some(count_max);
+    }
+
+    /// Applied before the handshake, see `AsyncSession::method_pref`.
+    pub fn set_method_pref(&mut self, method_type: MethodType, prefs: &str) {
+        self.method_prefs
+            .retain(|x| x.method_type as i32 != method_type as i32);
+        self.method_prefs.push(SessionMethodPrefConfiguration {
+            method_type,
+            prefs: prefs.to_owned(),
+        });
+    }
+
+    pub fn set_host_key_policy(&mut self, policy: HostKeyPolicy) {
+        self.host_key_policy = Some(policy);
+    }
+
+    /// Defaults to `~/.ssh/known_hosts` and `/etc/ssh/ssh_known_hosts`, new hosts are appended to the first one.
+    pub fn set_known_hosts_files<I, P>(&mut self, files: I)
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.known_hosts_files = Some(files.into_iter().map(Into::into).collect());
+    }
+
+    /// Write hashed hostnames (like `HashKnownHosts yes`) when appending new hosts.
+    pub fn set_hash_known_hosts(&mut self, hash_known_hosts: bool) {
+        self.hash_known_hosts = Some(hash_known_hosts);
+    }
+
+    fn host_key_verification(&self) -> Option<HostKeyVerification> {
+        self.host_key_policy
+            .as_ref()
+            .map(|policy| HostKeyVerification {
+                policy: policy.to_owned(),
+                known_hosts_files: self
+                    .known_hosts_files
+                    .to_owned()
+                    .unwrap_or_else(HostKeyVerification::default_known_hosts_files),
+                hash_known_hosts: self.hash_known_hosts.unwrap_or(false),
+            })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -724,6 +1549,21 @@ struct SessionKeepaliveConfiguration {
     interval: u32,
 }
 
+#[derive(Clone)]
+struct SessionMethodPrefConfiguration {
+    method_type: MethodType,
+    prefs: String,
+}
+
+impl core::fmt::Debug for SessionMethodPrefConfiguration {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SessionMethodPrefConfiguration")
+            .field("method_type", &(self.method_type as i32))
+            .field("prefs", &self.prefs)
+            .finish()
+    }
+}
+
 pub(crate) fn get_session(
     configuration: impl Into<Option<SessionConfiguration>>,
 ) -> Result<Session, Error> {
@@ -746,6 +1586,9 @@ pub(crate) fn get_session(
         if let Some(keepalive) = configuration.keepalive {
             session.set_keepalive(keepalive.want_reply, keepalive.interval);
         }
+        for method_pref in configuration.method_prefs {
+            session.method_pref(method_pref.method_type, method_pref.prefs.as_ref())?;
+        }
     }
 
     Ok(session)
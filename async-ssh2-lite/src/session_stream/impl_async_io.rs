@@ -1,127 +1,66 @@
-use core::{
-    task::{Context, Poll},
-    time::Duration,
-};
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use core::time::Duration;
+use std::{io::Error as IoError, net::Shutdown, sync::Arc};
 
 use async_io::{Async, Timer};
 use async_trait::async_trait;
-use futures_util::{future, pin_mut, ready};
-use ssh2::{BlockDirections, Error as Ssh2Error, Session};
+use futures_util::future;
+use socket2::SockRef;
+use ssh2::BlockDirections;
 
-use super::{AsyncSessionStream, BlockDirectionsExt as _};
-use crate::{error::Error, util::ssh2_error_is_would_block};
+use super::{AsyncSessionStream, Readiness};
 
 //
 #[async_trait]
 impl<S> AsyncSessionStream for Async<S>
 where
-    S: Send + Sync,
+    S: Send + Sync + 'static,
+    for<'a> SockRef<'a>: From<&'a S>,
 {
-    async fn x_with<R>(
-        &self,
-        mut op: impl FnMut() -> Result<R, Ssh2Error> + Send,
-        sess: &Session,
-        expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Result<R, Error> {
-        loop {
-            match op() {
-                Ok(x) => return Ok(x),
-                Err(err) => {
-                    if !ssh2_error_is_would_block(&err) {
-                        return Err(err.into());
-                    }
-                }
-            }
-
-            match sess.block_directions() {
-                BlockDirections::None => continue,
-                BlockDirections::Inbound => {
-                    assert!(expected_block_directions.is_readable());
-
-                    self.readable().await?
-                }
-                BlockDirections::Outbound => {
-                    assert!(expected_block_directions.is_writable());
-
-                    self.writable().await?
-                }
-                BlockDirections::Both => {
-                    assert!(expected_block_directions.is_readable());
-                    assert!(expected_block_directions.is_writable());
-
-                    let (ret, _) = future::select(self.readable(), self.writable())
-                        .await
-                        .factor_first();
-                    ret?
-                }
-            }
+    fn run_clearing_readiness(&self, _directions: &BlockDirections, op: &mut dyn FnMut() -> bool) {
+        // async-io only reports readiness that arrives after it is asked for
+        op();
+    }
 
-            if let Some(dur) = sleep_dur {
-                sleep_async_fn(dur).await;
+    async fn readiness(&self, directions: &BlockDirections) -> Result<(), IoError> {
+        match directions {
+            BlockDirections::None => future::pending().await,
+            BlockDirections::Inbound => self.readable().await,
+            BlockDirections::Outbound => self.writable().await,
+            BlockDirections::Both => {
+                future::select(self.readable(), self.writable())
+                    .await
+                    .factor_first()
+                    .0
             }
         }
     }
 
-    fn poll_x_with<R>(
-        &self,
-        cx: &mut Context,
-        mut op: impl FnMut() -> Result<R, IoError> + Send,
-        sess: &Session,
-        expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Poll<Result<R, IoError>> {
-        match op() {
-            Err(err) if err.kind() == IoErrorKind::WouldBlock => {}
-            ret => return Poll::Ready(ret),
-        }
-
-        match sess.block_directions() {
-            BlockDirections::None => return Poll::Pending,
-            BlockDirections::Inbound => {
-                assert!(expected_block_directions.is_readable());
-
-                ready!(self.poll_readable(cx))?;
-            }
-            BlockDirections::Outbound => {
-                assert!(expected_block_directions.is_writable());
-
-                ready!(self.poll_writable(cx))?;
-            }
+    fn readiness_owned(self: &Arc<Self>, directions: &BlockDirections) -> Readiness {
+        match directions {
+            BlockDirections::None => Box::pin(future::pending()),
+            BlockDirections::Inbound => Box::pin(self.clone().readable_owned()),
+            BlockDirections::Outbound => Box::pin(self.clone().writable_owned()),
             BlockDirections::Both => {
-                assert!(expected_block_directions.is_readable());
-                assert!(expected_block_directions.is_writable());
-
-                // Must first poll_writable, because session__scp_send_and_scp_recv.rs
-                ready!(self.poll_writable(cx))?;
-                ready!(self.poll_readable(cx))?;
+                let readable = self.clone().readable_owned();
+                let writable = self.clone().writable_owned();
+                Box::pin(async move { future::select(readable, writable).await.factor_first().0 })
             }
         }
+    }
 
-        if let Some(dur) = sleep_dur {
-            let waker = cx.waker().clone();
-            // TODO, maybe wrong
-            let timer = sleep(dur);
-            pin_mut!(timer);
-            ready!(future::Future::poll(timer, cx));
-            waker.wake();
-        } else {
-            let waker = cx.waker().clone();
-            waker.wake();
-        }
+    async fn wait_readable(&self) -> Result<(), IoError> {
+        self.readable().await
+    }
 
-        Poll::Pending
+    fn peek_len(&self) -> Result<Option<usize>, IoError> {
+        super::peek_len(SockRef::from(self.get_ref()))
     }
-}
 
-//
-//
-//
-async fn sleep_async_fn(dur: Duration) {
-    sleep(dur).await;
-}
+    fn shutdown_socket(&self) -> Result<(), IoError> {
+        SockRef::from(self.get_ref()).shutdown(Shutdown::Both)
+    }
 
-async fn sleep(dur: Duration) -> Timer {
-    Timer::after(dur)
+    async fn sleep(&self, dur: Duration) {
+        Timer::after(dur).await;
+    }
 }
@@ -1,218 +1,257 @@
-use core::{
-    task::{Context, Poll},
-    time::Duration,
+use core::time::Duration;
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    net::Shutdown,
+    sync::Arc,
 };
-use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 
 use async_trait::async_trait;
-use futures_util::ready;
-use ssh2::{BlockDirections, Error as Ssh2Error, Session};
-use tokio::net::TcpStream;
+use socket2::SockRef;
+use ssh2::BlockDirections;
 #[cfg(unix)]
 use tokio::net::UnixStream;
+use tokio::{io::Interest, net::TcpStream};
 
-use super::{AsyncSessionStream, BlockDirectionsExt as _};
-use crate::{error::Error, util::ssh2_error_is_would_block};
+use super::{AsyncSessionStream, Readiness};
 
 //
 #[async_trait]
 impl AsyncSessionStream for TcpStream {
-    async fn x_with<R>(
-        &self,
-        mut op: impl FnMut() -> Result<R, Ssh2Error> + Send,
-        sess: &Session,
-        expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Result<R, Error> {
-        loop {
-            match op() {
-                Ok(x) => return Ok(x),
-                Err(err) => {
-                    if !ssh2_error_is_would_block(&err) {
-                        return Err(err.into());
-                    }
-                }
-            }
-
-            match sess.block_directions() {
-                BlockDirections::None => continue,
-                BlockDirections::Inbound => {
-                    assert!(expected_block_directions.is_readable());
-
-                    self.readable().await?
-                }
-                BlockDirections::Outbound => {
-                    assert!(expected_block_directions.is_writable());
-
-                    self.writable().await?
-                }
-                BlockDirections::Both => {
-                    assert!(expected_block_directions.is_readable());
-                    assert!(expected_block_directions.is_writable());
-
-                    self.ready(tokio::io::Interest::READABLE | tokio::io::Interest::WRITABLE)
-                        .await?;
-                }
-            }
-
-            if let Some(dur) = sleep_dur {
-                sleep_async_fn(dur).await;
-            }
-        }
+    fn run_clearing_readiness(&self, directions: &BlockDirections, op: &mut dyn FnMut() -> bool) {
+        run_clearing(
+            &|interest, f| self.try_io(interest, f),
+            interests(directions),
+            op,
+        );
     }
 
-    fn poll_x_with<R>(
-        &self,
-        cx: &mut Context,
-        mut op: impl FnMut() -> Result<R, IoError> + Send,
-        sess: &Session,
-        expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Poll<Result<R, IoError>> {
-        match op() {
-            Err(err) if err.kind() == IoErrorKind::WouldBlock => {}
-            ret => return Poll::Ready(ret),
+    async fn readiness(&self, directions: &BlockDirections) -> Result<(), IoError> {
+        match interest(directions) {
+            Some(interest) => self.ready(interest).await.map(|_| ()),
+            None => futures_util::future::pending().await,
         }
+    }
 
-        match sess.block_directions() {
-            BlockDirections::None => return Poll::Pending,
-            BlockDirections::Inbound => {
-                assert!(expected_block_directions.is_readable());
-
-                ready!(self.poll_read_ready(cx))?;
-            }
-            BlockDirections::Outbound => {
-                assert!(expected_block_directions.is_writable());
-
-                ready!(self.poll_write_ready(cx))?;
+    fn readiness_owned(self: &Arc<Self>, directions: &BlockDirections) -> Readiness {
+        let stream = self.clone();
+        let interest = interest(directions);
+        Box::pin(async move {
+            match interest {
+                Some(interest) => stream.ready(interest).await.map(|_| ()),
+                None => futures_util::future::pending().await,
             }
-            BlockDirections::Both => {
-                assert!(expected_block_directions.is_readable());
-                assert!(expected_block_directions.is_writable());
+        })
+    }
 
-                ready!(self.poll_write_ready(cx))?;
-                ready!(self.poll_read_ready(cx))?;
+    async fn wait_readable(&self) -> Result<(), IoError> {
+        loop {
+            self.readable().await?;
+            // libssh2 reads the socket itself, tokio only clears stale readiness on WouldBlock
+            match self.try_io(Interest::READABLE, || {
+                match super::peek_len(SockRef::from(self))? {
+                    Some(0) => Err(IoErrorKind::WouldBlock.into()),
+                    _ => Ok(()),
+                }
+            }) {
+                Err(err) if err.kind() == IoErrorKind::WouldBlock => continue,
+                ret => return ret,
             }
         }
+    }
 
-        if let Some(dur) = sleep_dur {
-            let waker = cx.waker().clone();
-            tokio::spawn(async move {
-                sleep_async_fn(dur).await;
-                waker.wake();
-            });
-        } else {
-            let waker = cx.waker().clone();
-            waker.wake();
-        }
+    fn peek_len(&self) -> Result<Option<usize>, IoError> {
+        super::peek_len(SockRef::from(self))
+    }
+
+    fn shutdown_socket(&self) -> Result<(), IoError> {
+        SockRef::from(self).shutdown(Shutdown::Both)
+    }
 
-        Poll::Pending
+    async fn sleep(&self, dur: Duration) {
+        tokio::time::sleep(dur).await
     }
 }
 
 #[cfg(unix)]
 #[async_trait]
 impl AsyncSessionStream for UnixStream {
-    async fn x_with<R>(
-        &self,
-        mut op: impl FnMut() -> Result<R, Ssh2Error> + Send,
-        sess: &Session,
-        expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Result<R, Error> {
-        loop {
-            match op() {
-                Ok(x) => return Ok(x),
-                Err(err) => {
-                    if !ssh2_error_is_would_block(&err) {
-                        return Err(err.into());
-                    }
-                }
-            }
-
-            match sess.block_directions() {
-                BlockDirections::None => continue,
-                BlockDirections::Inbound => {
-                    assert!(expected_block_directions.is_readable());
-
-                    self.readable().await?
-                }
-                BlockDirections::Outbound => {
-                    assert!(expected_block_directions.is_writable());
+    fn run_clearing_readiness(&self, directions: &BlockDirections, op: &mut dyn FnMut() -> bool) {
+        run_clearing(
+            &|interest, f| self.try_io(interest, f),
+            interests(directions),
+            op,
+        );
+    }
 
-                    self.writable().await?
-                }
-                BlockDirections::Both => {
-                    assert!(expected_block_directions.is_readable());
-                    assert!(expected_block_directions.is_writable());
+    async fn readiness(&self, directions: &BlockDirections) -> Result<(), IoError> {
+        match interest(directions) {
+            Some(interest) => self.ready(interest).await.map(|_| ()),
+            None => futures_util::future::pending().await,
+        }
+    }
 
-                    self.ready(tokio::io::Interest::READABLE | tokio::io::Interest::WRITABLE)
-                        .await?;
-                }
+    fn readiness_owned(self: &Arc<Self>, directions: &BlockDirections) -> Readiness {
+        let stream = self.clone();
+        let interest = interest(directions);
+        Box::pin(async move {
+            match interest {
+                Some(interest) => stream.ready(interest).await.map(|_| ()),
+                None => futures_util::future::pending().await,
             }
+        })
+    }
 
-            if let Some(dur) = sleep_dur {
-                sleep_async_fn(dur).await;
+    async fn wait_readable(&self) -> Result<(), IoError> {
+        loop {
+            self.readable().await?;
+            // libssh2 reads the socket itself, tokio only clears stale readiness on WouldBlock
+            match self.try_io(Interest::READABLE, || {
+                match super::peek_len(SockRef::from(self))? {
+                    Some(0) => Err(IoErrorKind::WouldBlock.into()),
+                    _ => Ok(()),
+                }
+            }) {
+                Err(err) if err.kind() == IoErrorKind::WouldBlock => continue,
+                ret => return ret,
             }
         }
     }
 
-    fn poll_x_with<R>(
-        &self,
-        cx: &mut Context,
-        mut op: impl FnMut() -> Result<R, IoError> + Send,
-        sess: &Session,
-        expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Poll<Result<R, IoError>> {
-        match op() {
-            Err(err) if err.kind() == IoErrorKind::WouldBlock => {}
-            ret => return Poll::Ready(ret),
-        }
+    fn peek_len(&self) -> Result<Option<usize>, IoError> {
+        super::peek_len(SockRef::from(self))
+    }
 
-        match sess.block_directions() {
-            BlockDirections::None => return Poll::Pending,
-            BlockDirections::Inbound => {
-                assert!(expected_block_directions.is_readable());
+    fn shutdown_socket(&self) -> Result<(), IoError> {
+        SockRef::from(self).shutdown(Shutdown::Both)
+    }
 
-                ready!(self.poll_read_ready(cx))?;
-            }
-            BlockDirections::Outbound => {
-                assert!(expected_block_directions.is_writable());
+    async fn sleep(&self, dur: Duration) {
+        tokio::time::sleep(dur).await
+    }
+}
 
-                ready!(self.poll_write_ready(cx))?;
-            }
-            BlockDirections::Both => {
-                assert!(expected_block_directions.is_readable());
-                assert!(expected_block_directions.is_writable());
+//
+//
+//
+type TryIo<'a> =
+    dyn Fn(Interest, &mut dyn FnMut() -> Result<(), IoError>) -> Result<(), IoError> + 'a;
 
-                ready!(self.poll_write_ready(cx))?;
-                ready!(self.poll_read_ready(cx))?;
-            }
-        }
+/// Runs `op` within `try_io` of each of `interests`. tokio clears the readiness it saw before
+/// `op` ran when `op` would block, but only one interest at a time.
+fn run_clearing(try_io: &TryIo<'_>, interests: &[Interest], op: &mut dyn FnMut() -> bool) -> bool {
+    let Some((interest, rest)) = interests.split_first() else {
+        return op();
+    };
 
-        if let Some(dur) = sleep_dur {
-            let waker = cx.waker().clone();
-            tokio::spawn(async move {
-                sleep_async_fn(dur).await;
-                waker.wake();
-            });
+    let mut would_block = None;
+    let _ = try_io(*interest, &mut || {
+        let x = run_clearing(try_io, rest, op);
+        would_block = Some(x);
+        if x {
+            Err(IoErrorKind::WouldBlock.into())
         } else {
-            let waker = cx.waker().clone();
-            waker.wake();
+            Ok(())
         }
+    });
+    // Not ready in `interest`, nothing to forget
+    match would_block {
+        Some(x) => x,
+        None => run_clearing(try_io, rest, op),
+    }
+}
 
-        Poll::Pending
+fn interests(directions: &BlockDirections) -> &'static [Interest] {
+    match directions {
+        BlockDirections::None => &[],
+        BlockDirections::Inbound => &[Interest::READABLE],
+        BlockDirections::Outbound => &[Interest::WRITABLE],
+        BlockDirections::Both => &[Interest::READABLE, Interest::WRITABLE],
     }
 }
 
-//
-//
-//
-async fn sleep_async_fn(dur: Duration) {
-    sleep(dur).await;
+fn interest(directions: &BlockDirections) -> Option<Interest> {
+    match directions {
+        BlockDirections::None => None,
+        BlockDirections::Inbound => Some(Interest::READABLE),
+        BlockDirections::Outbound => Some(Interest::WRITABLE),
+        BlockDirections::Both => Some(Interest::READABLE | Interest::WRITABLE),
+    }
 }
 
-fn sleep(dur: Duration) -> tokio::time::Sleep {
-    tokio::time::sleep(tokio::time::Duration::from_millis(dur.as_millis() as u64))
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use std::os::fd::AsRawFd;
+
+    use ssh2::Session;
+
+    use crate::{error::Error, session_stream::PollTimeout};
+
+    // Waits on a peer that never answers, each retry of the handshake is counted
+    async fn handshake_with_silent_peer<S>(stream: S) -> Result<(), Box<dyn std::error::Error>>
+    where
+        S: AsyncSessionStream + AsRawFd + Send + Sync + 'static,
+    {
+        let mut sess = Session::new()?;
+        sess.set_tcp_stream(stream.as_raw_fd());
+        sess.set_blocking(false);
+
+        let dur = Duration::from_millis(300);
+        let mut n = 0;
+        let mut handshaking = sess.clone();
+        let ret = stream
+            .x_with_timeout(
+                || {
+                    n += 1;
+                    handshaking.handshake()
+                },
+                &sess,
+                BlockDirections::Both,
+                Some(dur),
+            )
+            .await;
+        assert!(matches!(ret, Err(Error::Timeout(x)) if x == dur), "{ret:?}");
+        assert!(n < 10, "{n} retries");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_with_poll() -> Result<(), Box<dyn std::error::Error>> {
+        let (stream, _peer) = UnixStream::pair()?;
+        let stream = Arc::new(stream);
+        let mut sess = Session::new()?;
+        sess.set_tcp_stream(stream.as_raw_fd());
+        sess.set_blocking(false);
+
+        let mut handshaking = sess.clone();
+        let mut state = PollTimeout::new(Some(Duration::from_millis(300)));
+        let ret = futures_util::future::poll_fn(|cx| {
+            stream.poll_write_with_timeout(
+                cx,
+                || handshaking.handshake().map_err(IoError::from),
+                &sess,
+                &mut state,
+            )
+        })
+        .await;
+        assert_eq!(ret.unwrap_err().kind(), IoErrorKind::TimedOut);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_with_tcp() -> Result<(), Box<dyn std::error::Error>> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let stream = TcpStream::connect(listener.local_addr()?).await?;
+        let (_peer, _) = listener.accept().await?;
+        handshake_with_silent_peer(stream).await
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_with_unix() -> Result<(), Box<dyn std::error::Error>> {
+        let (stream, _peer) = UnixStream::pair()?;
+        handshake_with_silent_peer(stream).await
+    }
 }
@@ -0,0 +1,85 @@
+use core::time::Duration;
+use std::{io::Error as IoError, os::unix::io::AsRawFd, sync::Arc};
+
+use async_trait::async_trait;
+use ssh2::BlockDirections;
+
+use super::{AsyncSessionStream, Readiness};
+
+//
+/// The stream of `AsyncSession::connect_with_ssh_config`, to the server itself or to a
+/// ProxyJump or ProxyCommand in between.
+#[derive(Debug)]
+pub enum MaybeProxied<S, P> {
+    Direct(Arc<S>),
+    Proxied(Arc<P>),
+}
+
+impl<S, P> AsRawFd for MaybeProxied<S, P>
+where
+    S: AsRawFd,
+    P: AsRawFd,
+{
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        match self {
+            Self::Direct(stream) => stream.as_raw_fd(),
+            Self::Proxied(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S, P> AsyncSessionStream for MaybeProxied<S, P>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+    P: AsyncSessionStream + Send + Sync + 'static,
+{
+    fn run_clearing_readiness(&self, directions: &BlockDirections, op: &mut dyn FnMut() -> bool) {
+        match self {
+            Self::Direct(stream) => stream.run_clearing_readiness(directions, op),
+            Self::Proxied(stream) => stream.run_clearing_readiness(directions, op),
+        }
+    }
+
+    async fn readiness(&self, directions: &BlockDirections) -> Result<(), IoError> {
+        match self {
+            Self::Direct(stream) => stream.readiness(directions).await,
+            Self::Proxied(stream) => stream.readiness(directions).await,
+        }
+    }
+
+    fn readiness_owned(self: &Arc<Self>, directions: &BlockDirections) -> Readiness {
+        match &**self {
+            Self::Direct(stream) => stream.readiness_owned(directions),
+            Self::Proxied(stream) => stream.readiness_owned(directions),
+        }
+    }
+
+    async fn wait_readable(&self) -> Result<(), IoError> {
+        match self {
+            Self::Direct(stream) => stream.wait_readable().await,
+            Self::Proxied(stream) => stream.wait_readable().await,
+        }
+    }
+
+    fn peek_len(&self) -> Result<Option<usize>, IoError> {
+        match self {
+            Self::Direct(stream) => stream.peek_len(),
+            Self::Proxied(stream) => stream.peek_len(),
+        }
+    }
+
+    fn shutdown_socket(&self) -> Result<(), IoError> {
+        match self {
+            Self::Direct(stream) => stream.shutdown_socket(),
+            Self::Proxied(stream) => stream.shutdown_socket(),
+        }
+    }
+
+    async fn sleep(&self, dur: Duration) {
+        match self {
+            Self::Direct(stream) => stream.sleep(dur).await,
+            Self::Proxied(stream) => stream.sleep(dur).await,
+        }
+    }
+}
@@ -1,44 +1,158 @@
 use core::{
+    future::Future,
+    pin::Pin,
     task::{Context, Poll},
     time::Duration,
 };
-use std::io::Error as IoError;
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    sync::{Arc, Mutex},
+};
 
 use async_trait::async_trait;
+use futures_util::{
+    future::{self, Either},
+    poll,
+};
 use ssh2::{BlockDirections, Error as Ssh2Error, Session};
 
-use crate::error::Error;
+use crate::{error::Error, util::ssh2_error_is_would_block};
 
 //
 #[cfg(feature = "async-io")]
 mod impl_async_io;
 #[cfg(feature = "tokio")]
 mod impl_tokio;
+#[cfg(unix)]
+mod maybe_proxied;
+
+#[cfg(unix)]
+pub use maybe_proxied::MaybeProxied;
+
+/// Readiness of the socket that outlives the borrow of the stream, see
+/// `AsyncSessionStream::readiness_owned`.
+pub type Readiness = Pin<Box<dyn Future<Output = Result<(), IoError>> + Send + Sync>>;
 
 //
+/// Runs libssh2 operations on a non-blocking socket.
+///
+/// An operation that would block is retried once the socket is ready in the direction libssh2
+/// reports. The readiness is registered before the operation runs, so an operation on another
+/// channel that reads our data off the socket still wakes us. Every retry either waits for
+/// readiness or yields once, there are no timers involved.
 #[async_trait]
 pub trait AsyncSessionStream {
     //
+    /// Fails with `Error::Timeout` once `timeout` has elapsed since `op` first had to wait. The
+    /// deadline is checked before every retry.
+    async fn x_with_timeout<R>(
+        &self,
+        mut op: impl FnMut() -> Result<R, Ssh2Error> + Send,
+        sess: &Session,
+        expected_block_directions: BlockDirections,
+        timeout: Option<Duration>,
+    ) -> Result<R, Error> {
+        let mut directions = expected_block_directions;
+        let mut yielded = false;
+        // Started once `op` first has to wait, checked before every retry
+        let mut timer = None;
+        loop {
+            let mut readiness = self.readiness(&directions);
+            let fired = match poll!(&mut readiness) {
+                Poll::Ready(ret) => {
+                    ret?;
+                    true
+                }
+                Poll::Pending => false,
+            };
+
+            {
+                let mut ret = None;
+                self.run_clearing_readiness(&directions, &mut || {
+                    let x = op();
+                    let would_block = matches!(&x, Err(err) if ssh2_error_is_would_block(err));
+                    ret = Some(x);
+                    would_block
+                });
+                match ret.expect("op runs") {
+                    Ok(x) => return Ok(x),
+                    Err(err) => {
+                        if !ssh2_error_is_would_block(&err) {
+                            return Err(err.into());
+                        }
+                    }
+                }
+            }
+
+            if let Some(dur) = timeout {
+                let timer = timer.get_or_insert_with(|| self.sleep(dur));
+                if poll!(timer.as_mut()).is_ready() {
+                    return Err(Error::Timeout(dur));
+                }
+            }
+
+            let actual = match sess.block_directions() {
+                // A concurrent operation reset the directions, it is done with the socket by now
+                BlockDirections::None if !yielded => {
+                    yielded = true;
+                    yield_now().await;
+                    continue;
+                }
+                // What the session still waits for arrives as inbound data
+                BlockDirections::None => BlockDirections::Inbound,
+                actual => {
+                    yielded = false;
+                    actual
+                }
+            };
+            // Only what libssh2 reports is registered from now on
+            if fired || !covers(&directions, &actual) {
+                // The readiness seen before is used up, or doesn't cover, retry with new one
+                drop(readiness);
+                directions = actual;
+                yield_now().await;
+            } else {
+                match (timer.as_mut(), timeout) {
+                    (Some(timer), Some(dur)) => {
+                        match future::select(readiness, timer.as_mut()).await {
+                            Either::Left((ret, _)) => ret?,
+                            Either::Right(_) => return Err(Error::Timeout(dur)),
+                        }
+                    }
+                    _ => readiness.await?,
+                }
+                directions = actual;
+            }
+        }
+    }
+
+    /// Times out after the session timeout, see `SessionConfiguration::set_timeout`.
     async fn x_with<R>(
         &self,
         op: impl FnMut() -> Result<R, Ssh2Error> + Send,
         sess: &Session,
         expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Result<R, Error>;
+    ) -> Result<R, Error> {
+        self.x_with_timeout(op, sess, expected_block_directions, session_timeout(sess))
+            .await
+    }
+
+    async fn rw_with_timeout<R>(
+        &self,
+        op: impl FnMut() -> Result<R, Ssh2Error> + Send,
+        sess: &Session,
+        timeout: Option<Duration>,
+    ) -> Result<R, Error> {
+        self.x_with_timeout(op, sess, BlockDirections::Both, timeout)
+            .await
+    }
 
     async fn rw_with<R>(
         &self,
         op: impl FnMut() -> Result<R, Ssh2Error> + Send,
         sess: &Session,
     ) -> Result<R, Error> {
-        self.x_with(
-            op,
-            sess,
-            BlockDirections::Both,
-            Some(Duration::from_millis(1)),
-        )
-        .await
+        self.x_with(op, sess, BlockDirections::Both).await
     }
 
     async fn none_with<R>(
@@ -46,13 +160,7 @@ pub trait AsyncSessionStream {
         op: impl FnMut() -> Result<R, Ssh2Error> + Send,
         sess: &Session,
     ) -> Result<R, Error> {
-        self.x_with(
-            op,
-            sess,
-            BlockDirections::None,
-            Some(Duration::from_millis(1)),
-        )
-        .await
+        self.x_with(op, sess, BlockDirections::None).await
     }
 
     async fn read_with<R>(
@@ -60,13 +168,7 @@ pub trait AsyncSessionStream {
         op: impl FnMut() -> Result<R, Ssh2Error> + Send,
         sess: &Session,
     ) -> Result<R, Error> {
-        self.x_with(
-            op,
-            sess,
-            BlockDirections::Inbound,
-            Some(Duration::from_millis(1)),
-        )
-        .await
+        self.x_with(op, sess, BlockDirections::Inbound).await
     }
 
     async fn write_with<R>(
@@ -74,53 +176,324 @@ pub trait AsyncSessionStream {
         op: impl FnMut() -> Result<R, Ssh2Error> + Send,
         sess: &Session,
     ) -> Result<R, Error> {
-        self.x_with(
-            op,
-            sess,
-            BlockDirections::Outbound,
-            Some(Duration::from_millis(1)),
-        )
-        .await
+        self.x_with(op, sess, BlockDirections::Outbound).await
     }
 
     //
-    fn poll_x_with<R>(
-        &self,
+    /// Fails with `IoErrorKind::TimedOut` once the timeout of `state` expires, checked before
+    /// every retry. The readiness waited for is kept in `state` across polls.
+    fn poll_x_with_timeout<R>(
+        self: &Arc<Self>,
         cx: &mut Context,
-        op: impl FnMut() -> Result<R, IoError> + Send,
+        mut op: impl FnMut() -> Result<R, IoError> + Send,
         sess: &Session,
         expected_block_directions: BlockDirections,
-        sleep_dur: Option<Duration>,
-    ) -> Poll<Result<R, IoError>>;
+        state: &mut PollTimeout,
+    ) -> Poll<Result<R, IoError>>
+    where
+        Self: Sized + Send + Sync + 'static,
+    {
+        // A copy, `BlockDirections` isn't `Clone`
+        let directions = union(
+            state
+                .directions
+                .as_ref()
+                .unwrap_or(&expected_block_directions),
+            &BlockDirections::None,
+        );
+        let fired = match state.poll_readiness(cx, self, &directions) {
+            Poll::Ready(ret) => {
+                ret?;
+                true
+            }
+            Poll::Pending => false,
+        };
 
-    fn poll_read_with<R>(
-        &self,
+        let mut ret = None;
+        self.run_clearing_readiness(&directions, &mut || {
+            let x = op();
+            let would_block = matches!(&x, Err(err) if err.kind() == IoErrorKind::WouldBlock);
+            ret = Some(x);
+            would_block
+        });
+        match ret.expect("op runs") {
+            Err(err) if err.kind() == IoErrorKind::WouldBlock => {}
+            ret => {
+                state.reset();
+                return Poll::Ready(ret);
+            }
+        }
+
+        if let Poll::Ready(err) = state.poll_expired(cx, sess, |dur| {
+            let stream = self.clone();
+            async move { stream.sleep(dur).await }
+        }) {
+            return Poll::Ready(Err(err));
+        }
+
+        let actual = match sess.block_directions() {
+            // See `x_with_timeout`
+            BlockDirections::None if !state.yielded => {
+                state.yielded = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            BlockDirections::None => BlockDirections::Inbound,
+            actual => {
+                state.yielded = false;
+                actual
+            }
+        };
+        // Only what libssh2 reports is registered from now on
+        let covered = covers(&directions, &actual);
+        state.directions = Some(union(&actual, &BlockDirections::None));
+        if fired || !covered {
+            // The readiness seen before is used up, or doesn't cover, retry with new one
+            cx.waker().wake_by_ref();
+        }
+        Poll::Pending
+    }
+
+    fn poll_read_with_timeout<R>(
+        self: &Arc<Self>,
         cx: &mut Context,
         op: impl FnMut() -> Result<R, IoError> + Send,
         sess: &Session,
-    ) -> Poll<Result<R, IoError>> {
-        self.poll_x_with(
-            cx,
-            op,
-            sess,
-            BlockDirections::Inbound,
-            Some(Duration::from_millis(1)),
-        )
+        state: &mut PollTimeout,
+    ) -> Poll<Result<R, IoError>>
+    where
+        Self: Sized + Send + Sync + 'static,
+    {
+        self.poll_x_with_timeout(cx, op, sess, BlockDirections::Inbound, state)
     }
 
-    fn poll_write_with<R>(
-        &self,
+    fn poll_write_with_timeout<R>(
+        self: &Arc<Self>,
         cx: &mut Context,
         op: impl FnMut() -> Result<R, IoError> + Send,
         sess: &Session,
-    ) -> Poll<Result<R, IoError>> {
-        self.poll_x_with(
-            cx,
-            op,
-            sess,
-            BlockDirections::Both,
-            Some(Duration::from_millis(1)),
-        )
+        state: &mut PollTimeout,
+    ) -> Poll<Result<R, IoError>>
+    where
+        Self: Sized + Send + Sync + 'static,
+    {
+        self.poll_x_with_timeout(cx, op, sess, BlockDirections::Both, state)
+    }
+
+    //
+    /// Runs `op`, which returns whether it would block. If it would, the readiness of
+    /// `directions` seen before it ran is forgotten, libssh2 does the socket I/O itself so the
+    /// runtime never learns that it was used up. Readiness that arrives while `op` runs is kept.
+    fn run_clearing_readiness(&self, directions: &BlockDirections, op: &mut dyn FnMut() -> bool);
+
+    /// Resolves once the socket is ready in any of `directions`.
+    async fn readiness(&self, directions: &BlockDirections) -> Result<(), IoError>;
+
+    /// Like `readiness`, holding on to the stream.
+    fn readiness_owned(self: &Arc<Self>, directions: &BlockDirections) -> Readiness;
+
+    /// Resolves once the socket has something to read, without reading it.
+    async fn wait_readable(&self) -> Result<(), IoError>;
+
+    /// Bytes waiting to be read, `None` once the peer has closed.
+    fn peek_len(&self) -> Result<Option<usize>, IoError>;
+
+    /// Shuts down both directions of the socket, pending operations fail.
+    fn shutdown_socket(&self) -> Result<(), IoError>;
+
+    /// Sleeps on the runtime of the stream.
+    async fn sleep(&self, dur: Duration);
+}
+
+async fn yield_now() {
+    let mut yielded = false;
+    future::poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+//
+/// The session timeout, `None` when it is 0.
+pub fn session_timeout(sess: &Session) -> Option<Duration> {
+    match sess.timeout() {
+        0 => None,
+        ms => Some(Duration::from_millis(ms as u64)),
+    }
+}
+
+//
+/// State of `poll_*` operations, kept by e.g. `AsyncChannel` across polls.
+///
+/// The timer starts when an operation first has to wait and is reset when it completes, so it
+/// bounds how long a single read or write can stall. The readiness registered for the operation
+/// stays armed in between.
+#[derive(Default)]
+pub struct PollTimeout {
+    timeout: Option<Duration>,
+    // Only ever polled through `get_mut`, the mutex just makes the timer `Sync`
+    timer: Option<Timer>,
+    readiness: Option<(BlockDirections, Readiness)>,
+    // What libssh2 last reported, armed on the next poll
+    directions: Option<BlockDirections>,
+    yielded: bool,
+}
+
+type Timer = Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>;
+
+impl core::fmt::Debug for PollTimeout {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PollTimeout")
+            .field("timeout", &self.timeout)
+            .field("running", &self.timer.is_some())
+            .field(
+                "readiness",
+                &self.readiness.as_ref().map(|(directions, _)| directions),
+            )
+            .field("directions", &self.directions)
+            .finish()
+    }
+}
+
+impl PollTimeout {
+    /// `None` falls back to the session timeout.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            timer: None,
+            readiness: None,
+            directions: None,
+            yielded: false,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+        self.timer = None;
+    }
+
+    pub fn reset(&mut self) {
+        self.timer = None;
+        self.directions = None;
+        self.yielded = false;
+    }
+
+    /// Arms the readiness of `directions` unless armed already, `Ready` once it fired since.
+    fn poll_readiness<S>(
+        &mut self,
+        cx: &mut Context,
+        stream: &Arc<S>,
+        directions: &BlockDirections,
+    ) -> Poll<Result<(), IoError>>
+    where
+        S: AsyncSessionStream + ?Sized,
+    {
+        if !matches!(&self.readiness, Some((armed, _)) if covers(armed, directions)) {
+            self.readiness = Some((
+                // A copy, `BlockDirections` isn't `Clone`
+                union(directions, &BlockDirections::None),
+                stream.readiness_owned(directions),
+            ));
+        }
+        let (_, readiness) = self.readiness.as_mut().expect("armed above");
+        let ret = readiness.as_mut().poll(cx);
+        if ret.is_ready() {
+            self.readiness = None;
+        }
+        ret
+    }
+
+    /// Starts the timer with `sleep` if needed, `Ready` with a `TimedOut` error once it fires.
+    pub fn poll_expired<F>(
+        &mut self,
+        cx: &mut Context,
+        sess: &Session,
+        sleep: impl FnOnce(Duration) -> F,
+    ) -> Poll<IoError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let dur = match self.timeout.or_else(|| session_timeout(sess)) {
+            Some(dur) => dur,
+            None => return Poll::Pending,
+        };
+
+        let timer = self
+            .timer
+            .get_or_insert_with(|| Mutex::new(Box::pin(sleep(dur))))
+            .get_mut()
+            .expect("timer lock poisoned");
+        match timer.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                self.timer = None;
+                Poll::Ready(IoError::new(IoErrorKind::TimedOut, Error::Timeout(dur)))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub(crate) fn peek_len(sock: socket2::SockRef<'_>) -> Result<Option<usize>, IoError> {
+    // One byte tells data from EOF, the kernel counts the rest
+    let mut buf = [core::mem::MaybeUninit::uninit(); 1];
+    match sock.peek(&mut buf) {
+        Ok(0) => Ok(None),
+        Ok(_) => unread_len(&sock).map(Some),
+        Err(err) if err.kind() == IoErrorKind::WouldBlock => Ok(Some(0)),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(all(unix, any(feature = "tokio", feature = "async-io")))]
+fn unread_len(sock: &socket2::SockRef<'_>) -> Result<usize, IoError> {
+    use std::os::fd::AsRawFd as _;
+
+    let mut n: libc::c_int = 0;
+    // SAFETY: FIONREAD writes one int
+    if unsafe { libc::ioctl(sock.as_raw_fd(), libc::FIONREAD, &mut n) } == -1 {
+        return Err(IoError::last_os_error());
+    }
+    Ok(n as usize)
+}
+
+#[cfg(all(windows, any(feature = "tokio", feature = "async-io")))]
+fn unread_len(sock: &socket2::SockRef<'_>) -> Result<usize, IoError> {
+    use std::os::windows::io::AsRawSocket as _;
+    use windows_sys::Win32::Networking::WinSock::{ioctlsocket, WSAGetLastError, FIONREAD};
+
+    let mut n: u32 = 0;
+    // SAFETY: FIONREAD writes one u_long
+    if unsafe { ioctlsocket(sock.as_raw_socket() as _, FIONREAD, &mut n) } != 0 {
+        return Err(IoError::from_raw_os_error(unsafe { WSAGetLastError() }));
+    }
+    Ok(n as usize)
+}
+
+/// Whether readiness armed for `armed` also wakes an operation blocking in `directions`.
+fn covers(armed: &BlockDirections, directions: &BlockDirections) -> bool {
+    (armed.is_readable() || !directions.is_readable())
+        && (armed.is_writable() || !directions.is_writable())
+}
+
+fn union(a: &BlockDirections, b: &BlockDirections) -> BlockDirections {
+    match (
+        a.is_readable() || b.is_readable(),
+        a.is_writable() || b.is_writable(),
+    ) {
+        (false, false) => BlockDirections::None,
+        (true, false) => BlockDirections::Inbound,
+        (false, true) => BlockDirections::Outbound,
+        (true, true) => BlockDirections::Both,
     }
 }
 
@@ -154,4 +527,112 @@ mod tests {
         assert!(BlockDirections::Both.is_readable());
         assert!(BlockDirections::Both.is_writable());
     }
+
+    #[test]
+    fn test_covers_and_union() {
+        assert!(covers(&BlockDirections::Inbound, &BlockDirections::Inbound));
+        assert!(!covers(&BlockDirections::Inbound, &BlockDirections::Both));
+        assert!(!covers(
+            &BlockDirections::Outbound,
+            &BlockDirections::Inbound
+        ));
+        assert!(covers(&BlockDirections::Both, &BlockDirections::Outbound));
+        assert!(covers(&BlockDirections::None, &BlockDirections::None));
+
+        assert_eq!(
+            union(&BlockDirections::Outbound, &BlockDirections::Inbound),
+            BlockDirections::Both
+        );
+        assert_eq!(
+            union(&BlockDirections::None, &BlockDirections::Inbound),
+            BlockDirections::Inbound
+        );
+        assert_eq!(
+            union(&BlockDirections::None, &BlockDirections::None),
+            BlockDirections::None
+        );
+    }
+
+    #[cfg(all(unix, feature = "async-io"))]
+    #[test]
+    fn test_blocked_on_none() -> Result<(), Box<dyn std::error::Error>> {
+        use std::{
+            io::Write as _,
+            os::unix::net::UnixStream,
+            sync::atomic::{AtomicBool, Ordering},
+        };
+
+        // A fresh session blocks in no direction
+        let sess = Session::new()?;
+        let (stream, mut peer) = UnixStream::pair()?;
+        let stream = Arc::new(async_io::Async::new(stream)?);
+        let would_block = || {
+            Ssh2Error::new(
+                ssh2::ErrorCode::Session(libssh2_sys::LIBSSH2_ERROR_EAGAIN),
+                "would block",
+            )
+        };
+        let dur = Duration::from_millis(100);
+
+        futures_lite::future::block_on(async {
+            // Retried once, then waits for the silent socket, also when it is writable
+            for directions in [BlockDirections::Inbound, BlockDirections::Both] {
+                let mut n = 0;
+                let ret = stream
+                    .x_with_timeout(
+                        || {
+                            n += 1;
+                            Err::<(), _>(would_block())
+                        },
+                        &sess,
+                        directions,
+                        Some(dur),
+                    )
+                    .await;
+                assert!(matches!(ret, Err(Error::Timeout(_))), "{ret:?}");
+                assert!(n < 10, "{n} retries");
+            }
+
+            let mut n = 0;
+            let mut state = PollTimeout::new(Some(dur));
+            let ret = future::poll_fn(|cx| {
+                stream.poll_write_with_timeout(
+                    cx,
+                    || {
+                        n += 1;
+                        Err::<(), _>(IoErrorKind::WouldBlock.into())
+                    },
+                    &sess,
+                    &mut state,
+                )
+            })
+            .await;
+            assert_eq!(ret.unwrap_err().kind(), IoErrorKind::TimedOut);
+            assert!(n < 10, "{n} retries");
+
+            // Woken by inbound data
+            let sent = Arc::new(AtomicBool::new(false));
+            std::thread::spawn({
+                let sent = sent.clone();
+                move || {
+                    std::thread::sleep(Duration::from_millis(20));
+                    sent.store(true, Ordering::SeqCst);
+                    peer.write_all(b"x").unwrap();
+                }
+            });
+            stream
+                .x_with_timeout(
+                    || match sent.load(Ordering::SeqCst) {
+                        true => Ok(()),
+                        false => Err(would_block()),
+                    },
+                    &sess,
+                    BlockDirections::Inbound,
+                    Some(Duration::from_secs(5)),
+                )
+                .await?;
+
+            Ok(())
+        })
+    }
 }
@@ -1,25 +1,71 @@
+use core::{
+    task::{Context, Poll},
+    time::Duration,
+};
 use std::{
+    io::Error as IoError,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
+use futures_util::{
+    ready,
+    stream::{self, BoxStream, StreamExt as _, TryStreamExt as _},
+};
 use ssh2::{File, FileStat, OpenFlags, OpenType, RenameFlags, Session, Sftp};
 
-use crate::{error::Error, session_stream::AsyncSessionStream};
+use crate::{
+    error::Error,
+    fs::{self, Metadata},
+    session_stream::{AsyncSessionStream, PollTimeout},
+    transfer::{self, DirTransferConfiguration, DirTransferReport, TransferConfiguration},
+    walk::{self, WalkConfiguration, WalkStream},
+};
+
+/// See `AsyncSftp::read_dir_stream`.
+pub type ReadDirStream = BoxStream<'static, Result<(PathBuf, FileStat), Error>>;
 
 //
 pub struct AsyncSftp<S> {
     inner: Sftp,
     sess: Session,
     stream: Arc<S>,
+    broken: Arc<AtomicBool>,
+    remote_host: Option<(String, u16)>,
 }
 
 impl<S> AsyncSftp<S> {
-    pub(crate) fn from_parts(inner: Sftp, sess: Session, stream: Arc<S>) -> Self {
+    pub(crate) fn from_parts(
+        inner: Sftp,
+        sess: Session,
+        stream: Arc<S>,
+        broken: Arc<AtomicBool>,
+        remote_host: Option<(String, u16)>,
+    ) -> Self {
         Self {
             inner,
             sess,
             stream,
+            broken,
+            remote_host,
+        }
+    }
+
+    /// See `AsyncSession::is_broken`.
+    pub fn is_broken(&self) -> bool {
+        self.broken.load(Ordering::SeqCst)
+    }
+
+    fn context(&self, op: &'static str) -> impl FnOnce(Error) -> Error + '_ {
+        move |err| {
+            let remote = self
+                .remote_host
+                .as_ref()
+                .map(|(host, port)| (host.as_str(), *port));
+            err.with_context(op, remote)
         }
     }
 }
@@ -41,7 +87,8 @@ where
                 || self.inner.open_mode(filename, flags, mode, open_type),
                 &self.sess,
             )
-            .await?;
+            .await
+            .map_err(self.context("sftp_open"))?;
 
         Ok(AsyncFile::from_parts(
             file,
@@ -54,7 +101,26 @@ where
         let file = self
             .stream
             .rw_with(|| self.inner.open(filename), &self.sess)
-            .await?;
+            .await
+            .map_err(self.context("sftp_open"))?;
+
+        Ok(AsyncFile::from_parts(
+            file,
+            self.sess.clone(),
+            self.stream.clone(),
+        ))
+    }
+
+    pub async fn open_with_timeout(
+        &self,
+        filename: &Path,
+        timeout: Duration,
+    ) -> Result<AsyncFile<S>, Error> {
+        let file = self
+            .stream
+            .rw_with_timeout(|| self.inner.open(filename), &self.sess, Some(timeout))
+            .await
+            .map_err(self.context("sftp_open"))?;
 
         Ok(AsyncFile::from_parts(
             file,
@@ -67,7 +133,8 @@ where
         let file = self
             .stream
             .rw_with(|| self.inner.create(filename), &self.sess)
-            .await?;
+            .await
+            .map_err(self.context("sftp_create"))?;
 
         Ok(AsyncFile::from_parts(
             file,
@@ -80,7 +147,8 @@ where
         let file = self
             .stream
             .rw_with(|| self.inner.opendir(dirname), &self.sess)
-            .await?;
+            .await
+            .map_err(self.context("sftp_opendir"))?;
 
         Ok(AsyncFile::from_parts(
             file,
@@ -90,75 +158,108 @@ where
     }
 
     pub async fn readdir(&self, dirname: &Path) -> Result<Vec<(PathBuf, FileStat)>, Error> {
-        // Copy from ssh2
-        let mut dir = self.opendir(dirname).await?;
-        let mut ret = Vec::new();
-        loop {
-            match dir.readdir().await {
-                Ok((filename, stat)) => {
-                    if &*filename == Path::new(".") || &*filename == Path::new("..") {
-                        continue;
-                    }
+        self.read_dir_stream(dirname).await?.try_collect().await
+    }
 
-                    ret.push((dirname.join(&filename), stat))
-                }
-                Err(Error::Ssh2(ref e))
-                    if e.code() == ssh2::ErrorCode::Session(libssh2_sys::LIBSSH2_ERROR_FILE) =>
-                {
-                    break
+    /// Like `readdir`, one entry at a time. The stream ends after the first error, the handle
+    /// is closed at the end or when the stream is dropped.
+    pub async fn read_dir_stream(&self, dirname: &Path) -> Result<ReadDirStream, Error> {
+        let dir = self.opendir(dirname).await?;
+        let dirname = dirname.to_owned();
+
+        Ok(stream::unfold(Some(dir), move |dir| {
+            let dirname = dirname.clone();
+            async move {
+                let mut dir = dir?;
+                loop {
+                    // Copy from ssh2
+                    match dir.readdir().await {
+                        Ok((filename, stat)) => {
+                            if &*filename == Path::new(".") || &*filename == Path::new("..") {
+                                continue;
+                            }
+
+                            return Some((Ok((dirname.join(&filename), stat)), Some(dir)));
+                        }
+                        Err(Error::Ssh2(ref e))
+                            if e.code()
+                                == ssh2::ErrorCode::Session(libssh2_sys::LIBSSH2_ERROR_FILE) =>
+                        {
+                            let _ = dir.close().await;
+                            return None;
+                        }
+                        Err(e) => return Some((Err(e), None)),
+                    }
                 }
-                Err(e) => return Err(e),
             }
-        }
-        Ok(ret)
+        })
+        .boxed())
+    }
+
+    /// Every entry under `root`, depth first and one directory handle open per level, see
+    /// `WalkConfiguration`.
+    pub fn walk(
+        &self,
+        root: &Path,
+        configuration: impl Into<Option<WalkConfiguration>>,
+    ) -> WalkStream<'_> {
+        walk::walk(self, root, configuration.into().unwrap_or_default())
     }
 
     pub async fn mkdir(&self, filename: &Path, mode: i32) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.mkdir(filename, mode), &self.sess)
             .await
+            .map_err(self.context("sftp_mkdir"))
     }
 
     pub async fn rmdir(&self, filename: &Path) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.rmdir(filename), &self.sess)
             .await
+            .map_err(self.context("sftp_rmdir"))
     }
 
     pub async fn stat(&self, filename: &Path) -> Result<FileStat, Error> {
         self.stream
             .rw_with(|| self.inner.stat(filename), &self.sess)
             .await
+            .map_err(self.context("sftp_stat"))
     }
 
     pub async fn lstat(&self, filename: &Path) -> Result<FileStat, Error> {
         self.stream
             .rw_with(|| self.inner.lstat(filename), &self.sess)
             .await
+            .map_err(self.context("sftp_lstat"))
     }
 
     pub async fn setstat(&self, filename: &Path, stat: FileStat) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.setstat(filename, stat.clone()), &self.sess)
             .await
+            .map_err(self.context("sftp_setstat"))
     }
 
     pub async fn symlink(&self, path: &Path, target: &Path) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.symlink(path, target), &self.sess)
             .await
+            .map_err(self.context("sftp_symlink"))
     }
 
     pub async fn readlink(&self, path: &Path) -> Result<PathBuf, Error> {
         self.stream
             .rw_with(|| self.inner.readlink(path), &self.sess)
             .await
+            .map_err(self.context("sftp_readlink"))
     }
 
     pub async fn realpath(&self, path: &Path) -> Result<PathBuf, Error> {
         self.stream
             .rw_with(|| self.inner.realpath(path), &self.sess)
             .await
+            .map_err(self.context("sftp_realpath"))
     }
 
     pub async fn rename(
@@ -170,18 +271,150 @@ where
         self.stream
             .rw_with(|| self.inner.rename(src, dst, flags), &self.sess)
             .await
+            .map_err(self.context("sftp_rename"))
     }
 
     pub async fn unlink(&self, file: &Path) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.unlink(file), &self.sess)
             .await
+            .map_err(self.context("sftp_unlink"))
+    }
+
+    /// Copies `local_path` to `remote_path` with several writes in flight, see
+    /// `TransferConfiguration`. Returns the size of the file.
+    pub async fn upload(
+        &self,
+        local_path: &Path,
+        remote_path: &Path,
+        configuration: impl Into<Option<TransferConfiguration>>,
+    ) -> Result<u64, Error> {
+        transfer::upload(
+            self,
+            local_path,
+            remote_path,
+            &configuration.into().unwrap_or_default(),
+        )
+        .await
+    }
+
+    /// Copies `remote_path` to `local_path` with several reads in flight, see
+    /// `TransferConfiguration`. Returns the size of the file.
+    pub async fn download(
+        &self,
+        remote_path: &Path,
+        local_path: &Path,
+        configuration: impl Into<Option<TransferConfiguration>>,
+    ) -> Result<u64, Error> {
+        transfer::download(
+            self,
+            remote_path,
+            local_path,
+            &configuration.into().unwrap_or_default(),
+        )
+        .await
+    }
+
+    /// Copies the tree under `local_dir` into `remote_dir`, see `DirTransferConfiguration`.
+    /// Fails only when the roots cannot be read or created, the report has what happened to
+    /// every entry.
+    pub async fn upload_dir(
+        &self,
+        local_dir: &Path,
+        remote_dir: &Path,
+        configuration: impl Into<Option<DirTransferConfiguration>>,
+    ) -> Result<DirTransferReport, Error> {
+        transfer::upload_dir(
+            self,
+            local_dir,
+            remote_dir,
+            &configuration.into().unwrap_or_default(),
+        )
+        .await
+    }
+
+    /// Copies the tree under `remote_dir` into `local_dir`, like `upload_dir`.
+    pub async fn download_dir(
+        &self,
+        remote_dir: &Path,
+        local_dir: &Path,
+        configuration: impl Into<Option<DirTransferConfiguration>>,
+    ) -> Result<DirTransferReport, Error> {
+        transfer::download_dir(
+            self,
+            remote_dir,
+            local_dir,
+            &configuration.into().unwrap_or_default(),
+        )
+        .await
+    }
+
+    /// The whole file, like `tokio::fs::read`.
+    pub async fn read(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        fs::read(self, path).await
+    }
+
+    pub async fn read_to_string(&self, path: &Path) -> Result<String, Error> {
+        fs::read_to_string(self, path).await
+    }
+
+    /// Creates or truncates the file.
+    pub async fn write(&self, path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+        fs::write(self, path, contents.as_ref(), false).await
+    }
+
+    /// Creates the file or writes at its end.
+    pub async fn append(&self, path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Error> {
+        fs::write(self, path, contents.as_ref(), true).await
+    }
+
+    /// False also when the server cannot tell, see `try_exists`.
+    pub async fn exists(&self, path: &Path) -> bool {
+        self.try_exists(path).await.unwrap_or(false)
+    }
+
+    /// False for a dangling symlink.
+    pub async fn try_exists(&self, path: &Path) -> Result<bool, Error> {
+        fs::try_exists(self, path).await
+    }
+
+    pub async fn metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        self.stat(path).await.map(Into::into)
+    }
+
+    pub async fn symlink_metadata(&self, path: &Path) -> Result<Metadata, Error> {
+        self.lstat(path).await.map(Into::into)
+    }
+
+    pub async fn canonicalize(&self, path: &Path) -> Result<PathBuf, Error> {
+        self.realpath(path).await
+    }
+
+    /// Creates `path` and the parents that are missing, an existing directory is not an error.
+    pub async fn create_dir_all(&self, path: &Path) -> Result<(), Error> {
+        fs::create_dir_all(self, path).await
+    }
+
+    /// Removes `path` and everything under it. A symlink is removed and not followed.
+    pub async fn remove_dir_all(&self, path: &Path) -> Result<(), Error> {
+        fs::remove_dir_all(self, path).await
+    }
+
+    pub async fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        self.unlink(path).await
+    }
+
+    /// Copies a file on the server, with its permissions. The content passes through the
+    /// client, libssh2 has no server side copy. Returns the size of the file.
+    pub async fn copy(&self, from: &Path, to: &Path) -> Result<u64, Error> {
+        fs::copy(self, from, to).await
     }
 
     pub async fn shutdown(&mut self) -> Result<(), Error> {
         self.stream
             .rw_with(|| self.inner.shutdown(), &self.sess)
             .await
+            .map_err(self.context("sftp_shutdown"))
     }
 }
 
@@ -190,6 +423,11 @@ pub struct AsyncFile<S> {
     inner: File,
     sess: Session,
     stream: Arc<S>,
+    timeout: PollTimeout,
+    closed: bool,
+    /// Set by tokio's `start_seek`, run by `poll_complete`.
+    #[cfg(feature = "tokio")]
+    seek: Option<std::io::SeekFrom>,
 }
 
 impl<S> AsyncFile<S> {
@@ -198,8 +436,17 @@ impl<S> AsyncFile<S> {
             inner,
             sess,
             stream,
+            timeout: PollTimeout::default(),
+            closed: false,
+            #[cfg(feature = "tokio")]
+            seek: None,
         }
     }
+
+    /// How long a read, write or seek may stall, `None` falls back to the session timeout.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout.set_timeout(timeout);
+    }
 }
 
 impl<S> AsyncFile<S>
@@ -234,7 +481,33 @@ where
 
     #[doc(hidden)]
     pub async fn close(&mut self) -> Result<(), Error> {
-        self.stream.rw_with(|| self.inner.close(), &self.sess).await
+        if self.closed {
+            return Ok(());
+        }
+
+        self.stream
+            .rw_with(|| self.inner.close(), &self.sess)
+            .await?;
+        self.closed = true;
+        Ok(())
+    }
+
+    /// Backs `poll_close` and `poll_shutdown`, writes are sent as they are made so there is
+    /// nothing to flush before closing the handle.
+    fn poll_close_handle(&mut self, cx: &mut Context) -> Poll<Result<(), IoError>> {
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
+
+        let inner = &mut self.inner;
+        ready!(self.stream.poll_write_with_timeout(
+            cx,
+            || inner.close().map_err(Into::into),
+            &self.sess,
+            &mut self.timeout
+        ))?;
+        self.closed = true;
+        Poll::Ready(Ok(()))
     }
 }
 
@@ -245,7 +518,10 @@ mod impl_futures_util {
     };
     use std::io::{Error as IoError, Read as _, Seek, SeekFrom, Write as _};
 
-    use futures_util::io::{AsyncRead, AsyncSeek, AsyncWrite};
+    use futures_util::{
+        io::{AsyncRead, AsyncSeek, AsyncWrite},
+        ready,
+    };
 
     use super::AsyncFile;
     use crate::session_stream::AsyncSessionStream;
@@ -264,7 +540,8 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_read_with(cx, || inner.read(buf), &sess)
+            this.stream
+                .poll_read_with_timeout(cx, || inner.read(buf), &sess, &mut this.timeout)
         }
     }
 
@@ -281,7 +558,8 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_write_with(cx, || inner.write(buf), &sess)
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
         }
 
         fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
@@ -289,11 +567,13 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_write_with(cx, || inner.flush(), &sess)
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
         }
 
-        fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
-            self.poll_flush(cx)
+        fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), IoError>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            self.get_mut().poll_close_handle(cx)
         }
     }
 
@@ -310,7 +590,116 @@ mod impl_futures_util {
             let sess = this.sess.clone();
             let inner = &mut this.inner;
 
-            this.stream.poll_read_with(cx, || inner.seek(pos), &sess)
+            this.stream
+                .poll_read_with_timeout(cx, || inner.seek(pos), &sess, &mut this.timeout)
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod impl_tokio {
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use std::io::{Error as IoError, Read as _, Seek, SeekFrom, Write as _};
+
+    use futures_util::ready;
+    use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+    use super::AsyncFile;
+    use crate::session_stream::AsyncSessionStream;
+
+    //
+    impl<S> AsyncRead for AsyncFile<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<Result<(), IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream.poll_read_with_timeout(
+                cx,
+                || {
+                    let size = inner.read(buf.initialize_unfilled())?;
+                    buf.advance(size);
+                    Ok(())
+                },
+                &sess,
+                &mut this.timeout,
+            )
+        }
+    }
+
+    impl<S> AsyncWrite for AsyncFile<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.write(buf), &sess, &mut this.timeout)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+
+            this.stream
+                .poll_write_with_timeout(cx, || inner.flush(), &sess, &mut this.timeout)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<(), IoError>> {
+            ready!(self.as_mut().poll_flush(cx))?;
+            self.get_mut().poll_close_handle(cx)
+        }
+    }
+
+    impl<S> AsyncSeek for AsyncFile<S>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        fn start_seek(self: Pin<&mut Self>, pos: SeekFrom) -> Result<(), IoError> {
+            let this = self.get_mut();
+            if this.seek.is_some() {
+                return Err(IoError::other("other seek operation is in progress"));
+            }
+            this.seek = Some(pos);
+            Ok(())
+        }
+
+        /// Without a seek started, resolves to the current position.
+        fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64, IoError>> {
+            let this = self.get_mut();
+            let sess = this.sess.clone();
+            let inner = &mut this.inner;
+            let pos = this.seek.unwrap_or(SeekFrom::Current(0));
+
+            let ret = ready!(this.stream.poll_read_with_timeout(
+                cx,
+                || inner.seek(pos),
+                &sess,
+                &mut this.timeout
+            ));
+            this.seek = None;
+            Poll::Ready(ret)
         }
     }
 }
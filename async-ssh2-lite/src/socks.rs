@@ -0,0 +1,457 @@
+//! The proxy side of SOCKS5 (RFC 1928, RFC 1929), SOCKS4a and HTTP CONNECT, see
+//! `AsyncSession::dynamic_port_forwarding`.
+
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures_util::io::{
+    AsyncBufRead, AsyncBufReadExt as _, AsyncRead, AsyncReadExt as _, AsyncWrite,
+    AsyncWriteExt as _,
+};
+
+use crate::error::{ChannelOpenFailure, Error, ErrorKind};
+
+//
+/// Where the client wants to go, and how to answer it.
+#[derive(Debug)]
+pub(crate) struct Request {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    protocol: Protocol,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Protocol {
+    Socks5,
+    Socks4,
+    HttpConnect,
+}
+
+const HTTP_HEADERS_MAX: usize = 8 * 1024;
+
+/// Reads the request of any supported protocol, after authenticating it when `auth` is set.
+/// Failures are answered before they are returned.
+pub(crate) async fn accept<L>(
+    local: &mut L,
+    auth: Option<&(String, String)>,
+) -> Result<Request, IoError>
+where
+    L: AsyncBufRead + AsyncRead + AsyncWrite + Unpin,
+{
+    let version = match local.fill_buf().await?.first() {
+        Some(version) => *version,
+        None => return Err(IoErrorKind::UnexpectedEof.into()),
+    };
+    match version {
+        5 => {
+            local.consume_unpin(1);
+            accept_socks5(local, auth).await
+        }
+        4 => {
+            local.consume_unpin(1);
+            accept_socks4(local, auth).await
+        }
+        _ => accept_http_connect(local, auth).await,
+    }
+}
+
+impl Request {
+    /// Tells the client whether the channel was opened, data flows after a success.
+    pub(crate) async fn reply<L>(
+        &self,
+        local: &mut L,
+        ret: Result<(), &Error>,
+    ) -> Result<(), IoError>
+    where
+        L: AsyncWrite + Unpin,
+    {
+        match self.protocol {
+            Protocol::Socks5 => {
+                let rep = match ret {
+                    Ok(()) => 0,
+                    Err(err) => match err.kind() {
+                        ErrorKind::ChannelOpenFailed(
+                            ChannelOpenFailure::AdministrativelyProhibited,
+                        ) => 2,
+                        ErrorKind::ChannelOpenFailed(ChannelOpenFailure::ConnectFailed) => 5,
+                        _ => 1,
+                    },
+                };
+                socks5_reply(local, rep).await
+            }
+            Protocol::Socks4 => socks4_reply(local, ret.is_ok()).await,
+            Protocol::HttpConnect => match ret {
+                Ok(()) => http_reply(local, "200 Connection established", "").await,
+                Err(_) => http_reply(local, "502 Bad Gateway", "").await,
+            },
+        }
+    }
+}
+
+//
+async fn accept_socks5<L>(
+    local: &mut L,
+    auth: Option<&(String, String)>,
+) -> Result<Request, IoError>
+where
+    L: AsyncRead + AsyncWrite + Unpin,
+{
+    let n = read_u8(local).await?;
+    let mut methods = vec![0; n as usize];
+    local.read_exact(&mut methods).await?;
+
+    let method = if auth.is_some() { 2 } else { 0 };
+    if !methods.contains(&method) {
+        local.write_all(&[5, 0xff]).await?;
+        return Err(invalid_data("no acceptable SOCKS5 authentication method"));
+    }
+    local.write_all(&[5, method]).await?;
+
+    if let Some((username, password)) = auth {
+        // RFC 1929
+        if read_u8(local).await? != 1 {
+            return Err(invalid_data("bad SOCKS5 username/password version"));
+        }
+        let given_username = read_u8_prefixed(local).await?;
+        let given_password = read_u8_prefixed(local).await?;
+        if given_username != username.as_bytes() || given_password != password.as_bytes() {
+            local.write_all(&[1, 1]).await?;
+            return Err(auth_failed());
+        }
+        local.write_all(&[1, 0]).await?;
+    }
+
+    let mut head = [0; 4];
+    local.read_exact(&mut head).await?;
+    let [version, cmd, _, atyp] = head;
+    if version != 5 {
+        return Err(invalid_data("bad SOCKS5 request version"));
+    }
+    let host = match atyp {
+        1 => {
+            let mut ip = [0; 4];
+            local.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        3 => String::from_utf8(read_u8_prefixed(local).await?)
+            .map_err(|_| invalid_data("bad SOCKS5 domain name"))?,
+        4 => {
+            let mut ip = [0; 16];
+            local.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        _ => {
+            socks5_reply(local, 8).await?;
+            return Err(invalid_data("unsupported SOCKS5 address type"));
+        }
+    };
+    let port = read_u16(local).await?;
+    // BIND and UDP ASSOCIATE have no direct-tcpip equivalent
+    if cmd != 1 {
+        socks5_reply(local, 7).await?;
+        return Err(invalid_data("unsupported SOCKS5 command"));
+    }
+
+    Ok(Request {
+        host,
+        port,
+        protocol: Protocol::Socks5,
+    })
+}
+
+async fn socks5_reply<L>(local: &mut L, rep: u8) -> Result<(), IoError>
+where
+    L: AsyncWrite + Unpin,
+{
+    // The bound address means nothing here, the channel has none
+    local.write_all(&[5, rep, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    local.flush().await
+}
+
+//
+async fn accept_socks4<L>(
+    local: &mut L,
+    auth: Option<&(String, String)>,
+) -> Result<Request, IoError>
+where
+    L: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let cmd = read_u8(local).await?;
+    let port = read_u16(local).await?;
+    let mut ip = [0; 4];
+    local.read_exact(&mut ip).await?;
+    let _userid = read_nul_terminated(local).await?;
+    // SOCKS4a, 0.0.0.x with x != 0 means a domain name follows
+    let host = if ip[..3] == [0, 0, 0] && ip[3] != 0 {
+        String::from_utf8(read_nul_terminated(local).await?)
+            .map_err(|_| invalid_data("bad SOCKS4a domain name"))?
+    } else {
+        Ipv4Addr::from(ip).to_string()
+    };
+
+    // SOCKS4 has no passwords
+    if auth.is_some() {
+        socks4_reply(local, false).await?;
+        return Err(auth_failed());
+    }
+    if cmd != 1 {
+        socks4_reply(local, false).await?;
+        return Err(invalid_data("unsupported SOCKS4 command"));
+    }
+
+    Ok(Request {
+        host,
+        port,
+        protocol: Protocol::Socks4,
+    })
+}
+
+async fn socks4_reply<L>(local: &mut L, granted: bool) -> Result<(), IoError>
+where
+    L: AsyncWrite + Unpin,
+{
+    let status = if granted { 0x5a } else { 0x5b };
+    local.write_all(&[0, status, 0, 0, 0, 0, 0, 0]).await?;
+    local.flush().await
+}
+
+//
+async fn accept_http_connect<L>(
+    local: &mut L,
+    auth: Option<&(String, String)>,
+) -> Result<Request, IoError>
+where
+    L: AsyncBufRead + AsyncWrite + Unpin,
+{
+    let mut lines = vec![];
+    let mut len = 0;
+    loop {
+        let mut line = String::new();
+        let n = (&mut *local)
+            .take((HTTP_HEADERS_MAX - len) as u64)
+            .read_line(&mut line)
+            .await?;
+        len += n;
+        if n == 0 || !line.ends_with('\n') {
+            return Err(invalid_data("HTTP CONNECT headers too long or cut off"));
+        }
+        let line = line.trim_end().to_owned();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let mut request_line = lines.first().map(|x| x.split(' ')).into_iter().flatten();
+    let (method, authority) = (request_line.next(), request_line.next());
+    if method != Some("CONNECT") {
+        http_reply(local, "405 Method Not Allowed", "").await?;
+        return Err(invalid_data("not an HTTP CONNECT request"));
+    }
+    let (host, port) = match authority.and_then(parse_authority) {
+        Some(x) => x,
+        None => {
+            http_reply(local, "400 Bad Request", "").await?;
+            return Err(invalid_data("bad HTTP CONNECT authority"));
+        }
+    };
+
+    if let Some((username, password)) = auth {
+        let expected = format!("Basic {}", BASE64.encode(format!("{username}:{password}")));
+        let authorized = lines[1..].iter().any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.eq_ignore_ascii_case("proxy-authorization") && value.trim() == expected
+            })
+        });
+        if !authorized {
+            http_reply(
+                local,
+                "407 Proxy Authentication Required",
+                "Proxy-Authenticate: Basic realm=\"async-ssh2-lite\"\r\n",
+            )
+            .await?;
+            return Err(auth_failed());
+        }
+    }
+
+    Ok(Request {
+        host,
+        port,
+        protocol: Protocol::HttpConnect,
+    })
+}
+
+/// `host:port` or `[v6]:port`.
+fn parse_authority(authority: &str) -> Option<(String, u16)> {
+    let (host, port) = authority.rsplit_once(':')?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.into(), port.parse().ok()?))
+}
+
+async fn http_reply<L>(local: &mut L, status: &str, headers: &str) -> Result<(), IoError>
+where
+    L: AsyncWrite + Unpin,
+{
+    local
+        .write_all(format!("HTTP/1.1 {status}\r\n{headers}\r\n").as_bytes())
+        .await?;
+    local.flush().await
+}
+
+//
+async fn read_u8<L>(local: &mut L) -> Result<u8, IoError>
+where
+    L: AsyncRead + Unpin,
+{
+    let mut buf = [0; 1];
+    local.read_exact(&mut buf).await?;
+    Ok(buf[0])
+}
+
+async fn read_u16<L>(local: &mut L) -> Result<u16, IoError>
+where
+    L: AsyncRead + Unpin,
+{
+    let mut buf = [0; 2];
+    local.read_exact(&mut buf).await?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+async fn read_u8_prefixed<L>(local: &mut L) -> Result<Vec<u8>, IoError>
+where
+    L: AsyncRead + Unpin,
+{
+    let n = read_u8(local).await?;
+    let mut buf = vec![0; n as usize];
+    local.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn read_nul_terminated<L>(local: &mut L) -> Result<Vec<u8>, IoError>
+where
+    L: AsyncBufRead + Unpin,
+{
+    let mut buf = vec![];
+    (&mut *local).take(256).read_until(0, &mut buf).await?;
+    if buf.pop() != Some(0) {
+        return Err(invalid_data("SOCKS4 string too long or cut off"));
+    }
+    Ok(buf)
+}
+
+fn invalid_data(msg: &str) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, msg)
+}
+
+fn auth_failed() -> IoError {
+    IoError::new(IoErrorKind::PermissionDenied, "proxy authentication failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use core::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use futures_util::io::{BufReader, Cursor};
+
+    /// Gives the client input and keeps what the proxy answers.
+    struct Client {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl AsyncRead for Client {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize, IoError>> {
+            Pin::new(&mut self.get_mut().input).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Client {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, IoError>> {
+            Pin::new(&mut self.get_mut().output).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    async fn accept_with(
+        input: &[u8],
+        auth: Option<&(String, String)>,
+    ) -> (Result<Request, IoError>, Vec<u8>) {
+        let mut local = BufReader::new(Client {
+            input: Cursor::new(input.to_vec()),
+            output: vec![],
+        });
+        let ret = accept(&mut local, auth).await;
+        (ret, local.into_inner().output)
+    }
+
+    #[test]
+    fn test_accept() {
+        futures_lite::future::block_on(async {
+            // SOCKS5 domain name
+            let (ret, output) =
+                accept_with(b"\x05\x01\x00\x05\x01\x00\x03\x07example\x01\xbb", None).await;
+            let request = ret.unwrap();
+            assert_eq!((request.host.as_str(), request.port), ("example", 443));
+            assert_eq!(output, b"\x05\x00");
+
+            // SOCKS5 username/password, wrong password
+            let auth = ("user".to_owned(), "pass".to_owned());
+            let (ret, output) = accept_with(b"\x05\x01\x02\x01\x04user\x04nope", Some(&auth)).await;
+            assert_eq!(ret.unwrap_err().kind(), IoErrorKind::PermissionDenied);
+            assert_eq!(output, b"\x05\x02\x01\x01");
+
+            // SOCKS5 needs auth but the client offers none
+            let (ret, output) = accept_with(b"\x05\x01\x00", Some(&auth)).await;
+            assert!(ret.is_err());
+            assert_eq!(output, b"\x05\xff");
+
+            // SOCKS4a
+            let (ret, _) =
+                accept_with(b"\x04\x01\x00\x50\x00\x00\x00\x01me\x00example\x00", None).await;
+            let request = ret.unwrap();
+            assert_eq!((request.host.as_str(), request.port), ("example", 80));
+
+            // HTTP CONNECT with Basic auth
+            let (ret, _) = accept_with(
+                b"CONNECT [::1]:22 HTTP/1.1\r\nHost: [::1]:22\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n",
+                Some(&auth),
+            )
+            .await;
+            let request = ret.unwrap();
+            assert_eq!((request.host.as_str(), request.port), ("::1", 22));
+
+            let (ret, output) = accept_with(b"GET / HTTP/1.1\r\n\r\n", None).await;
+            assert!(ret.is_err());
+            assert!(output.starts_with(b"HTTP/1.1 405"));
+        })
+    }
+}
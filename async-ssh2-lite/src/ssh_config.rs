@@ -0,0 +1,914 @@
+//! OpenSSH client configuration, see `man 5 ssh_config`.
+
+use core::time::Duration;
+use std::{
+    fs,
+    io::ErrorKind as IoErrorKind,
+    path::{Path, PathBuf},
+};
+
+use ssh2::MethodType;
+
+use crate::{
+    error::Error,
+    host_key::HostKeyPolicy,
+    session::SessionConfiguration,
+    util::{home_dir, local_user},
+};
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+//
+#[derive(Debug, Clone, Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone)]
+struct Block {
+    conditions: Vec<Condition>,
+    options: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    Host(Vec<String>),
+    Match(Vec<Criterion>),
+}
+
+#[derive(Debug, Clone)]
+struct Criterion {
+    negated: bool,
+    kind: CriterionKind,
+}
+
+#[derive(Debug, Clone)]
+enum CriterionKind {
+    All,
+    Host(String),
+    OriginalHost(String),
+    User(String),
+    LocalUser(String),
+    Unsupported,
+}
+
+/// Relative `Include` paths are resolved against `~/.ssh`.
+impl core::str::FromStr for SshConfig {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut blocks = vec![Block {
+            conditions: vec![],
+            options: vec![],
+        }];
+        parse(s, None, &[], &mut blocks, 0)?;
+        Ok(Self { blocks })
+    }
+}
+
+impl SshConfig {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path)?;
+
+        let mut blocks = vec![Block {
+            conditions: vec![],
+            options: vec![],
+        }];
+        parse(&s, Some(path), &[], &mut blocks, 0)?;
+        Ok(Self { blocks })
+    }
+
+    pub async fn read_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        blocking::unblock(move || Self::from_file(path)).await
+    }
+
+    /// Reads `~/.ssh/config`, a missing file gives an empty configuration.
+    pub async fn read_default() -> Result<Self, Error> {
+        let path = match home_dir() {
+            Some(home) => home.join(".ssh").join("config"),
+            None => return Ok(Self::default()),
+        };
+        match Self::read_file(path).await {
+            Ok(config) => Ok(config),
+            Err(Error::Io(err)) if err.kind() == IoErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn resolve(&self, host: &str) -> ResolvedSshConfig {
+        let mut resolved = self.resolve_host(host);
+        resolved.proxy_jump_configs = resolved
+            .proxy_jump
+            .iter()
+            .map(|jump| {
+                let mut jump_resolved = self.resolve_host(&jump.host);
+                if let Some(user) = jump.user.as_ref() {
+                    jump_resolved.user = Some(user.to_owned());
+                }
+                if let Some(port) = jump.port {
+                    jump_resolved.port = port;
+                }
+                // Only the hosts of the outermost ProxyJump are chained
+                jump_resolved.proxy_jump.clear();
+                jump_resolved.proxy_command = None;
+                jump_resolved
+            })
+            .collect();
+
+        resolved
+    }
+
+    fn resolve_host(&self, host: &str) -> ResolvedSshConfig {
+        let local_user = local_user();
+        let mut resolved = ResolvedSshConfig::new(host);
+        let mut seen = vec![];
+
+        for block in &self.blocks {
+            if !block
+                .conditions
+                .iter()
+                .all(|condition| condition.matches(host, &resolved, &local_user))
+            {
+                continue;
+            }
+
+            for (keyword, args) in &block.options {
+                if keyword == "identityfile" {
+                    if let Some(arg) = args.first().filter(|arg| *arg != "none") {
+                        let path = expand_tilde(&expand_tokens(arg, host, &resolved, &local_user));
+                        if !resolved.identity_files.contains(&path) {
+                            resolved.identity_files.push(path);
+                        }
+                    }
+                    continue;
+                }
+
+                // For all other options the first obtained value is used
+                if seen.contains(keyword) {
+                    continue;
+                }
+                if resolved.apply(keyword, args, host, &local_user) {
+                    seen.push(keyword.to_owned());
+                }
+            }
+        }
+
+        if resolved.host_name.is_empty() {
+            resolved.host_name = host.to_owned();
+        }
+        if let Some(proxy_command) = resolved.proxy_command.take() {
+            resolved.proxy_command =
+                Some(expand_tokens(&proxy_command, host, &resolved, &local_user));
+        }
+
+        resolved
+    }
+}
+
+//
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ResolvedSshConfig {
+    /// The host as given to `SshConfig::resolve`.
+    pub host: String,
+    pub host_name: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub identity_files: Vec<PathBuf>,
+    pub identities_only: bool,
+    pub proxy_jump: Vec<ProxyJumpHost>,
+    /// Each of `proxy_jump` resolved like a host of its own, what `connect_with_ssh_config`
+    /// goes through. Their own ProxyJump and ProxyCommand are not used.
+    pub proxy_jump_configs: Vec<ResolvedSshConfig>,
+    /// Tokens are expanded, run it with `proxy_command::shell_command`.
+    pub proxy_command: Option<String>,
+    pub connect_timeout: Option<Duration>,
+    pub server_alive_interval: Option<Duration>,
+    pub server_alive_count_max: u32,
+    pub ciphers: Option<String>,
+    pub kex_algorithms: Option<String>,
+    pub compression: Option<bool>,
+    pub strict_host_key_checking: Option<HostKeyPolicy>,
+    pub user_known_hosts_files: Vec<PathBuf>,
+    pub global_known_hosts_files: Vec<PathBuf>,
+    pub hash_known_hosts: Option<bool>,
+}
+
+impl ResolvedSshConfig {
+    fn new(host: &str) -> Self {
+        Self {
+            host: host.to_owned(),
+            host_name: String::new(),
+            port: 22,
+            user: None,
+            identity_files: vec![],
+            identities_only: false,
+            proxy_jump: vec![],
+            proxy_jump_configs: vec![],
+            proxy_command: None,
+            connect_timeout: None,
+            server_alive_interval: None,
+            server_alive_count_max: 3,
+            ciphers: None,
+            kex_algorithms: None,
+            compression: None,
+            strict_host_key_checking: None,
+            user_known_hosts_files: vec![],
+            global_known_hosts_files: vec![],
+            hash_known_hosts: None,
+        }
+    }
+
+    fn apply(&mut self, keyword: &str, args: &[String], host: &str, local_user: &str) -> bool {
+        let arg = match args.first() {
+            Some(arg) => arg.as_str(),
+            None => return false,
+        };
+
+        match keyword {
+            "hostname" => self.host_name = expand_tokens(arg, host, self, local_user),
+            "port" => match arg.parse() {
+                Ok(port) => self.port = port,
+                Err(_) => return false,
+            },
+            "user" => self.user = Some(arg.to_owned()),
+            "identitiesonly" => self.identities_only = parse_yes_no(arg),
+            // Whichever of ProxyJump and ProxyCommand comes first wins
+            "proxyjump" => {
+                if arg != "none" && self.proxy_command.is_none() {
+                    self.proxy_jump = arg.split(',').filter_map(ProxyJumpHost::parse).collect();
+                }
+            }
+            "proxycommand" => {
+                if arg != "none" && self.proxy_jump.is_empty() {
+                    self.proxy_command = Some(arg.to_owned());
+                }
+            }
+            "connecttimeout" => self.connect_timeout = parse_secs(arg),
+            "serveraliveinterval" => {
+                self.server_alive_interval = parse_secs(arg).filter(|dur| !dur.is_zero())
+            }
+            "serveralivecountmax" => match arg.parse() {
+                Ok(n) => self.server_alive_count_max = n,
+                Err(_) => return false,
+            },
+            "ciphers" => self.ciphers = method_prefs(arg),
+            "kexalgorithms" => self.kex_algorithms = method_prefs(arg),
+            "compression" => self.compression = Some(parse_yes_no(arg)),
+            "stricthostkeychecking" => {
+                self.strict_host_key_checking = Some(match arg.to_ascii_lowercase().as_str() {
+                    "accept-new" => HostKeyPolicy::AcceptNew,
+                    "no" | "off" => HostKeyPolicy::Insecure,
+                    // "ask" cannot prompt here
+                    _ => HostKeyPolicy::Strict,
+                })
+            }
+            "userknownhostsfile" => {
+                self.user_known_hosts_files = known_hosts_files(args, host, self, local_user)
+            }
+            "globalknownhostsfile" => {
+                self.global_known_hosts_files = known_hosts_files(args, host, self, local_user)
+            }
+            "hashknownhosts" => self.hash_known_hosts = Some(parse_yes_no(arg)),
+            _ => return false,
+        }
+
+        true
+    }
+
+    pub fn session_configuration(&self) -> SessionConfiguration {
+        let mut configuration = SessionConfiguration::new();
+
+        if let Some(connect_timeout) = self.connect_timeout {
+            configuration.set_connect_timeout(connect_timeout);
+        }
+        if let Some(interval) = self.server_alive_interval {
+            configuration.set_keepalive(true, interval.as_secs() as u32);
+            configuration.set_keepalive_count_max(self.server_alive_count_max);
+        }
+        if let Some(compression) = self.compression {
+            configuration.set_compress(compression);
+        }
+        if let Some(ciphers) = self.ciphers.as_deref() {
+            configuration.set_method_pref(MethodType::CryptCs, ciphers);
+            configuration.set_method_pref(MethodType::CryptSc, ciphers);
+        }
+        if let Some(kex_algorithms) = self.kex_algorithms.as_deref() {
+            configuration.set_method_pref(MethodType::Kex, kex_algorithms);
+        }
+        if let Some(policy) = self.strict_host_key_checking.as_ref() {
+            configuration.set_host_key_policy(policy.to_owned());
+        }
+        if !self.user_known_hosts_files.is_empty() || !self.global_known_hosts_files.is_empty() {
+            configuration.set_known_hosts_files(
+                self.user_known_hosts_files
+                    .iter()
+                    .chain(self.global_known_hosts_files.iter())
+                    .cloned(),
+            );
+        }
+        if let Some(hash_known_hosts) = self.hash_known_hosts {
+            configuration.set_hash_known_hosts(hash_known_hosts);
+        }
+
+        configuration
+    }
+}
+
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyJumpHost {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl ProxyJumpHost {
+    /// `[user@]host[:port]` or `ssh://[user@]host[:port]`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let s = s.strip_prefix("ssh://").unwrap_or(s);
+        let (user, s) = match s.rsplit_once('@') {
+            Some((user, s)) => (Some(user.to_owned()), s),
+            None => (None, s),
+        };
+
+        let (host, port) = if let Some(s) = s.strip_prefix('[') {
+            // [::1]:22
+            let (host, rest) = s.split_once(']')?;
+            (host, rest.strip_prefix(':'))
+        } else if s.matches(':').count() == 1 {
+            let (host, port) = s.split_once(':')?;
+            (host, Some(port))
+        } else {
+            (s, None)
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let port = match port {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+
+        Some(Self {
+            user,
+            host: host.to_owned(),
+            port,
+        })
+    }
+}
+
+//
+impl Condition {
+    fn matches(&self, host: &str, resolved: &ResolvedSshConfig, local_user: &str) -> bool {
+        match self {
+            Self::Host(patterns) => match_host_patterns(patterns.iter().map(String::as_str), host),
+            Self::Match(criteria) => criteria.iter().all(|criterion| {
+                let host_name = if resolved.host_name.is_empty() {
+                    host
+                } else {
+                    resolved.host_name.as_str()
+                };
+                let user = resolved.user.as_deref().unwrap_or(local_user);
+
+                let matched = match &criterion.kind {
+                    CriterionKind::All => true,
+                    CriterionKind::Host(patterns) => {
+                        match_host_patterns(patterns.split(','), host_name)
+                    }
+                    CriterionKind::OriginalHost(patterns) => {
+                        match_host_patterns(patterns.split(','), host)
+                    }
+                    CriterionKind::User(patterns) => match_patterns(patterns.split(','), user),
+                    CriterionKind::LocalUser(patterns) => {
+                        match_patterns(patterns.split(','), local_user)
+                    }
+                    // exec, canonical, final, etc. are never matched
+                    CriterionKind::Unsupported => return false,
+                };
+                matched != criterion.negated
+            }),
+        }
+    }
+}
+
+fn match_host_patterns<'a>(patterns: impl Iterator<Item = &'a str>, host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.to_ascii_lowercase();
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if wildcard_match(pattern, &host) {
+                return false;
+            }
+        } else if wildcard_match(&pattern, &host) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+fn match_patterns<'a>(patterns: impl Iterator<Item = &'a str>, s: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if wildcard_match(pattern, s) {
+                return false;
+            }
+        } else if wildcard_match(pattern, s) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+/// `*` matches zero or more characters, `?` matches exactly one.
+fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+
+    let (mut p, mut i) = (0, 0);
+    let mut backtrack = None;
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, i));
+            p += 1;
+        } else if let Some((bp, bi)) = backtrack {
+            p = bp + 1;
+            i = bi + 1;
+            backtrack = Some((bp, bi + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+//
+fn parse(
+    s: &str,
+    path: Option<&Path>,
+    parent_conditions: &[Condition],
+    blocks: &mut Vec<Block>,
+    depth: usize,
+) -> Result<(), Error> {
+    let location = |n: usize| match path {
+        Some(path) => format!("{}:{}", path.display(), n + 1),
+        None => format!("line {}", n + 1),
+    };
+
+    for (n, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, rest) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+            Some(i) => (&line[..i], line[i..].trim_start()),
+            None => (line, ""),
+        };
+        let rest = rest.strip_prefix('=').unwrap_or(rest);
+        let keyword = keyword.to_ascii_lowercase();
+        // ProxyCommand is handed to the shell as is
+        let args = if keyword == "proxycommand" {
+            vec![rest.to_owned()]
+        } else {
+            split_args(rest)
+                .ok_or_else(|| Error::Other(format!("{} unterminated quote", location(n)).into()))?
+        };
+
+        match keyword.as_str() {
+            "host" => {
+                if args.is_empty() {
+                    return Err(Error::Other(
+                        format!("{} Host requires a pattern", location(n)).into(),
+                    ));
+                }
+                let mut conditions = parent_conditions.to_vec();
+                conditions.push(Condition::Host(args));
+                blocks.push(Block {
+                    conditions,
+                    options: vec![],
+                });
+            }
+            "match" => {
+                let criteria = parse_match(&args).ok_or_else(|| {
+                    Error::Other(format!("{} invalid Match criteria", location(n)).into())
+                })?;
+                let mut conditions = parent_conditions.to_vec();
+                conditions.push(Condition::Match(criteria));
+                blocks.push(Block {
+                    conditions,
+                    options: vec![],
+                });
+            }
+            "include" => {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(Error::Other(
+                        format!("{} Include nested too deeply", location(n)).into(),
+                    ));
+                }
+
+                let conditions = blocks
+                    .last()
+                    .map(|block| block.conditions.to_owned())
+                    .unwrap_or_default();
+                for arg in &args {
+                    let pattern = expand_tilde(arg);
+                    let pattern = if pattern.is_absolute() {
+                        pattern
+                    } else {
+                        match home_dir() {
+                            Some(home) => home.join(".ssh").join(pattern),
+                            None => pattern,
+                        }
+                    };
+
+                    for include_path in glob(&pattern) {
+                        let content = match fs::read_to_string(&include_path) {
+                            Ok(content) => content,
+                            Err(err) if err.kind() == IoErrorKind::NotFound => continue,
+                            Err(err) => return Err(err.into()),
+                        };
+                        blocks.push(Block {
+                            conditions: conditions.to_owned(),
+                            options: vec![],
+                        });
+                        parse(
+                            &content,
+                            Some(&include_path),
+                            &conditions,
+                            blocks,
+                            depth + 1,
+                        )?;
+                    }
+                }
+
+                // Options after the Include still belong to the enclosing block
+                blocks.push(Block {
+                    conditions,
+                    options: vec![],
+                });
+            }
+            _ => {
+                if let Some(block) = blocks.last_mut() {
+                    block.options.push((keyword, args));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_match(args: &[String]) -> Option<Vec<Criterion>> {
+    let mut criteria = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (negated, name) = match arg.strip_prefix('!') {
+            Some(name) => (true, name),
+            None => (false, arg.as_str()),
+        };
+        let name = name.to_ascii_lowercase();
+        let kind = match name.as_str() {
+            "all" => CriterionKind::All,
+            "canonical" | "final" => CriterionKind::Unsupported,
+            _ => {
+                let value = args.next()?.to_owned();
+                match name.as_str() {
+                    "host" => CriterionKind::Host(value),
+                    "originalhost" => CriterionKind::OriginalHost(value),
+                    "user" => CriterionKind::User(value),
+                    "localuser" => CriterionKind::LocalUser(value),
+                    _ => CriterionKind::Unsupported,
+                }
+            }
+        };
+        criteria.push(Criterion { negated, kind });
+    }
+    Some(criteria)
+}
+
+fn split_args(s: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.peek().map(|c| c.is_whitespace()) == Some(true) {
+            chars.next();
+        }
+        match chars.peek() {
+            None | Some('#') => break,
+            Some('"') => {
+                chars.next();
+                let mut arg = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => arg.push(c),
+                        None => return None,
+                    }
+                }
+                args.push(arg);
+            }
+            Some(_) => {
+                let mut arg = String::new();
+                while let Some(c) = chars.peek().copied() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    arg.push(c);
+                    chars.next();
+                }
+                args.push(arg);
+            }
+        }
+    }
+    Some(args)
+}
+
+//
+fn glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let component = component.as_os_str();
+        let component_str = component.to_string_lossy();
+        if !component_str.contains(['*', '?']) {
+            for path in paths.iter_mut() {
+                path.push(component);
+            }
+            continue;
+        }
+
+        let mut next = vec![];
+        for path in &paths {
+            let entries = match fs::read_dir(path) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            let mut names = entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.') || component_str.starts_with('.'))
+                .filter(|name| wildcard_match(&component_str, name))
+                .collect::<Vec<_>>();
+            names.sort();
+            next.extend(names.into_iter().map(|name| path.join(name)));
+        }
+        paths = next;
+    }
+    paths
+}
+
+fn expand_tokens(s: &str, host: &str, resolved: &ResolvedSshConfig, local_user: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => ret.push('%'),
+            Some('h') => ret.push_str(if resolved.host_name.is_empty() {
+                host
+            } else {
+                &resolved.host_name
+            }),
+            Some('n') => ret.push_str(host),
+            Some('p') => ret.push_str(&resolved.port.to_string()),
+            Some('r') => ret.push_str(resolved.user.as_deref().unwrap_or(local_user)),
+            Some('u') => ret.push_str(local_user),
+            Some('d') => {
+                if let Some(home) = home_dir() {
+                    ret.push_str(&home.to_string_lossy())
+                }
+            }
+            Some(c) => {
+                ret.push('%');
+                ret.push(c);
+            }
+            None => ret.push('%'),
+        }
+    }
+    ret
+}
+
+fn expand_tilde(s: &str) -> PathBuf {
+    match (s.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ if s == "~" => home_dir().unwrap_or_else(|| PathBuf::from(s)),
+        _ => PathBuf::from(s),
+    }
+}
+
+fn known_hosts_files(
+    args: &[String],
+    host: &str,
+    resolved: &ResolvedSshConfig,
+    local_user: &str,
+) -> Vec<PathBuf> {
+    args.iter()
+        .filter(|arg| *arg != "none")
+        .map(|arg| expand_tilde(&expand_tokens(arg, host, resolved, local_user)))
+        .collect()
+}
+
+fn method_prefs(s: &str) -> Option<String> {
+    // +, - modify the default list, which libssh2 cannot express, keep the libssh2 defaults
+    if s.starts_with(['+', '-']) {
+        return None;
+    }
+    Some(s.strip_prefix('^').unwrap_or(s).to_owned())
+}
+
+fn parse_yes_no(s: &str) -> bool {
+    matches!(s.to_ascii_lowercase().as_str(), "yes" | "true")
+}
+
+fn parse_secs(s: &str) -> Option<Duration> {
+    s.parse::<u64>().ok().map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("*", "foo"));
+        assert!(wildcard_match("*.example.com", "db1.example.com"));
+        assert!(!wildcard_match("*.example.com", "example.com"));
+        assert!(wildcard_match("db?", "db1"));
+        assert!(!wildcard_match("db?", "db12"));
+        assert!(wildcard_match("a*b*c", "axxbyyc"));
+        assert!(!wildcard_match("a*b*c", "axxbyy"));
+    }
+
+    #[test]
+    fn test_resolve() -> Result<(), Box<dyn std::error::Error>> {
+        let config: SshConfig = r#"
+# comment
+Host db1 db2
+    HostName %h.internal
+    Port 2222
+    IdentityFile /keys/db
+    ProxyJump bastion@jump.example.com:2200,[::1]:22
+
+Host *.internal !secret.internal
+    User deploy
+
+Host=web
+    HostName="web.example.com"
+    ConnectTimeout 5
+    ProxyCommand sh -c "exec nc %h %p"
+
+Match originalhost web
+    User www
+
+Host *
+    User root
+    IdentityFile /keys/default
+    IdentitiesOnly yes
+    ServerAliveInterval 15
+    ServerAliveCountMax 2
+    Ciphers ^aes256-ctr,aes128-ctr
+    KexAlgorithms +diffie-hellman-group14-sha1
+    StrictHostKeyChecking accept-new
+"#
+        .parse()?;
+
+        let resolved = config.resolve("db1");
+        assert_eq!(resolved.host_name, "db1.internal");
+        assert_eq!(resolved.port, 2222);
+        assert_eq!(resolved.user.as_deref(), Some("root"));
+        assert_eq!(
+            resolved.identity_files,
+            vec![PathBuf::from("/keys/db"), PathBuf::from("/keys/default")]
+        );
+        assert!(resolved.identities_only);
+        assert_eq!(
+            resolved.proxy_jump,
+            vec![
+                ProxyJumpHost {
+                    user: Some("bastion".into()),
+                    host: "jump.example.com".into(),
+                    port: Some(2200)
+                },
+                ProxyJumpHost {
+                    user: None,
+                    host: "::1".into(),
+                    port: Some(22)
+                }
+            ]
+        );
+        assert_eq!(resolved.proxy_jump_configs[0].host_name, "jump.example.com");
+        assert_eq!(resolved.proxy_jump_configs[0].port, 2200);
+        assert_eq!(
+            resolved.proxy_jump_configs[0].user.as_deref(),
+            Some("bastion")
+        );
+        assert_eq!(resolved.proxy_jump_configs[1].user.as_deref(), Some("root"));
+        assert!(resolved.proxy_jump_configs[1].proxy_jump.is_empty());
+        assert_eq!(
+            resolved.server_alive_interval,
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(resolved.server_alive_count_max, 2);
+        assert_eq!(resolved.ciphers.as_deref(), Some("aes256-ctr,aes128-ctr"));
+        assert_eq!(resolved.kex_algorithms, None);
+        assert!(matches!(
+            resolved.strict_host_key_checking,
+            Some(HostKeyPolicy::AcceptNew)
+        ));
+
+        let resolved = config.resolve("foo.internal");
+        assert_eq!(resolved.host_name, "foo.internal");
+        assert_eq!(resolved.port, 22);
+        assert_eq!(resolved.user.as_deref(), Some("deploy"));
+
+        let resolved = config.resolve("secret.internal");
+        assert_eq!(resolved.user.as_deref(), Some("root"));
+
+        let resolved = config.resolve("web");
+        assert_eq!(resolved.host_name, "web.example.com");
+        assert_eq!(resolved.user.as_deref(), Some("www"));
+        assert_eq!(resolved.connect_timeout, Some(Duration::from_secs(5)));
+        assert!(resolved.proxy_jump.is_empty());
+        assert_eq!(
+            resolved.proxy_command.as_deref(),
+            Some(r#"sh -c "exec nc web.example.com 22""#)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_include() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("config.d"))?;
+        fs::write(
+            dir.path().join("config.d").join("a.conf"),
+            "Host a\n    Port 2201\nHost *\n    User included\n",
+        )?;
+        fs::write(dir.path().join("config.d").join("b.conf"), "Port 2202\n")?;
+
+        let config: SshConfig = format!(
+            "Host b\n    Include {}/config.d/*.conf\n    User b\nHost *\n    User default\n",
+            dir.path().display()
+        )
+        .parse()?;
+
+        // Included inside `Host b`, so `Host a` never applies
+        let resolved = config.resolve("a");
+        assert_eq!(resolved.port, 22);
+        assert_eq!(resolved.user.as_deref(), Some("default"));
+
+        let resolved = config.resolve("b");
+        assert_eq!(resolved.port, 2202);
+        assert_eq!(resolved.user.as_deref(), Some("included"));
+
+        let resolved = config.resolve("c");
+        assert_eq!(resolved.port, 22);
+        assert_eq!(resolved.user.as_deref(), Some("default"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_proxy_jump_or_proxy_command() -> Result<(), Box<dyn std::error::Error>> {
+        let config: SshConfig = r#"
+Host a
+    ProxyJump jump
+Host b
+    ProxyCommand nc %h %p
+Host *
+    ProxyCommand nc -x proxy %h %p
+    ProxyJump other
+"#
+        .parse()?;
+
+        let resolved = config.resolve("a");
+        assert_eq!(resolved.proxy_jump_configs.len(), 1);
+        assert_eq!(resolved.proxy_command, None);
+
+        let resolved = config.resolve("b");
+        assert!(resolved.proxy_jump.is_empty());
+        assert_eq!(resolved.proxy_command.as_deref(), Some("nc b 22"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!("Host\n".parse::<SshConfig>().is_err());
+        assert!("HostName \"foo\n".parse::<SshConfig>().is_err());
+        assert!("Match host\n".parse::<SshConfig>().is_err());
+    }
+}
@@ -0,0 +1,1179 @@
+//! Copying files and directory trees over SFTP, see `AsyncSftp::upload` and
+//! `AsyncSftp::upload_dir`.
+
+use core::{fmt, ops::Range, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{Error as IoError, ErrorKind as IoErrorKind, Read as _, Seek as _, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use blocking::Unblock;
+use futures_util::{
+    future,
+    io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _},
+    stream::{self, StreamExt as _},
+};
+use libssh2_sys::LIBSSH2_FX_NO_SUCH_FILE;
+use ssh2::{FileStat, FileType, OpenFlags, OpenType};
+
+use crate::{
+    error::{Error, ErrorKind},
+    session_stream::AsyncSessionStream,
+    sftp::AsyncSftp,
+};
+
+const CHUNK_SIZE_DEFAULT: usize = 256 * 1024;
+const CONCURRENCY_DEFAULT: usize = 4;
+
+//
+/// How `AsyncSftp::upload` and `AsyncSftp::download` copy a file.
+///
+/// Each handle copies its own range of the file, and libssh2 splits every chunk into several
+/// requests that are in flight together, so a round trip is paid per chunk and not per request.
+#[derive(Clone)]
+pub struct TransferConfiguration {
+    chunk_size: usize,
+    concurrency: usize,
+    preserve: bool,
+    resume: bool,
+    resume_verify_len: u64,
+    on_progress: Option<OnProgress>,
+}
+
+type OnProgress = Arc<dyn Fn(&TransferProgress) + Send + Sync>;
+
+impl Default for TransferConfiguration {
+    fn default() -> Self {
+        Self {
+            chunk_size: CHUNK_SIZE_DEFAULT,
+            concurrency: CONCURRENCY_DEFAULT,
+            preserve: true,
+            resume: false,
+            resume_verify_len: 0,
+            on_progress: None,
+        }
+    }
+}
+
+impl TransferConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Bytes per read or write, 256KiB by default.
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    /// Handles copying at the same time, 4 by default.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /// Copies the mode and the access and modification times, on by default.
+    pub fn set_preserve(&mut self, preserve: bool) {
+        self.preserve = preserve;
+    }
+
+    /// Continues from the size of the destination instead of starting over. Copies with one
+    /// handle so the destination is always a prefix of the source, only destinations left by
+    /// transfers with resume on can be continued.
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+
+    /// Compares the last `len` bytes of the destination with the source before resuming, and
+    /// starts over when they differ.
+    pub fn set_resume_verify_len(&mut self, len: u64) {
+        self.resume_verify_len = len;
+    }
+
+    /// Called after every chunk, from whichever handle copied it.
+    pub fn set_on_progress<F>(&mut self, on_progress: F)
+    where
+        F: Fn(&TransferProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+    }
+}
+
+impl fmt::Debug for TransferConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransferConfiguration")
+            .field("chunk_size", &self.chunk_size)
+            .field("concurrency", &self.concurrency)
+            .field("preserve", &self.preserve)
+            .field("resume", &self.resume)
+            .field("resume_verify_len", &self.resume_verify_len)
+            .field("on_progress", &self.on_progress.is_some())
+            .finish()
+    }
+}
+
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// Bytes copied so far, including those kept by a resumed transfer.
+    pub transferred: u64,
+    /// Size of the file.
+    pub total: u64,
+}
+
+struct Progress<'a> {
+    transferred: AtomicU64,
+    total: u64,
+    on_progress: Option<&'a OnProgress>,
+}
+
+impl Progress<'_> {
+    fn add(&self, n: u64) {
+        let transferred = self.transferred.fetch_add(n, Ordering::SeqCst) + n;
+        if let Some(on_progress) = self.on_progress {
+            on_progress(&TransferProgress {
+                transferred,
+                total: self.total,
+            });
+        }
+    }
+}
+
+//
+pub(crate) async fn upload<S>(
+    sftp: &AsyncSftp<S>,
+    local_path: &Path,
+    remote_path: &Path,
+    configuration: &TransferConfiguration,
+) -> Result<u64, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let metadata = {
+        let local_path = local_path.to_owned();
+        blocking::unblock(move || fs::metadata(local_path)).await?
+    };
+    let total = metadata.len();
+
+    let offset = if configuration.resume {
+        let len = match sftp.stat(remote_path).await {
+            Ok(stat) => stat.size.unwrap_or(0),
+            Err(err) if err.kind() == ErrorKind::SftpStatus(LIBSSH2_FX_NO_SUCH_FILE) => 0,
+            Err(err) => return Err(err),
+        };
+        resume_offset(sftp, local_path, remote_path, len, total, configuration).await?
+    } else {
+        0
+    };
+    if offset == 0 {
+        let mut remote = sftp
+            .open_mode(
+                remote_path,
+                OpenFlags::WRITE | OpenFlags::TRUNCATE,
+                mode(&metadata).unwrap_or(0o644) as i32,
+                OpenType::File,
+            )
+            .await?;
+        remote.close().await?;
+    }
+
+    let progress = Progress {
+        transferred: AtomicU64::new(offset),
+        total,
+        on_progress: configuration.on_progress.as_ref(),
+    };
+    future::try_join_all(
+        ranges(offset..total, configuration)
+            .into_iter()
+            .map(|range| {
+                upload_range(
+                    sftp,
+                    local_path,
+                    remote_path,
+                    range,
+                    configuration.chunk_size,
+                    &progress,
+                )
+            }),
+    )
+    .await?;
+
+    if configuration.preserve {
+        sftp.setstat(
+            remote_path,
+            FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: mode(&metadata),
+                atime: metadata.accessed().ok().and_then(unix_secs),
+                mtime: metadata.modified().ok().and_then(unix_secs),
+            },
+        )
+        .await?;
+    }
+
+    let size = sftp.stat(remote_path).await?.size;
+    if size != Some(total) {
+        return Err(size_mismatch(remote_path, size, total));
+    }
+
+    Ok(total)
+}
+
+async fn upload_range<S>(
+    sftp: &AsyncSftp<S>,
+    local_path: &Path,
+    remote_path: &Path,
+    range: Range<u64>,
+    chunk_size: usize,
+    progress: &Progress<'_>,
+) -> Result<(), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let local = {
+        let local_path = local_path.to_owned();
+        blocking::unblock(move || fs::File::open(local_path)).await?
+    };
+    let mut local = Unblock::with_capacity(chunk_size, local);
+    local.seek(SeekFrom::Start(range.start)).await?;
+
+    let mut remote = sftp
+        .open_mode(remote_path, OpenFlags::WRITE, 0o644, OpenType::File)
+        .await?;
+    remote.seek(SeekFrom::Start(range.start)).await?;
+
+    let mut buf = vec![0; chunk_size];
+    let mut pos = range.start;
+    while pos < range.end {
+        let n = ((range.end - pos) as usize).min(chunk_size);
+        local.read_exact(&mut buf[..n]).await?;
+        remote.write_all(&buf[..n]).await?;
+        pos += n as u64;
+        progress.add(n as u64);
+    }
+    remote.close().await?;
+
+    Ok(())
+}
+
+//
+pub(crate) async fn download<S>(
+    sftp: &AsyncSftp<S>,
+    remote_path: &Path,
+    local_path: &Path,
+    configuration: &TransferConfiguration,
+) -> Result<u64, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let stat = sftp.stat(remote_path).await?;
+    let total = stat.size.ok_or_else(|| {
+        Error::Other(format!("size of {} is unknown", remote_path.display()).into())
+    })?;
+
+    let offset = if configuration.resume {
+        let len = {
+            let local_path = local_path.to_owned();
+            blocking::unblock(move || match fs::metadata(local_path) {
+                Ok(metadata) => Ok(metadata.len()),
+                Err(err) if err.kind() == IoErrorKind::NotFound => Ok(0),
+                Err(err) => Err(err),
+            })
+            .await?
+        };
+        resume_offset(sftp, local_path, remote_path, len, total, configuration).await?
+    } else {
+        0
+    };
+    {
+        let local_path = local_path.to_owned();
+        // A resumed download grows the file as it goes, see `ranges`
+        let resume = configuration.resume;
+        let len = if resume { offset } else { total };
+        blocking::unblock(move || {
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(!resume)
+                .open(local_path)?
+                .set_len(len)
+        })
+        .await?;
+    }
+
+    let progress = Progress {
+        transferred: AtomicU64::new(offset),
+        total,
+        on_progress: configuration.on_progress.as_ref(),
+    };
+    future::try_join_all(
+        ranges(offset..total, configuration)
+            .into_iter()
+            .map(|range| {
+                download_range(
+                    sftp,
+                    remote_path,
+                    local_path,
+                    range,
+                    configuration.chunk_size,
+                    &progress,
+                )
+            }),
+    )
+    .await?;
+
+    // The ranges only cover the size seen before the copy
+    let after = sftp.stat(remote_path).await?;
+    if after.size != Some(total) || after.mtime != stat.mtime {
+        return Err(changed(remote_path));
+    }
+
+    let size = {
+        let local_path = local_path.to_owned();
+        let preserve = configuration.preserve;
+        blocking::unblock(move || {
+            let file = fs::OpenOptions::new().write(true).open(local_path)?;
+            if preserve {
+                set_local_stat(&file, &stat)?;
+            }
+            Ok::<_, IoError>(file.metadata()?.len())
+        })
+        .await?
+    };
+    if size != total {
+        return Err(size_mismatch(local_path, Some(size), total));
+    }
+
+    Ok(total)
+}
+
+async fn download_range<S>(
+    sftp: &AsyncSftp<S>,
+    remote_path: &Path,
+    local_path: &Path,
+    range: Range<u64>,
+    chunk_size: usize,
+    progress: &Progress<'_>,
+) -> Result<(), Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut remote = sftp.open(remote_path).await?;
+    remote.seek(SeekFrom::Start(range.start)).await?;
+
+    let local = {
+        let local_path = local_path.to_owned();
+        blocking::unblock(move || fs::OpenOptions::new().write(true).open(local_path)).await?
+    };
+    let mut local = Unblock::with_capacity(chunk_size, local);
+    local.seek(SeekFrom::Start(range.start)).await?;
+
+    let mut buf = vec![0; chunk_size];
+    let mut pos = range.start;
+    while pos < range.end {
+        let n = ((range.end - pos) as usize).min(chunk_size);
+        remote
+            .read_exact(&mut buf[..n])
+            .await
+            .map_err(|err| match err.kind() {
+                IoErrorKind::UnexpectedEof => changed(remote_path),
+                _ => err.into(),
+            })?;
+        local.write_all(&buf[..n]).await?;
+        pos += n as u64;
+        progress.add(n as u64);
+    }
+    local.flush().await?;
+    remote.close().await?;
+
+    Ok(())
+}
+
+//
+/// Where a resumed transfer continues, given the size of the destination, 0 to start over.
+async fn resume_offset<S>(
+    sftp: &AsyncSftp<S>,
+    local_path: &Path,
+    remote_path: &Path,
+    len: u64,
+    total: u64,
+    configuration: &TransferConfiguration,
+) -> Result<u64, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    if len > total {
+        return Ok(0);
+    }
+
+    let verify_len = configuration.resume_verify_len.min(len);
+    if verify_len > 0 {
+        let range = len - verify_len..len;
+        let local = {
+            let local_path = local_path.to_owned();
+            let range = range.clone();
+            blocking::unblock(move || {
+                let mut file = fs::File::open(local_path)?;
+                file.seek(SeekFrom::Start(range.start))?;
+                let mut buf = vec![0; (range.end - range.start) as usize];
+                file.read_exact(&mut buf)?;
+                Ok::<_, IoError>(buf)
+            })
+            .await?
+        };
+
+        let mut remote = sftp.open(remote_path).await?;
+        remote.seek(SeekFrom::Start(range.start)).await?;
+        let mut buf = vec![0; (range.end - range.start) as usize];
+        remote.read_exact(&mut buf).await?;
+        remote.close().await?;
+
+        if local != buf {
+            return Ok(0);
+        }
+    }
+
+    Ok(len)
+}
+
+/// Splits `range` into at most `concurrency` ranges of whole chunks, a single one when resuming.
+fn ranges(range: Range<u64>, configuration: &TransferConfiguration) -> Vec<Range<u64>> {
+    let concurrency = if configuration.resume {
+        1
+    } else {
+        configuration.concurrency as u64
+    };
+    let chunk_size = configuration.chunk_size as u64;
+    let chunks = (range.end - range.start).div_ceil(chunk_size);
+    let per_range = chunks.div_ceil(concurrency).max(1) * chunk_size;
+
+    range
+        .clone()
+        .step_by(per_range as usize)
+        .map(|start| start..(start + per_range).min(range.end))
+        .collect()
+}
+
+fn size_mismatch(path: &Path, size: Option<u64>, expected: u64) -> Error {
+    Error::Other(
+        format!(
+            "size of {} is {size:?} after the transfer, expected {expected}",
+            path.display()
+        )
+        .into(),
+    )
+}
+
+fn changed(path: &Path) -> Error {
+    Error::Other(format!("{} changed during the transfer", path.display()).into())
+}
+
+fn unix_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|dur| dur.as_secs())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+fn set_local_stat(file: &fs::File, stat: &FileStat) -> Result<(), IoError> {
+    #[cfg(unix)]
+    if let Some(perm) = stat.perm {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        file.set_permissions(fs::Permissions::from_mode(perm & 0o7777))?;
+    }
+
+    let mut times = fs::FileTimes::new();
+    if let Some(atime) = stat.atime {
+        times = times.set_accessed(UNIX_EPOCH + Duration::from_secs(atime));
+    }
+    if let Some(mtime) = stat.mtime {
+        times = times.set_modified(UNIX_EPOCH + Duration::from_secs(mtime));
+    }
+    file.set_times(times)
+}
+
+//
+/// What `AsyncSftp::upload_dir` and `AsyncSftp::download_dir` do with symlinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Recreates the link with the same target, fails where the platform has no symlinks.
+    #[default]
+    Preserve,
+    /// Copies what the link points to, links to a directory already copied are skipped.
+    Follow,
+    Skip,
+}
+
+/// How `AsyncSftp::upload_dir` and `AsyncSftp::download_dir` copy a tree.
+#[derive(Debug, Clone)]
+pub struct DirTransferConfiguration {
+    file: TransferConfiguration,
+    concurrency: usize,
+    sync: bool,
+    delete: bool,
+    symlinks: SymlinkPolicy,
+}
+
+impl Default for DirTransferConfiguration {
+    fn default() -> Self {
+        Self {
+            file: Default::default(),
+            concurrency: CONCURRENCY_DEFAULT,
+            sync: false,
+            delete: false,
+            symlinks: Default::default(),
+        }
+    }
+}
+
+impl DirTransferConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// How each file is copied, every file copying at the same time has its own handles.
+    pub fn set_file_configuration(&mut self, configuration: TransferConfiguration) {
+        self.file = configuration;
+    }
+
+    /// Files copying at the same time, 4 by default.
+    pub fn set_concurrency(&mut self, concurrency: usize) {
+        self.concurrency = concurrency.max(1);
+    }
+
+    /// Skips files whose destination has the same size and modification time, like rsync. The
+    /// times have to be preserved for this, see `TransferConfiguration::set_preserve`.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Removes what is in the destination but not in the source.
+    pub fn set_delete(&mut self, delete: bool) {
+        self.delete = delete;
+    }
+
+    pub fn set_symlink_policy(&mut self, symlinks: SymlinkPolicy) {
+        self.symlinks = symlinks;
+    }
+}
+
+/// What a directory transfer did, with paths relative to the roots.
+#[derive(Debug, Default)]
+pub struct DirTransferReport {
+    /// Files and symlinks.
+    pub copied: Vec<PathBuf>,
+    /// Files unchanged since the last sync, and what the symlink policy or the file type left out.
+    pub skipped: Vec<PathBuf>,
+    /// See `DirTransferConfiguration::set_delete`.
+    pub deleted: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, Error)>,
+}
+
+impl DirTransferReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Entry {
+    Dir {
+        perm: Option<u32>,
+    },
+    File {
+        size: u64,
+        mtime: Option<u64>,
+    },
+    Symlink {
+        target: PathBuf,
+    },
+    /// Left alone, e.g. sockets or symlinks skipped by the policy.
+    Other,
+}
+
+/// By relative path, a directory comes before what it contains.
+type Tree = BTreeMap<PathBuf, Entry>;
+
+type Failed = Vec<(PathBuf, Error)>;
+
+enum Outcome {
+    Copied,
+    Skipped,
+}
+
+#[derive(Clone, Copy)]
+enum Direction<'a> {
+    Upload { local: &'a Path, remote: &'a Path },
+    Download { remote: &'a Path, local: &'a Path },
+}
+
+pub(crate) async fn upload_dir<S>(
+    sftp: &AsyncSftp<S>,
+    local_dir: &Path,
+    remote_dir: &Path,
+    configuration: &DirTransferConfiguration,
+) -> Result<DirTransferReport, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    transfer_dir(
+        sftp,
+        Direction::Upload {
+            local: local_dir,
+            remote: remote_dir,
+        },
+        configuration,
+    )
+    .await
+}
+
+pub(crate) async fn download_dir<S>(
+    sftp: &AsyncSftp<S>,
+    remote_dir: &Path,
+    local_dir: &Path,
+    configuration: &DirTransferConfiguration,
+) -> Result<DirTransferReport, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    transfer_dir(
+        sftp,
+        Direction::Download {
+            remote: remote_dir,
+            local: local_dir,
+        },
+        configuration,
+    )
+    .await
+}
+
+async fn transfer_dir<S>(
+    sftp: &AsyncSftp<S>,
+    direction: Direction<'_>,
+    configuration: &DirTransferConfiguration,
+) -> Result<DirTransferReport, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let (source, source_failed) = direction.source_tree(sftp, configuration.symlinks).await?;
+    let (destination, destination_failed) = direction.destination_tree(sftp).await?;
+
+    let mut report = DirTransferReport::default();
+    // Whatever could not be read is kept out of the deletion
+    let unreadable = source_failed
+        .iter()
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    report.failed.extend(source_failed);
+    report.failed.extend(destination_failed);
+
+    for (path, entry) in &source {
+        if let Entry::Dir { perm } = entry {
+            if !matches!(destination.get(path), Some(Entry::Dir { .. })) {
+                if let Err(err) = direction.mkdir(sftp, path, *perm).await {
+                    report.failed.push((path.clone(), err));
+                }
+            }
+        }
+    }
+
+    // Collected up front, closures over references in the stream would make the future not `Send`
+    let destination = &destination;
+    let copies = source
+        .iter()
+        .filter(|(_, entry)| !matches!(entry, Entry::Dir { .. }))
+        .map(|(path, entry)| async move {
+            let ret = copy_entry(
+                sftp,
+                direction,
+                path,
+                entry,
+                destination.get(path),
+                configuration,
+            )
+            .await;
+            (path, ret)
+        })
+        .collect::<Vec<_>>();
+    let outcomes = stream::iter(copies)
+        .buffer_unordered(configuration.concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    for (path, ret) in outcomes {
+        match ret {
+            Ok(Outcome::Copied) => report.copied.push(path.clone()),
+            Ok(Outcome::Skipped) => report.skipped.push(path.clone()),
+            Err(err) => report.failed.push((path.clone(), err)),
+        }
+    }
+
+    if configuration.delete {
+        // What a directory contains comes first
+        for (path, entry) in destination.iter().rev() {
+            if source.contains_key(path) || unreadable.iter().any(|x| path.starts_with(x)) {
+                continue;
+            }
+            match direction.remove(sftp, path, entry).await {
+                Ok(()) => report.deleted.push(path.clone()),
+                Err(err) => report.failed.push((path.clone(), err)),
+            }
+        }
+    }
+
+    report.copied.sort();
+    report.skipped.sort();
+    Ok(report)
+}
+
+async fn copy_entry<S>(
+    sftp: &AsyncSftp<S>,
+    direction: Direction<'_>,
+    path: &Path,
+    entry: &Entry,
+    destination: Option<&Entry>,
+    configuration: &DirTransferConfiguration,
+) -> Result<Outcome, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    // Without a modification time an unchanged file cannot be told apart
+    let unchanged = destination == Some(entry) && !matches!(entry, Entry::File { mtime: None, .. });
+    if configuration.sync && unchanged {
+        return Ok(Outcome::Skipped);
+    }
+
+    match entry {
+        Entry::File { .. } => {
+            // Writing through a symlink would change its target
+            if let Some(destination @ (Entry::Symlink { .. } | Entry::Other)) = destination {
+                direction.remove(sftp, path, destination).await?;
+            }
+            direction.copy_file(sftp, path, &configuration.file).await?;
+        }
+        Entry::Symlink { target } => {
+            if let Some(destination) = destination {
+                direction.remove(sftp, path, destination).await?;
+            }
+            direction.symlink(sftp, path, target).await?;
+        }
+        Entry::Dir { .. } | Entry::Other => return Ok(Outcome::Skipped),
+    }
+    Ok(Outcome::Copied)
+}
+
+impl Direction<'_> {
+    async fn source_tree<S>(
+        &self,
+        sftp: &AsyncSftp<S>,
+        symlinks: SymlinkPolicy,
+    ) -> Result<(Tree, Failed), Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        match *self {
+            Self::Upload { local, .. } => {
+                let local = local.to_owned();
+                Ok(blocking::unblock(move || {
+                    if !fs::metadata(&local)?.is_dir() {
+                        return Err(not_a_dir(&local));
+                    }
+                    Ok(local_tree(&local, symlinks))
+                })
+                .await?)
+            }
+            Self::Download { remote, .. } => {
+                if !sftp.stat(remote).await?.is_dir() {
+                    return Err(not_a_dir(remote).into());
+                }
+                Ok(remote_tree(sftp, remote, symlinks).await)
+            }
+        }
+    }
+
+    /// Creates the root when missing.
+    async fn destination_tree<S>(&self, sftp: &AsyncSftp<S>) -> Result<(Tree, Failed), Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        match *self {
+            Self::Upload { remote, .. } => match sftp.stat(remote).await {
+                Ok(_) => Ok(remote_tree(sftp, remote, SymlinkPolicy::Preserve).await),
+                Err(err) if err.kind() == ErrorKind::SftpStatus(LIBSSH2_FX_NO_SUCH_FILE) => {
+                    sftp.mkdir(remote, 0o755).await?;
+                    Ok(Default::default())
+                }
+                Err(err) => Err(err),
+            },
+            Self::Download { local, .. } => {
+                let local = local.to_owned();
+                Ok(blocking::unblock(move || {
+                    if local.exists() {
+                        Ok::<_, IoError>(local_tree(&local, SymlinkPolicy::Preserve))
+                    } else {
+                        fs::create_dir_all(&local)?;
+                        Ok(Default::default())
+                    }
+                })
+                .await?)
+            }
+        }
+    }
+
+    async fn mkdir<S>(
+        &self,
+        sftp: &AsyncSftp<S>,
+        path: &Path,
+        perm: Option<u32>,
+    ) -> Result<(), Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        // Writable by the owner, the transfer has to fill it
+        let mode = perm.unwrap_or(0o755) | 0o700;
+        match *self {
+            Self::Upload { remote, .. } => sftp.mkdir(&remote.join(path), mode as i32).await,
+            Self::Download { local, .. } => {
+                let path = local.join(path);
+                Ok(blocking::unblock(move || {
+                    let mut builder = fs::DirBuilder::new();
+                    #[cfg(unix)]
+                    std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode);
+                    builder.create(path)
+                })
+                .await?)
+            }
+        }
+    }
+
+    async fn copy_file<S>(
+        &self,
+        sftp: &AsyncSftp<S>,
+        path: &Path,
+        configuration: &TransferConfiguration,
+    ) -> Result<u64, Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        match *self {
+            Self::Upload { local, remote } => {
+                upload(sftp, &local.join(path), &remote.join(path), configuration).await
+            }
+            Self::Download { remote, local } => {
+                download(sftp, &remote.join(path), &local.join(path), configuration).await
+            }
+        }
+    }
+
+    async fn symlink<S>(&self, sftp: &AsyncSftp<S>, path: &Path, target: &Path) -> Result<(), Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        match *self {
+            // The link is the second argument, see `ssh2::Sftp::symlink`
+            Self::Upload { remote, .. } => sftp.symlink(target, &remote.join(path)).await,
+            Self::Download { local, .. } => {
+                let path = local.join(path);
+                let target = target.to_owned();
+                Ok(blocking::unblock(move || local_symlink(&target, &path)).await?)
+            }
+        }
+    }
+
+    async fn remove<S>(&self, sftp: &AsyncSftp<S>, path: &Path, entry: &Entry) -> Result<(), Error>
+    where
+        S: AsyncSessionStream + Send + Sync + 'static,
+    {
+        match (*self, entry) {
+            (Self::Upload { remote, .. }, Entry::Dir { .. }) => {
+                sftp.rmdir(&remote.join(path)).await
+            }
+            (Self::Upload { remote, .. }, _) => sftp.unlink(&remote.join(path)).await,
+            (Self::Download { local, .. }, entry) => {
+                let path = local.join(path);
+                let is_dir = matches!(entry, Entry::Dir { .. });
+                Ok(blocking::unblock(move || {
+                    if is_dir {
+                        fs::remove_dir(path)
+                    } else {
+                        fs::remove_file(path)
+                    }
+                })
+                .await?)
+            }
+        }
+    }
+}
+
+fn local_tree(root: &Path, symlinks: SymlinkPolicy) -> (Tree, Failed) {
+    let mut tree = Tree::new();
+    let mut failed = Failed::new();
+    let mut visited = fs::canonicalize(root).into_iter().collect::<HashSet<_>>();
+
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let read_dir = match fs::read_dir(root.join(&dir)) {
+            Ok(read_dir) => read_dir,
+            Err(err) => {
+                failed.push((dir, err.into()));
+                continue;
+            }
+        };
+        for item in read_dir {
+            let path = match item {
+                Ok(item) => dir.join(item.file_name()),
+                Err(err) => {
+                    failed.push((dir.clone(), err.into()));
+                    continue;
+                }
+            };
+            let entry = match local_entry(&root.join(&path), symlinks) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    failed.push((path, err.into()));
+                    continue;
+                }
+            };
+            if let Entry::Dir { .. } = entry {
+                // Followed symlinks may loop
+                let seen = match fs::canonicalize(root.join(&path)) {
+                    Ok(real) => !visited.insert(real),
+                    Err(_) => false,
+                };
+                if seen {
+                    tree.insert(path, Entry::Other);
+                    continue;
+                }
+                dirs.push(path.clone());
+            }
+            tree.insert(path, entry);
+        }
+    }
+
+    (tree, failed)
+}
+
+fn local_entry(path: &Path, symlinks: SymlinkPolicy) -> Result<Entry, IoError> {
+    let mut metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        match symlinks {
+            SymlinkPolicy::Preserve => {
+                return Ok(Entry::Symlink {
+                    target: fs::read_link(path)?,
+                })
+            }
+            SymlinkPolicy::Follow => metadata = fs::metadata(path)?,
+            SymlinkPolicy::Skip => return Ok(Entry::Other),
+        }
+    }
+
+    Ok(if metadata.is_dir() {
+        Entry::Dir {
+            perm: mode(&metadata),
+        }
+    } else if metadata.is_file() {
+        Entry::File {
+            size: metadata.len(),
+            mtime: metadata.modified().ok().and_then(unix_secs),
+        }
+    } else {
+        Entry::Other
+    })
+}
+
+async fn remote_tree<S>(sftp: &AsyncSftp<S>, root: &Path, symlinks: SymlinkPolicy) -> (Tree, Failed)
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let mut tree = Tree::new();
+    let mut failed = Failed::new();
+    let mut visited = sftp
+        .realpath(root)
+        .await
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let items = match sftp.readdir(&root.join(&dir)).await {
+            Ok(items) => items,
+            Err(err) => {
+                failed.push((dir, err));
+                continue;
+            }
+        };
+        for (remote_path, stat) in items {
+            let path = match remote_path.file_name() {
+                Some(name) => dir.join(name),
+                None => continue,
+            };
+            let entry = match remote_entry(sftp, &remote_path, stat, symlinks).await {
+                Ok(entry) => entry,
+                Err(err) => {
+                    failed.push((path, err));
+                    continue;
+                }
+            };
+            if let Entry::Dir { .. } = entry {
+                let seen = match sftp.realpath(&remote_path).await {
+                    Ok(real) => !visited.insert(real),
+                    Err(_) => false,
+                };
+                if seen {
+                    tree.insert(path, Entry::Other);
+                    continue;
+                }
+                dirs.push(path.clone());
+            }
+            tree.insert(path, entry);
+        }
+    }
+
+    (tree, failed)
+}
+
+async fn remote_entry<S>(
+    sftp: &AsyncSftp<S>,
+    path: &Path,
+    mut stat: FileStat,
+    symlinks: SymlinkPolicy,
+) -> Result<Entry, Error>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    if stat.file_type() == FileType::Symlink {
+        match symlinks {
+            SymlinkPolicy::Preserve => {
+                return Ok(Entry::Symlink {
+                    target: sftp.readlink(path).await?,
+                })
+            }
+            SymlinkPolicy::Follow => stat = sftp.stat(path).await?,
+            SymlinkPolicy::Skip => return Ok(Entry::Other),
+        }
+    }
+
+    Ok(match stat.file_type() {
+        FileType::Directory => Entry::Dir {
+            perm: stat.perm.map(|perm| perm & 0o7777),
+        },
+        FileType::RegularFile => Entry::File {
+            size: stat.size.unwrap_or(0),
+            mtime: stat.mtime,
+        },
+        _ => Entry::Other,
+    })
+}
+
+#[cfg(unix)]
+fn local_symlink(target: &Path, path: &Path) -> Result<(), IoError> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(not(unix))]
+fn local_symlink(_target: &Path, _path: &Path) -> Result<(), IoError> {
+    Err(IoError::new(
+        IoErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+fn not_a_dir(path: &Path) -> IoError {
+    IoError::new(
+        IoErrorKind::NotADirectory,
+        format!("{} is not a directory", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        let configuration = |concurrency| {
+            let mut configuration = TransferConfiguration::new();
+            configuration.set_chunk_size(4);
+            configuration.set_concurrency(concurrency);
+            configuration
+        };
+        assert_eq!(ranges(0..0, &configuration(4)), vec![]);
+        assert_eq!(ranges(0..3, &configuration(4)), vec![0..3]);
+        assert_eq!(ranges(0..10, &configuration(4)), vec![0..4, 4..8, 8..10]);
+        assert_eq!(ranges(0..10, &configuration(2)), vec![0..8, 8..10]);
+        assert_eq!(ranges(0..10, &configuration(1)), vec![0..10]);
+        assert_eq!(ranges(0..16, &configuration(3)), vec![0..8, 8..16]);
+        assert_eq!(
+            ranges(6..16, &configuration(4)),
+            vec![6..10, 10..14, 14..16]
+        );
+
+        let mut resume = configuration(4);
+        resume.set_resume(true);
+        assert_eq!(ranges(6..16, &resume), vec![6..16]);
+        assert_eq!(ranges(16..16, &resume), vec![]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_local_tree() -> Result<(), IoError> {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir(dir.path().join("a"))?;
+        fs::write(dir.path().join("a/f"), b"foo")?;
+        std::os::unix::fs::symlink("a", dir.path().join("l"))?;
+        std::os::unix::fs::symlink(".", dir.path().join("a/loop"))?;
+
+        let (tree, failed) = local_tree(dir.path(), SymlinkPolicy::Preserve);
+        assert!(failed.is_empty());
+        assert_eq!(tree.keys().collect::<Vec<_>>(), ["a", "a/f", "a/loop", "l"]);
+        assert!(matches!(tree[Path::new("a")], Entry::Dir { .. }));
+        assert!(matches!(
+            tree[Path::new("a/f")],
+            Entry::File { size: 3, .. }
+        ));
+        assert_eq!(
+            tree[Path::new("l")],
+            Entry::Symlink {
+                target: PathBuf::from("a")
+            }
+        );
+
+        // Either "a" or "l" is walked, the other one and the loop are left alone
+        let (tree, failed) = local_tree(dir.path(), SymlinkPolicy::Follow);
+        assert!(failed.is_empty());
+        let dirs = tree
+            .values()
+            .filter(|entry| matches!(entry, Entry::Dir { .. }))
+            .count();
+        let files = tree
+            .values()
+            .filter(|entry| matches!(entry, Entry::File { .. }))
+            .count();
+        assert_eq!((dirs, files), (1, 1));
+
+        let (tree, _) = local_tree(dir.path(), SymlinkPolicy::Skip);
+        assert_eq!(tree[Path::new("l")], Entry::Other);
+        assert_eq!(tree[Path::new("a/loop")], Entry::Other);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Runs a session over an arbitrary I/O object by pumping it through a socketpair.
+//!
+//! libssh2 needs a socket, so the session gets one end of a socketpair and a background task copies
+//! between the other end and the I/O object. The task ends when the session side is closed, that is when
+//! the session and every channel, sftp etc. created from it are dropped, or when the I/O object reaches EOF.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::io::Error as IoError;
+
+use futures_util::io::{AsyncRead, AsyncWrite, AsyncWriteExt as _};
+
+use crate::util::CopyBuffer;
+
+//
+pub(crate) async fn pump<A, B>(a: &mut A, b: &mut B) -> Result<(), IoError>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let ret = Pump {
+        a: &mut *a,
+        b: &mut *b,
+        a_to_b: CopyBuffer::new(),
+        b_to_a: CopyBuffer::new(),
+    }
+    .await;
+
+    let _ = a.close().await;
+    let _ = b.close().await;
+
+    ret
+}
+
+struct Pump<'a, A, B> {
+    a: &'a mut A,
+    b: &'a mut B,
+    a_to_b: CopyBuffer,
+    b_to_a: CopyBuffer,
+}
+
+impl<A, B> core::future::Future for Pump<'_, A, B>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    type Output = Result<(), IoError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+
+        // Either side reaching EOF ends the transport
+        match this
+            .a_to_b
+            .poll_copy(cx, Pin::new(&mut *this.a), Pin::new(&mut *this.b))?
+        {
+            Poll::Ready(_) => return Poll::Ready(Ok(())),
+            Poll::Pending => {}
+        }
+        match this
+            .b_to_a
+            .poll_copy(cx, Pin::new(&mut *this.b), Pin::new(&mut *this.a))?
+        {
+            Poll::Ready(_) => return Poll::Ready(Ok(())),
+            Poll::Pending => {}
+        }
+
+        Poll::Pending
+    }
+}
+
+//
+#[cfg(all(unix, feature = "tokio"))]
+pub(crate) use impl_tokio::spawn_with_tokio;
+
+#[cfg(all(unix, feature = "tokio"))]
+mod impl_tokio {
+    use std::{io::Error as IoError, os::unix::net::UnixStream as StdUnixStream};
+
+    use futures_util::io::{AsyncRead, AsyncWrite};
+
+    use super::pump;
+    use crate::{util::TokioCompat, TokioUnixStream};
+
+    //
+    pub(crate) fn spawn_with_tokio<IO>(mut io: IO) -> Result<TokioUnixStream, IoError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let handle = tokio::runtime::Handle::try_current().map_err(IoError::other)?;
+
+        let (stream, peer) = StdUnixStream::pair()?;
+        stream.set_nonblocking(true)?;
+        peer.set_nonblocking(true)?;
+        let stream = TokioUnixStream::from_std(stream)?;
+        let mut peer = TokioCompat(TokioUnixStream::from_std(peer)?);
+
+        handle.spawn(async move {
+            let _ = pump(&mut io, &mut peer).await;
+        });
+
+        Ok(stream)
+    }
+}
+
+//
+#[cfg(all(unix, feature = "async-io"))]
+pub(crate) use impl_async_io::spawn_with_async_io;
+
+#[cfg(all(unix, feature = "async-io"))]
+mod impl_async_io {
+    use std::{io::Error as IoError, os::unix::net::UnixStream as StdUnixStream};
+
+    use futures_util::io::{AsyncRead, AsyncWrite};
+
+    use super::pump;
+    use crate::{runtime::Runtime, AsyncIoUnixStream};
+
+    //
+    /// The pump runs on the executor shared by async-io tasks of this crate.
+    pub(crate) fn spawn_with_async_io<IO>(mut io: IO) -> Result<AsyncIoUnixStream, IoError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (stream, peer) = StdUnixStream::pair()?;
+        let stream = AsyncIoUnixStream::new(stream)?;
+        let mut peer = AsyncIoUnixStream::new(peer)?;
+
+        Runtime::AsyncIo.spawn(async move {
+            let _ = pump(&mut io, &mut peer).await;
+        })?;
+
+        Ok(stream)
+    }
+}
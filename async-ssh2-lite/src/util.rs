@@ -1,8 +1,19 @@
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
 use std::{
+    env,
     io::{Error as IoError, ErrorKind as IoErrorKind},
     net::SocketAddr,
+    path::PathBuf,
 };
 
+use futures_util::{
+    future::poll_fn,
+    io::{AsyncRead, AsyncWrite},
+    ready, AsyncWriteExt as _,
+};
 use ssh2::Error as Ssh2Error;
 
 //
@@ -10,6 +21,19 @@ pub fn ssh2_error_is_would_block(err: &Ssh2Error) -> bool {
     IoError::from(Ssh2Error::from_errno(err.code())).kind() == IoErrorKind::WouldBlock
 }
 
+//
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+pub(crate) fn local_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
 //
 #[derive(Debug, Clone)]
 pub enum ConnectInfo {
@@ -28,3 +52,237 @@ impl ConnectInfo {
         Self::Unix(path.as_ref().into())
     }
 }
+
+//
+/// Per direction, the largest channel data payload libssh2 sends in one packet.
+const COPY_BUF_SIZE: usize = 32 * 1024;
+
+/// Copies `a` to `b` and `b` to `a` until both reach EOF, closing each writer once its reader
+/// is done, which sends EOF on an `AsyncChannel`. On error both sides are closed. Returns the
+/// bytes moved from `a` to `b` and from `b` to `a`.
+pub async fn copy_bidirectional<A, B>(a: &mut A, b: &mut B) -> Result<(u64, u64), IoError>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut a_to_b = CopyBuffer::new();
+    let mut b_to_a = CopyBuffer::new();
+    let (mut a_to_b_done, mut b_to_a_done) = (None, None);
+
+    let ret = poll_fn(|cx| {
+        if a_to_b_done.is_none() {
+            if let Poll::Ready(n) = a_to_b.poll_copy(cx, Pin::new(&mut *a), Pin::new(&mut *b))? {
+                a_to_b_done = Some(n);
+            }
+        }
+        if b_to_a_done.is_none() {
+            if let Poll::Ready(n) = b_to_a.poll_copy(cx, Pin::new(&mut *b), Pin::new(&mut *a))? {
+                b_to_a_done = Some(n);
+            }
+        }
+        match (a_to_b_done, b_to_a_done) {
+            (Some(a_to_b), Some(b_to_a)) => Poll::Ready(Ok((a_to_b, b_to_a))),
+            _ => Poll::Pending,
+        }
+    })
+    .await;
+
+    if ret.is_err() {
+        let _ = a.close().await;
+        let _ = b.close().await;
+    }
+    ret
+}
+
+/// `copy_bidirectional` for tokio streams, e.g. an `AsyncChannel` and a `TokioTcpStream`.
+#[cfg(feature = "tokio")]
+pub async fn copy_bidirectional_with_tokio<A, B>(
+    a: &mut A,
+    b: &mut B,
+) -> Result<(u64, u64), IoError>
+where
+    A: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + ?Sized,
+    B: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + ?Sized,
+{
+    copy_bidirectional(&mut TokioCompat(&mut *a), &mut TokioCompat(&mut *b)).await
+}
+
+/// One direction of `copy_bidirectional`, also used by the transport pump.
+pub(crate) struct CopyBuffer {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    amt: u64,
+    read_done: bool,
+    need_flush: bool,
+}
+
+impl CopyBuffer {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: vec![0; COPY_BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            amt: 0,
+            read_done: false,
+            need_flush: false,
+        }
+    }
+
+    /// Ready with the bytes copied once the reader is at EOF and the writer closed.
+    pub(crate) fn poll_copy<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<Result<u64, IoError>>
+    where
+        R: AsyncRead + ?Sized,
+        W: AsyncWrite + ?Sized,
+    {
+        loop {
+            if self.pos == self.cap && !self.read_done {
+                match reader.as_mut().poll_read(cx, &mut self.buf) {
+                    Poll::Ready(Ok(0)) => self.read_done = true,
+                    Poll::Ready(Ok(n)) => {
+                        self.pos = 0;
+                        self.cap = n;
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => {
+                        // Flush while waiting, so nothing sits in a writer buffer
+                        if self.need_flush {
+                            ready!(writer.as_mut().poll_flush(cx))?;
+                            self.need_flush = false;
+                        }
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                let n = ready!(writer
+                    .as_mut()
+                    .poll_write(cx, &self.buf[self.pos..self.cap]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(IoErrorKind::WriteZero.into()));
+                }
+                self.pos += n;
+                self.amt += n as u64;
+                self.need_flush = true;
+            }
+
+            if self.pos == self.cap && self.read_done {
+                ready!(writer.as_mut().poll_close(cx))?;
+                return Poll::Ready(Ok(self.amt));
+            }
+        }
+    }
+}
+
+/// tokio I/O as futures I/O.
+#[cfg(feature = "tokio")]
+pub(crate) struct TokioCompat<T>(pub(crate) T);
+
+#[cfg(feature = "tokio")]
+impl<T> AsyncRead for TokioCompat<T>
+where
+    T: tokio::io::AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, IoError>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.get_mut().0).poll_read(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> AsyncWrite for TokioCompat<T>
+where
+    T: tokio::io::AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.get_mut().0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.get_mut().0).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.get_mut().0).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures_util::io::Cursor;
+
+    /// Reads from one cursor and writes into another, like one end of a connection.
+    struct Duplex {
+        reader: Cursor<Vec<u8>>,
+        writer: Vec<u8>,
+        closed: bool,
+    }
+
+    impl AsyncRead for Duplex {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<Result<usize, IoError>> {
+            Pin::new(&mut self.get_mut().reader).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Duplex {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize, IoError>> {
+            Pin::new(&mut self.get_mut().writer).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+            self.get_mut().closed = true;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn test_copy_bidirectional() {
+        futures_lite::future::block_on(async {
+            let mut a = Duplex {
+                reader: Cursor::new(vec![1; 100 * 1024]),
+                writer: vec![],
+                closed: false,
+            };
+            let mut b = Duplex {
+                reader: Cursor::new(b"foo".to_vec()),
+                writer: vec![],
+                closed: false,
+            };
+
+            let (a_to_b, b_to_a) = copy_bidirectional(&mut a, &mut b).await.unwrap();
+            assert_eq!((a_to_b, b_to_a), (100 * 1024, 3));
+            assert_eq!(b.writer, vec![1; 100 * 1024]);
+            assert_eq!(a.writer, b"foo");
+            assert!(a.closed && b.closed);
+        });
+    }
+}
@@ -0,0 +1,204 @@
+//! Walking remote directory trees, see `AsyncSftp::walk`.
+
+use core::fmt;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use futures_util::stream::{self, BoxStream, StreamExt as _};
+use ssh2::FileStat;
+
+use crate::{
+    error::Error,
+    session_stream::AsyncSessionStream,
+    sftp::{AsyncSftp, ReadDirStream},
+};
+
+/// See `AsyncSftp::walk`.
+pub type WalkStream<'a> = BoxStream<'a, Result<WalkEntry, Error>>;
+
+//
+/// How `AsyncSftp::walk` visits a tree.
+#[derive(Clone, Default)]
+pub struct WalkConfiguration {
+    min_depth: usize,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    filter_entry: Option<FilterEntry>,
+}
+
+type FilterEntry = Arc<dyn Fn(&WalkEntry) -> bool + Send + Sync>;
+
+impl WalkConfiguration {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Entries above this depth are visited but not yielded, 0 (the root) by default.
+    pub fn set_min_depth(&mut self, min_depth: usize) {
+        self.min_depth = min_depth;
+    }
+
+    /// Entries below this depth are not visited, unlimited by default.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = Some(max_depth);
+    }
+
+    /// Descends into symlinks to directories, off by default. Directories already visited are
+    /// not descended into again. The root is always followed.
+    pub fn set_follow_symlinks(&mut self, follow_symlinks: bool) {
+        self.follow_symlinks = follow_symlinks;
+    }
+
+    /// Entries for which `f` returns false are neither yielded nor descended into. To only
+    /// drop entries from the output, use `TryStreamExt::try_filter` on the stream.
+    pub fn set_filter_entry<F>(&mut self, f: F)
+    where
+        F: Fn(&WalkEntry) -> bool + Send + Sync + 'static,
+    {
+        self.filter_entry = Some(Arc::new(f));
+    }
+}
+
+impl fmt::Debug for WalkConfiguration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WalkConfiguration")
+            .field("min_depth", &self.min_depth)
+            .field("max_depth", &self.max_depth)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("filter_entry", &self.filter_entry.is_some())
+            .finish()
+    }
+}
+
+//
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    /// Of the link itself, unless symlinks are followed.
+    pub stat: FileStat,
+    /// 0 for the root.
+    pub depth: usize,
+}
+
+//
+struct Walk<'a, S> {
+    sftp: &'a AsyncSftp<S>,
+    configuration: WalkConfiguration,
+    root: Option<PathBuf>,
+    // One open directory per level, with the depth of its entries
+    stack: Vec<(ReadDirStream, usize)>,
+    error: Option<Error>,
+    visited: HashSet<PathBuf>,
+}
+
+impl<S> Walk<'_, S>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    async fn next(&mut self) -> Option<Result<WalkEntry, Error>> {
+        loop {
+            if let Some(err) = self.error.take() {
+                return Some(Err(err));
+            }
+
+            let entry = if let Some(root) = self.root.take() {
+                match self.sftp.stat(&root).await {
+                    Ok(stat) => WalkEntry {
+                        path: root,
+                        stat,
+                        depth: 0,
+                    },
+                    Err(err) => return Some(Err(err)),
+                }
+            } else {
+                let (dir, depth) = self.stack.last_mut()?;
+                let depth = *depth;
+                match dir.next().await {
+                    Some(Ok((path, stat))) => {
+                        // A dangling link is yielded as the link
+                        let stat = if self.configuration.follow_symlinks
+                            && stat.file_type().is_symlink()
+                        {
+                            self.sftp.stat(&path).await.unwrap_or(stat)
+                        } else {
+                            stat
+                        };
+                        WalkEntry { path, stat, depth }
+                    }
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                }
+            };
+
+            if let Some(filter_entry) = &self.configuration.filter_entry {
+                if !filter_entry(&entry) {
+                    continue;
+                }
+            }
+
+            if entry.stat.is_dir()
+                && self
+                    .configuration
+                    .max_depth
+                    .is_none_or(|max_depth| entry.depth < max_depth)
+            {
+                self.descend(&entry).await;
+            }
+
+            if entry.depth >= self.configuration.min_depth {
+                return Some(Ok(entry));
+            }
+        }
+    }
+
+    async fn descend(&mut self, entry: &WalkEntry) {
+        if self.configuration.follow_symlinks {
+            match self.sftp.realpath(&entry.path).await {
+                Ok(real) => {
+                    if !self.visited.insert(real) {
+                        return;
+                    }
+                }
+                Err(err) => {
+                    self.error = Some(err);
+                    return;
+                }
+            }
+        }
+
+        match self.sftp.read_dir_stream(&entry.path).await {
+            Ok(dir) => self.stack.push((dir, entry.depth + 1)),
+            Err(err) => self.error = Some(err),
+        }
+    }
+}
+
+pub(crate) fn walk<'a, S>(
+    sftp: &'a AsyncSftp<S>,
+    root: &Path,
+    configuration: WalkConfiguration,
+) -> WalkStream<'a>
+where
+    S: AsyncSessionStream + Send + Sync + 'static,
+{
+    let walk = Walk {
+        sftp,
+        configuration,
+        root: Some(root.to_owned()),
+        stack: vec![],
+        error: None,
+        visited: HashSet::new(),
+    };
+
+    stream::unfold(walk, |mut walk| async move {
+        let item = walk.next().await?;
+        Some((item, walk))
+    })
+    .boxed()
+}
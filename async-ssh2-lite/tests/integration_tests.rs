@@ -8,18 +8,63 @@ mod integration_tests {
     #[cfg(test)]
     mod agent__list_identities;
 
+    #[cfg(test)]
+    mod channel__copy_bidirectional;
+
     #[cfg(test)]
     mod channel__exec;
 
+    #[cfg(test)]
+    mod channel__into_split;
+
+    #[cfg(test)]
+    mod exec__remote_command;
+
     #[cfg(test)]
     mod remote_port_forwarding;
 
     #[cfg(test)]
     mod session__channel_forward_listen;
 
+    #[cfg(test)]
+    mod session__concurrent_channels;
+
+    #[cfg(test)]
+    mod session__connect_host;
+
+    #[cfg(test)]
+    mod session__connect_via;
+
+    #[cfg(test)]
+    mod session__connect_with_proxy_command;
+
+    #[cfg(test)]
+    mod session__dynamic_port_forwarding;
+
+    #[cfg(test)]
+    mod session__exec_output;
+
+    #[cfg(test)]
+    mod session__host_key_policy;
+
+    #[cfg(test)]
+    mod session__keepalive;
+
+    #[cfg(test)]
+    mod session__local_port_forwarding;
+
+    #[cfg(test)]
+    mod session__new_with_io;
+
     #[cfg(test)]
     mod session__scp_send_and_scp_recv;
 
+    #[cfg(test)]
+    mod session__ssh_config;
+
+    #[cfg(test)]
+    mod session__timeout;
+
     #[cfg(test)]
     mod session__userauth_password;
 
@@ -32,6 +77,18 @@ mod integration_tests {
     #[cfg(test)]
     mod sftp;
 
+    #[cfg(test)]
+    mod sftp__fs;
+
+    #[cfg(test)]
+    mod sftp__read_dir_stream_and_walk;
+
+    #[cfg(test)]
+    mod sftp__upload_and_download;
+
+    #[cfg(test)]
+    mod sftp__upload_dir_and_download_dir;
+
     #[cfg(test)]
     mod tokio_spawn_session;
 }
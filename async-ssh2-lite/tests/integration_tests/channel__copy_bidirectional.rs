@@ -0,0 +1,111 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::error;
+
+use async_ssh2_lite::AsyncSession;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    use async_ssh2_lite::util::copy_bidirectional_with_tokio;
+    use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let data = "foo\n".repeat(10000);
+
+    // tokio::io::copy_bidirectional half-closes the channel once the local side is done
+    for tuned in [false, true] {
+        let mut channel = session.channel_session().await?;
+        channel.exec("cat").await?;
+
+        let (mut local, mut peer) = tokio::io::duplex(4096);
+        let copy = tokio::spawn(async move {
+            if tuned {
+                copy_bidirectional_with_tokio(&mut channel, &mut local).await
+            } else {
+                tokio::io::copy_bidirectional(&mut channel, &mut local).await
+            }
+        });
+
+        let (mut peer_read, mut peer_write) = tokio::io::split(&mut peer);
+        let data_write = data.clone();
+        let (_, s) = tokio::try_join!(
+            async move {
+                peer_write.write_all(data_write.as_bytes()).await?;
+                peer_write.shutdown().await
+            },
+            async move {
+                let mut s = String::new();
+                peer_read.read_to_string(&mut s).await?;
+                Ok(s)
+            },
+        )?;
+        assert_eq!(s, data);
+
+        let (channel_to_local, local_to_channel) = copy.await??;
+        assert_eq!(channel_to_local, data.len() as u64);
+        assert_eq!(local_to_channel, data.len() as u64);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    use futures_util::{AsyncReadExt as _, AsyncWriteExt};
+
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+
+        let data = "foo\n".repeat(10000);
+
+        // futures::io::copy, closing the channel as a writer sends EOF
+        let mut channel = session.channel_session().await?;
+        channel.exec("wc -c").await?;
+        futures_util::io::copy(&mut data.as_bytes(), &mut channel).await?;
+        AsyncWriteExt::close(&mut channel).await?;
+        let mut s = String::new();
+        channel.read_to_string(&mut s).await?;
+        assert_eq!(s.trim(), data.len().to_string());
+
+        // Against a local socket whose peer sends the data and collects the echo
+        #[cfg(unix)]
+        {
+            use std::io::{Read as _, Write as _};
+
+            let mut channel = session.channel_session().await?;
+            channel.exec("cat").await?;
+
+            let (mut local, peer) = async_ssh2_lite::AsyncIoUnixStream::pair()?;
+            let mut peer = peer.into_inner()?;
+            let data_write = data.clone();
+            let peer = std::thread::spawn(move || -> std::io::Result<String> {
+                peer.write_all(data_write.as_bytes())?;
+                peer.shutdown(std::net::Shutdown::Write)?;
+                let mut s = String::new();
+                peer.read_to_string(&mut s)?;
+                Ok(s)
+            });
+
+            let (channel_to_local, local_to_channel) =
+                async_ssh2_lite::util::copy_bidirectional(&mut channel, &mut local).await?;
+            assert_eq!(local_to_channel, data.len() as u64);
+            assert_eq!(channel_to_local, data.len() as u64);
+            assert_eq!(peer.join().unwrap()?, data);
+        }
+
+        Ok(())
+    })
+}
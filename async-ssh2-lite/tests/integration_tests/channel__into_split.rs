@@ -0,0 +1,120 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, io};
+
+use async_ssh2_lite::{
+    AsyncChannelReadHalf, AsyncChannelWriteHalf, AsyncSession, AsyncSessionStream,
+};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::{AsyncReadExt as _, AsyncWriteExt as _};
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let mut channel = session.channel_session().await?;
+    channel.exec("cat; echo bar >&2").await?;
+    let (read_half, write_half, mut stderr) = channel.into_split();
+
+    let writer = tokio::spawn(__run__channel__write_half(write_half));
+    let reader = tokio::spawn(__run__channel__read_half(read_half));
+    writer.await??;
+    reader.await??;
+
+    let mut s = String::new();
+    stderr.read_to_string(&mut s).await?;
+    assert_eq!(s, "bar\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn drop_write_half_without_reading_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let mut channel = session.channel_session().await?;
+    channel.exec("cat; echo bar >&2").await?;
+    let (_read_half, mut write_half, mut stderr) = channel.into_split();
+
+    for _ in 0..10000 {
+        write_half.write_all(b"foo\n").await?;
+    }
+    // stdout is never read, cat still gets EOF and exits
+    drop(write_half);
+
+    let mut s = String::new();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(10),
+        stderr.read_to_string(&mut s),
+    )
+    .await??;
+    assert_eq!(s, "bar\n");
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+
+        let mut channel = session.channel_session().await?;
+        channel.exec("cat; echo bar >&2").await?;
+        let (read_half, write_half, mut stderr) = channel.into_split();
+
+        let writer = std::thread::spawn(move || {
+            futures_lite::future::block_on(__run__channel__write_half(write_half))
+        });
+        __run__channel__read_half(read_half).await?;
+        writer.join().unwrap()?;
+
+        let mut s = String::new();
+        stderr.read_to_string(&mut s).await?;
+        assert_eq!(s, "bar\n");
+
+        Ok(())
+    })
+}
+
+async fn __run__channel__write_half<S: AsyncSessionStream + Send + Sync + 'static>(
+    mut write_half: AsyncChannelWriteHalf<S>,
+) -> Result<(), io::Error> {
+    for _ in 0..100 {
+        write_half.write_all(b"foo\n").await?;
+    }
+    // cat exits on EOF
+    write_half.send_eof().await?;
+
+    Ok(())
+}
+
+async fn __run__channel__read_half<S: AsyncSessionStream + Send + Sync + 'static>(
+    mut read_half: AsyncChannelReadHalf<S>,
+) -> Result<(), io::Error> {
+    let mut s = String::new();
+    read_half.read_to_string(&mut s).await?;
+    assert_eq!(s, "foo\n".repeat(100));
+    println!(
+        "channel__into_split exit_status:{}",
+        read_half.exit_status()?
+    );
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{env, error, fs};
+
+use async_ssh2_lite::{exec::Stdio, AsyncSession, AsyncSessionStream, RemoteCommand};
+use futures_util::io::AllowStdIo;
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::{AsyncReadExt as _, AsyncWriteExt as _};
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+    __run__exec__remote_command(&session, "simple_with_tokio").await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__exec__remote_command(&session, "simple_with_async_io").await?;
+
+        Ok(())
+    })
+}
+
+async fn __run__exec__remote_command<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+    name: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    // Arguments reach the program as they are
+    let output = RemoteCommand::new("printf")
+        .arg("%s|")
+        .args(["it's", "$(hostname)", "a b", ""])
+        .current_dir("/tmp")
+        .output(session)
+        .await?;
+    assert!(output.success());
+    assert_eq!(output.stdout, b"it's|$(hostname)|a b||");
+
+    let status = RemoteCommand::new("sh")
+        .args(["-c", "exit 3"])
+        .status(session)
+        .await?;
+    assert_eq!(status.code, 3);
+
+    // Piped stdin and stdout
+    let mut child = RemoteCommand::new("cat")
+        .stdin(Stdio::piped())
+        .spawn(session)
+        .await?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"foo").await?;
+    stdin.flush().await?;
+    let mut stdout = child.stdout.take().unwrap();
+    let mut buf = [0; 3];
+    stdout.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"foo");
+    assert!(child.wait().await?.success());
+
+    // From a reader into a writer
+    let path = env::temp_dir().join(format!("async_ssh2_lite_remote_command_{name}"));
+    let output = RemoteCommand::new("tr")
+        .args(["a-z", "A-Z"])
+        .stdin(Stdio::from_reader(&b"foo\n"[..]))
+        .stdout(Stdio::from_writer(AllowStdIo::new(fs::File::create(
+            &path,
+        )?)))
+        .output(session)
+        .await?;
+    assert!(output.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(fs::read(&path)?, b"FOO\n");
+    fs::remove_file(&path)?;
+
+    let mut child = RemoteCommand::new("sleep")
+        .arg("30")
+        .pty("xterm")
+        .spawn(session)
+        .await?;
+    child.kill().await?;
+    let status = child.wait().await?;
+    println!("exec__remote_command kill status:{status:?}");
+
+    Ok(())
+}
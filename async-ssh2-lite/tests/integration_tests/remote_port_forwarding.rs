@@ -1,8 +1,9 @@
-#![cfg(feature = "tokio")]
+#![cfg(any(feature = "async-io", feature = "tokio"))]
 
 use std::{env, error, net::SocketAddr};
 
-use async_ssh2_lite::{util::ConnectInfo, AsyncSession};
+use async_ssh2_lite::{forward::RemoteForwardConfiguration, util::ConnectInfo, AsyncSession};
+#[cfg(feature = "tokio")]
 use futures_util::future::join_all;
 #[cfg(not(feature = "_integration_tests_tokio_ext"))]
 use futures_util::AsyncReadExt as _;
@@ -14,6 +15,7 @@ use super::{
     session__userauth_pubkey::__run__session__userauth_pubkey_file,
 };
 
+#[cfg(feature = "tokio")]
 #[tokio::test(flavor = "multi_thread", worker_threads = 10)]
 async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
     let http_server_listen_addr = get_listen_addr();
@@ -73,25 +75,20 @@ async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
         AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(ssh_server_connect_addr, None)
             .await?;
     __run__session__userauth_pubkey_file(&mut session).await?;
-    let forwarding_task: tokio::task::JoinHandle<Result<(), Box<dyn error::Error + Send + Sync>>> =
-        tokio::task::spawn(async move {
-            match session
-                .remote_port_forwarding(
-                    remote_port,
-                    None,
-                    None,
-                    ConnectInfo::Tcp(http_server_listen_addr_for_forwarding),
-                )
-                .await
-            {
-                Ok(_) => {}
-                Err(err) => {
-                    eprintln!("session.remote_port_forwarding error, err:{err}");
-                }
-            }
-
-            Ok(())
-        });
+    let mut configuration = RemoteForwardConfiguration::new();
+    configuration.set_max_connections(4);
+    configuration
+        .set_on_error(|err| eprintln!("remote_port_forwarding connection error, err:{err}"));
+    let forward = session
+        .remote_port_forwarding(
+            remote_port,
+            None,
+            None,
+            ConnectInfo::Tcp(http_server_listen_addr_for_forwarding),
+            configuration,
+        )
+        .await?;
+    assert_eq!(forward.remote_port(), remote_port);
 
     //
     tokio::time::sleep(tokio::time::Duration::from_millis(
@@ -145,8 +142,81 @@ async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
     server_task.abort();
     assert!(server_task.await.unwrap_err().is_cancelled());
 
-    forwarding_task.abort();
-    assert!(forwarding_task.await.unwrap_err().is_cancelled());
+    forward.shutdown();
 
     Ok(())
 }
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    use std::{
+        io::{BufRead as _, BufReader, Write as _},
+        net::TcpListener,
+        thread,
+    };
+
+    use async_ssh2_lite::AsyncIoTcpStream;
+
+    // Answers every request with an empty 200
+    let http_server_listen_addr = get_listen_addr();
+    let listener = TcpListener::bind(http_server_listen_addr)?;
+    thread::spawn(move || {
+        while let Ok((stream, _)) = listener.accept() {
+            thread::spawn(move || {
+                let mut reader = BufReader::new(&stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+                    line.clear();
+                }
+                let _ = (&stream).write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                );
+            });
+        }
+    });
+    let http_server_listen_addr_for_forwarding = SocketAddr::from((
+        if is_internal_test_openssh_server() {
+            [172, 17, 0, 1]
+        } else {
+            [127, 0, 0, 1]
+        },
+        http_server_listen_addr.port(),
+    ));
+
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<AsyncIoTcpStream>::connect(get_connect_addr()?, None).await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+
+        let forward = session
+            .remote_port_forwarding(
+                0,
+                None,
+                None,
+                ConnectInfo::Tcp(http_server_listen_addr_for_forwarding),
+                None,
+            )
+            .await?;
+        let remote_port = forward.remote_port();
+        println!("remote_port_forwarding remote_port:{remote_port}");
+        assert_ne!(remote_port, 0);
+
+        for _ in 0..3 {
+            let mut channel = session.channel_session().await?;
+            channel
+                .exec(&format!(
+                    r#"curl http://127.0.0.1:{remote_port}/200 -s -w "%{{http_code}}""#
+                ))
+                .await?;
+            let mut s = String::new();
+            channel.read_to_string(&mut s).await?;
+            assert_eq!(s, "200");
+            channel.close().await?;
+        }
+
+        forward.cancel().await?;
+
+        Ok(())
+    })
+}
@@ -2,7 +2,7 @@
 
 use std::error;
 
-use async_ssh2_lite::{ssh2::ErrorCode, AsyncSession, AsyncSessionStream, Error};
+use async_ssh2_lite::{ssh2::ErrorCode, AsyncSession, AsyncSessionStream};
 use futures_util::future::join_all;
 #[cfg(not(feature = "_integration_tests_tokio_ext"))]
 use futures_util::{AsyncReadExt as _, AsyncWriteExt as _};
@@ -51,14 +51,11 @@ async fn __run__session__channel_forward_listen__with_tokio_spawn<
         {
             Ok((listener, remote_port)) => break (listener, remote_port),
             Err(err) => {
-                match &err {
-                    Error::Ssh2(err) => match err.code() {
-                        ErrorCode::Session(-32) => {
-                            remote_port += 1;
-                            continue;
-                        }
-                        _ => {}
-                    },
+                match err.as_ssh2().map(|err| err.code()) {
+                    Some(ErrorCode::Session(-32)) => {
+                        remote_port += 1;
+                        continue;
+                    }
                     _ => {}
                 }
 
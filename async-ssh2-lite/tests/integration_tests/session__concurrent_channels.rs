@@ -0,0 +1,92 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, io};
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream};
+use futures_util::future::try_join;
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::{AsyncReadExt as _, AsyncWriteExt as _};
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+// Below the default MaxSessions of 10
+const CHANNELS: usize = 8;
+const LEN: usize = 256 * 1024;
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let handles = (0..CHANNELS)
+        .map(|_| tokio::spawn(__run__session__channel_echo(session.clone())))
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.await??;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    let session = futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        Ok::<_, Box<dyn error::Error>>(session)
+    })?;
+
+    let handles = (0..CHANNELS)
+        .map(|_| {
+            let session = session.clone();
+            std::thread::spawn(move || {
+                futures_lite::future::block_on(__run__session__channel_echo(session))
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in handles {
+        handle.join().unwrap()?;
+    }
+
+    Ok(())
+}
+
+/// Writes and reads at the same time, so either direction may have to wait on the other.
+async fn __run__session__channel_echo<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: AsyncSession<S>,
+) -> Result<(), io::Error> {
+    let mut channel = session.channel_session().await?;
+    channel.exec("cat").await?;
+    let (mut read_half, mut write_half, _) = channel.into_split();
+
+    let write = async move {
+        let buf = vec![b'x'; 16 * 1024];
+        for _ in 0..LEN / buf.len() {
+            write_half.write_all(&buf).await?;
+        }
+        // cat exits on EOF
+        write_half.send_eof().await?;
+        Ok::<_, io::Error>(())
+    };
+    let read = async move {
+        let mut b = vec![];
+        read_half.read_to_end(&mut b).await?;
+        Ok::<_, io::Error>(b)
+    };
+
+    let (_, b) = try_join(write, read).await?;
+    assert_eq!(b.len(), LEN);
+    assert!(b.iter().all(|x| *x == b'x'));
+
+    Ok(())
+}
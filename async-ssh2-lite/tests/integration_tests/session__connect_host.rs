@@ -0,0 +1,104 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use core::time::Duration;
+use std::{error, io::ErrorKind as IoErrorKind};
+
+use async_ssh2_lite::{
+    connect::ConnectError, AsyncSession, AsyncSessionStream, Error, SessionConfiguration,
+};
+
+use super::{
+    helpers::{get_connect_addr, is_internal_test_openssh_server},
+    session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session = AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect_host(
+        &__host()?,
+        __configuration(),
+    )
+    .await?;
+    __run__session__connect_host(&mut session).await?;
+
+    let ret = AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect_host(
+        "localhost:1",
+        __configuration(),
+    )
+    .await;
+    __check_connect_error(ret.map(|_| ()))?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session = AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect_host(
+            &__host()?,
+            __configuration(),
+        )
+        .await?;
+        __run__session__connect_host(&mut session).await?;
+
+        let ret = AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect_host(
+            "localhost:1",
+            __configuration(),
+        )
+        .await;
+        __check_connect_error(ret.map(|_| ()))?;
+
+        Ok(())
+    })
+}
+
+fn __host() -> Result<String, Box<dyn error::Error>> {
+    let addr = get_connect_addr()?;
+    if is_internal_test_openssh_server() {
+        // Resolves to both ::1 and 127.0.0.1 on most systems
+        Ok(format!("localhost:{}", addr.port()))
+    } else {
+        Ok(addr.to_string())
+    }
+}
+
+fn __configuration() -> SessionConfiguration {
+    let mut configuration = SessionConfiguration::new();
+    configuration.set_connect_timeout(Duration::from_secs(5));
+    configuration
+}
+
+async fn __run__session__connect_host<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    println!("connect_host remote_host:{:?}", session.remote_host());
+
+    __run__session__userauth_pubkey_file(session).await?;
+    assert!(session.authenticated());
+
+    session.disconnect(None, "foo", None).await?;
+
+    Ok(())
+}
+
+fn __check_connect_error(ret: Result<(), Error>) -> Result<(), Box<dyn error::Error>> {
+    let err = match ret {
+        Ok(_) => panic!("connect to port 1 should fail"),
+        Err(Error::Io(err)) => err,
+        Err(err) => panic!("unexpected err:{err:?}"),
+    };
+    println!("connect_host err:{err}");
+    assert_eq!(err.kind(), IoErrorKind::ConnectionRefused);
+
+    let err = err
+        .get_ref()
+        .and_then(|err| err.downcast_ref::<ConnectError>())
+        .expect("not a ConnectError");
+    assert_eq!(err.host, "localhost");
+    assert!(!err.attempts.is_empty());
+
+    Ok(())
+}
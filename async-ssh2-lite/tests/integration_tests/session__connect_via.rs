@@ -0,0 +1,151 @@
+#![cfg(all(unix, any(feature = "async-io", feature = "tokio")))]
+
+use std::error;
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream, JumpHost};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::AsyncReadExt as _;
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::AsyncReadExt as _;
+
+use super::{
+    helpers::{
+        get_connect_addr, get_privatekey_path, get_username, is_internal_test_openssh_server,
+    },
+    session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut bastion_session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut bastion_session).await?;
+
+    let (host, port) = __intranet_host_and_port()?;
+
+    // bastion -> hop -> target
+    let mut hop_session = AsyncSession::<async_ssh2_lite::TokioUnixStream>::connect_via(
+        &bastion_session,
+        &host,
+        port,
+        None,
+    )
+    .await?;
+    __run__session__userauth_pubkey_file(&mut hop_session).await?;
+
+    let mut session = AsyncSession::<async_ssh2_lite::TokioUnixStream>::connect_via(
+        &hop_session,
+        &host,
+        port,
+        None,
+    )
+    .await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+    __run__session__connect_via(&session).await?;
+
+    // The same chain in one go
+    let mut session = AsyncSession::<async_ssh2_lite::TokioUnixStream>::connect_via_chain(
+        &bastion_session,
+        &[JumpHost::new(&host, port)],
+        &host,
+        port,
+        None,
+        |_, hop_session| async move {
+            // Already handshaken
+            hop_session
+                .userauth_pubkey_file(&get_username(), None, &get_privatekey_path(), None)
+                .await?;
+            Ok(hop_session)
+        },
+    )
+    .await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+    __run__session__connect_via(&session).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut bastion_session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut bastion_session).await?;
+
+        let (host, port) = __intranet_host_and_port()?;
+
+        let mut hop_session = AsyncSession::<async_ssh2_lite::AsyncIoUnixStream>::connect_via(
+            &bastion_session,
+            &host,
+            port,
+            None,
+        )
+        .await?;
+        __run__session__userauth_pubkey_file(&mut hop_session).await?;
+
+        let mut session = AsyncSession::<async_ssh2_lite::AsyncIoUnixStream>::connect_via(
+            &hop_session,
+            &host,
+            port,
+            None,
+        )
+        .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__session__connect_via(&session).await?;
+
+        let mut session = AsyncSession::<async_ssh2_lite::AsyncIoUnixStream>::connect_via_chain(
+            &bastion_session,
+            &[JumpHost::new(&host, port)],
+            &host,
+            port,
+            None,
+            |_, hop_session| async move {
+                // Already handshaken
+                hop_session
+                    .userauth_pubkey_file(&get_username(), None, &get_privatekey_path(), None)
+                    .await?;
+                Ok(hop_session)
+            },
+        )
+        .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__session__connect_via(&session).await?;
+
+        Ok(())
+    })
+}
+
+// The ssh server as seen from itself
+fn __intranet_host_and_port() -> Result<(String, u16), Box<dyn error::Error>> {
+    if is_internal_test_openssh_server() {
+        Ok(("127.0.0.1".into(), 2222))
+    } else {
+        let addr = get_connect_addr()?;
+        Ok(("127.0.0.1".into(), addr.port()))
+    }
+}
+
+async fn __run__session__connect_via<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    for _ in 0..3 {
+        let mut channel = session.channel_session().await?;
+        channel.exec("head -c 16354 /dev/random").await?;
+        let mut b = vec![];
+        channel.read_to_end(&mut b).await?;
+        assert_eq!(b.len(), 16354);
+        channel.close().await?;
+        println!(
+            "connect_via exec head exit_status:{}",
+            channel.exit_status()?
+        );
+    }
+
+    session.disconnect(None, "foo", None).await?;
+
+    Ok(())
+}
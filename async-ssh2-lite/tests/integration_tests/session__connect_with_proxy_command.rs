@@ -0,0 +1,137 @@
+#![cfg(all(unix, any(feature = "async-io", feature = "tokio")))]
+
+use std::{env, error, process::Command};
+
+use async_ssh2_lite::{
+    proxy_command::{shell_command, ProxyCommandError},
+    AsyncSession, AsyncSessionStream,
+};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::AsyncReadExt as _;
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::AsyncReadExt as _;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let addr = get_connect_addr()?;
+
+    let mut session = AsyncSession::<async_ssh2_lite::TokioUnixStream>::connect_with_proxy_command(
+        __proxy_command()?,
+        &addr.ip().to_string(),
+        addr.port(),
+        None,
+    )
+    .await?;
+    __run__session__connect_with_proxy_command(&mut session).await?;
+
+    let mut session = AsyncSession::<async_ssh2_lite::TokioUnixStream>::connect_with_proxy_command(
+        __failing_proxy_command(),
+        "foo",
+        22,
+        None,
+    )
+    .await?;
+    __run__session__connect_with_failing_proxy_command(&mut session).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let addr = get_connect_addr()?;
+
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoUnixStream>::connect_with_proxy_command(
+                __proxy_command()?,
+                &addr.ip().to_string(),
+                addr.port(),
+                None,
+            )
+            .await?;
+        __run__session__connect_with_proxy_command(&mut session).await?;
+
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoUnixStream>::connect_with_proxy_command(
+                __failing_proxy_command(),
+                "foo",
+                22,
+                None,
+            )
+            .await?;
+        __run__session__connect_with_failing_proxy_command(&mut session).await?;
+
+        Ok(())
+    })
+}
+
+// SSH_PROXY_COMMAND may be e.g. `/usr/sbin/sshd -i -f /path/to/sshd_config`
+fn __proxy_command() -> Result<Command, Box<dyn error::Error>> {
+    match env::var("SSH_PROXY_COMMAND") {
+        Ok(command) => Ok(shell_command(&command)),
+        Err(_) => {
+            let addr = get_connect_addr()?;
+            let mut command = Command::new("nc");
+            command
+                .arg(addr.ip().to_string())
+                .arg(addr.port().to_string());
+            Ok(command)
+        }
+    }
+}
+
+fn __failing_proxy_command() -> Command {
+    shell_command("echo 'no route to foo' >&2; exit 3")
+}
+
+async fn __run__session__connect_with_proxy_command<
+    S: AsyncSessionStream + Send + Sync + 'static,
+>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    __run__session__userauth_pubkey_file(session).await?;
+
+    let mut channel = session.channel_session().await?;
+    channel.exec("hostname").await?;
+    let mut s = String::new();
+    channel.read_to_string(&mut s).await?;
+    println!("connect_with_proxy_command exec hostname output:{s}");
+    channel.close().await?;
+    assert_eq!(channel.exit_status()?, 0);
+
+    session.disconnect(None, "foo", None).await?;
+
+    Ok(())
+}
+
+async fn __run__session__connect_with_failing_proxy_command<
+    S: AsyncSessionStream + Send + Sync + 'static,
+>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    let err = match session.handshake().await {
+        Ok(_) => panic!("handshake should fail"),
+        Err(err) => err,
+    };
+    println!("connect_with_failing_proxy_command handshake err:{err}");
+
+    let err = err
+        .as_other()
+        .and_then(|err| err.downcast_ref::<ProxyCommandError>())
+        .expect("not a ProxyCommandError");
+    assert_eq!(err.status.and_then(|status| status.code()), Some(3));
+    assert!(err.stderr.contains("no route to foo"));
+    assert!(session
+        .proxy_command_stderr()
+        .unwrap_or_default()
+        .contains("no route to foo"));
+
+    Ok(())
+}
@@ -0,0 +1,123 @@
+#![cfg(feature = "tokio")]
+
+use std::{
+    error,
+    sync::{Arc, Mutex},
+};
+
+use async_ssh2_lite::{forward::DynamicForwardConfiguration, util::ConnectInfo, AsyncSession};
+use tokio::{
+    io::{AsyncBufReadExt as _, AsyncReadExt as _, AsyncWriteExt as _, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{
+    helpers::{get_connect_addr, get_listen_addr, is_internal_test_openssh_server},
+    session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    // An echo server, reached from the ssh server
+    let echo_server_listen_addr = get_listen_addr();
+    let echo_server_port = echo_server_listen_addr.port();
+    let echo_server_host = if is_internal_test_openssh_server() {
+        "172.17.0.1"
+    } else {
+        "127.0.0.1"
+    };
+    let listener = TcpListener::bind(echo_server_listen_addr).await?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let errors = Arc::new(Mutex::new(vec![]));
+    let mut configuration = DynamicForwardConfiguration::new();
+    configuration.set_auth("user", "pass");
+    {
+        let errors = errors.clone();
+        configuration.set_on_error(move |err| {
+            println!("dynamic_port_forwarding err:{err}");
+            errors.lock().unwrap().push(err.to_string());
+        });
+    }
+    let forward = session
+        .dynamic_port_forwarding(ConnectInfo::with_tcp(([127, 0, 0, 1], 0)), configuration)
+        .await?;
+    let proxy_addr = match forward.local_addr() {
+        ConnectInfo::Tcp(addr) => *addr,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    };
+
+    // SOCKS5 with username/password
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    stream.write_all(&[5, 1, 2]).await?;
+    let mut buf = [0; 2];
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(buf, [5, 2]);
+    stream.write_all(b"\x01\x04user\x04pass").await?;
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(buf, [1, 0]);
+    let mut request = vec![5, 1, 0, 3, echo_server_host.len() as u8];
+    request.extend_from_slice(echo_server_host.as_bytes());
+    request.extend_from_slice(&echo_server_port.to_be_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = [0; 10];
+    stream.read_exact(&mut reply).await?;
+    assert_eq!(reply[1], 0);
+    __run__echo(stream).await?;
+
+    // HTTP CONNECT with Basic auth
+    let mut stream = BufReader::new(TcpStream::connect(proxy_addr).await?);
+    stream
+        .write_all(
+            format!(
+                "CONNECT {echo_server_host}:{echo_server_port} HTTP/1.1\r\nProxy-Authorization: Basic dXNlcjpwYXNz\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await?;
+    let mut status = String::new();
+    stream.read_line(&mut status).await?;
+    assert!(status.starts_with("HTTP/1.1 200"), "{status}");
+    let mut empty = String::new();
+    stream.read_line(&mut empty).await?;
+    assert_eq!(empty, "\r\n");
+    __run__echo(stream.into_inner()).await?;
+
+    // A wrong password is reported through the callback
+    let mut stream = TcpStream::connect(proxy_addr).await?;
+    stream.write_all(&[5, 1, 2]).await?;
+    stream.read_exact(&mut buf).await?;
+    stream.write_all(b"\x01\x04user\x04nope").await?;
+    stream.read_exact(&mut buf).await?;
+    assert_eq!(buf, [1, 1]);
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    assert_eq!(errors.lock().unwrap().len(), 1);
+
+    forward.shutdown();
+
+    Ok(())
+}
+
+async fn __run__echo(mut stream: TcpStream) -> Result<(), Box<dyn error::Error>> {
+    let data = "foo\n".repeat(10000);
+    stream.write_all(data.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut s = String::new();
+    stream.read_to_string(&mut s).await?;
+    assert_eq!(s, data);
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use core::time::Duration;
+use std::error;
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream, ErrorKind, ExecConfiguration};
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+    __run__session__exec_output(&mut session).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__session__exec_output(&mut session).await?;
+
+        Ok(())
+    })
+}
+
+async fn __run__session__exec_output<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    let output = session.exec_output("hostname", None).await?;
+    println!("exec_output hostname output:{output:?}");
+    assert!(output.success());
+    assert!(!output.stdout.is_empty());
+
+    // More stderr than the window must not stall stdout
+    let output = session
+        .exec_output("head -c 1000000 /dev/zero >&2; echo ok", None)
+        .await?;
+    assert!(output.success());
+    assert_eq!(output.stdout, b"ok\n");
+    assert_eq!(output.stderr.len(), 1000000);
+
+    let output = session.exec_output("echo foo >&2; exit 3", None).await?;
+    assert!(!output.success());
+    assert_eq!(output.exit_status, 3);
+    assert_eq!(output.stderr, b"foo\n");
+
+    let mut configuration = ExecConfiguration::new();
+    configuration.set_output_limit(10);
+    let output = session
+        .exec_output("head -c 1000 /dev/zero", configuration)
+        .await?;
+    assert!(output.success());
+    assert_eq!(output.stdout.len(), 10);
+    assert!(output.truncated);
+
+    let mut configuration = ExecConfiguration::new();
+    configuration.set_timeout(Duration::from_millis(500));
+    let err = session
+        .exec_output("sleep 5", configuration)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Timeout);
+    assert_eq!(err.context().map(|x| x.op), Some("exec_output"));
+
+    Ok(())
+}
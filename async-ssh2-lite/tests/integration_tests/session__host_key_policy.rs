@@ -0,0 +1,101 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, fs, path::Path};
+
+use async_ssh2_lite::{
+    ssh2::CheckResult, AsyncSession, AsyncSessionStream, ErrorKind, HostKeyPolicy,
+    SessionConfiguration,
+};
+
+use super::helpers::get_connect_addr;
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let known_hosts_path = dir.path().join("known_hosts");
+
+    for (policy, hash_known_hosts) in __host_key_policies() {
+        let mut session = AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(
+            get_connect_addr()?,
+            __configuration(&known_hosts_path, policy.clone(), hash_known_hosts),
+        )
+        .await?;
+        __run__session__host_key_policy(&mut session, &known_hosts_path, &policy).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let dir = tempfile::tempdir()?;
+        let known_hosts_path = dir.path().join("known_hosts");
+
+        for (policy, hash_known_hosts) in __host_key_policies() {
+            let mut session = AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(
+                get_connect_addr()?,
+                __configuration(&known_hosts_path, policy.clone(), hash_known_hosts),
+            )
+            .await?;
+            __run__session__host_key_policy(&mut session, &known_hosts_path, &policy).await?;
+        }
+
+        Ok(())
+    })
+}
+
+fn __host_key_policies() -> Vec<(HostKeyPolicy, bool)> {
+    vec![
+        (HostKeyPolicy::Strict, false),
+        (HostKeyPolicy::AcceptNew, true),
+        (HostKeyPolicy::Strict, false),
+        (
+            HostKeyPolicy::with_callback(|check| matches!(check.result, CheckResult::Match)),
+            false,
+        ),
+        (HostKeyPolicy::Insecure, false),
+    ]
+}
+
+fn __configuration(
+    known_hosts_path: &Path,
+    policy: HostKeyPolicy,
+    hash_known_hosts: bool,
+) -> SessionConfiguration {
+    let mut configuration = SessionConfiguration::new();
+    configuration.set_host_key_policy(policy);
+    configuration.set_known_hosts_files([known_hosts_path]);
+    configuration.set_hash_known_hosts(hash_known_hosts);
+    configuration
+}
+
+async fn __run__session__host_key_policy<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &mut AsyncSession<S>,
+    known_hosts_path: &Path,
+    policy: &HostKeyPolicy,
+) -> Result<(), Box<dyn error::Error>> {
+    let ret = session.handshake().await;
+    println!("host_key_policy policy:{policy:?} ret:{ret:?}");
+
+    match policy {
+        HostKeyPolicy::Strict if !known_hosts_path.exists() => match ret {
+            Err(err) if err.kind() == ErrorKind::HostKeyMismatch => {
+                let err = err.as_host_key_mismatch().expect("host key mismatch");
+                assert!(matches!(err.result, CheckResult::NotFound))
+            }
+            ret => panic!("unexpected ret:{ret:?}"),
+        },
+        HostKeyPolicy::AcceptNew => {
+            ret?;
+            let content = fs::read_to_string(known_hosts_path)?;
+            assert!(content.starts_with("|1|"));
+        }
+        _ => ret?,
+    }
+
+    Ok(())
+}
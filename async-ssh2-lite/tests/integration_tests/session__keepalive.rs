@@ -0,0 +1,122 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{
+    error,
+    io::{Read as _, Write as _},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream, SessionConfiguration};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::AsyncReadExt as _;
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::AsyncReadExt as _;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let (addr, blackhole) = __blackhole_proxy(get_connect_addr()?)?;
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(addr, __configuration()).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let _keepalive = session.spawn_keepalive_with_tokio(2)?;
+    __run__session__keepalive(&mut session, blackhole).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let (addr, blackhole) = __blackhole_proxy(get_connect_addr()?)?;
+
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(addr, __configuration())
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+
+        let _keepalive = session.spawn_keepalive_with_async_io(2)?;
+        __run__session__keepalive(&mut session, blackhole).await?;
+
+        Ok(())
+    })
+}
+
+fn __configuration() -> SessionConfiguration {
+    let mut configuration = SessionConfiguration::new();
+    configuration.set_keepalive(true, 1);
+    configuration
+}
+
+/// Forwards to `target` until the flag is set, then silently drops everything, like a NAT that
+/// forgot the connection.
+fn __blackhole_proxy(
+    target: SocketAddr,
+) -> Result<(SocketAddr, Arc<AtomicBool>), Box<dyn error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let blackhole = Arc::new(AtomicBool::new(false));
+
+    let blackhole_accept = blackhole.clone();
+    thread::spawn(move || {
+        let (client, _) = listener.accept().unwrap();
+        let server = TcpStream::connect(target).unwrap();
+        for (mut from, mut to) in [
+            (client.try_clone().unwrap(), server.try_clone().unwrap()),
+            (server, client),
+        ] {
+            let blackhole = blackhole_accept.clone();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while let Ok(n) = from.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    if !blackhole.load(Ordering::SeqCst) && to.write_all(&buf[..n]).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    Ok((addr, blackhole))
+}
+
+async fn __run__session__keepalive<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &mut AsyncSession<S>,
+    blackhole: Arc<AtomicBool>,
+) -> Result<(), Box<dyn error::Error>> {
+    // Answered keepalives keep the session alive
+    let mut channel = session.channel_session().await?;
+    channel.exec("sleep 3; echo foo").await?;
+    let mut s = String::new();
+    channel.read_to_string(&mut s).await?;
+    assert_eq!(s, "foo\n");
+    channel.close().await?;
+    assert!(!session.is_broken());
+
+    // A pending read fails once the peer is considered dead
+    let mut channel = session.channel_session().await?;
+    channel.exec("sleep 30").await?;
+    blackhole.store(true, Ordering::SeqCst);
+    let mut b = vec![];
+    let err = channel.read_to_end(&mut b).await.unwrap_err();
+    println!("keepalive read_to_end err:{err}");
+    assert!(session.is_broken());
+
+    Ok(())
+}
@@ -0,0 +1,207 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, net::SocketAddr};
+
+use async_ssh2_lite::{util::ConnectInfo, AsyncSession};
+#[cfg(feature = "tokio")]
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{
+    helpers::{get_connect_addr, get_listen_addr, is_internal_test_openssh_server},
+    session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    // An echo server, reached from the ssh server
+    let echo_server_listen_addr = get_listen_addr();
+    let echo_server_port = echo_server_listen_addr.port();
+    let echo_server_host = if is_internal_test_openssh_server() {
+        "172.17.0.1"
+    } else {
+        "127.0.0.1"
+    };
+    let listener = TcpListener::bind(echo_server_listen_addr).await?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = stream.split();
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let forward = session
+        .local_port_forwarding(
+            ConnectInfo::with_tcp(([127, 0, 0, 1], 0)),
+            echo_server_host,
+            echo_server_port,
+            None,
+        )
+        .await?;
+    let local_addr = match forward.local_addr() {
+        ConnectInfo::Tcp(addr) => *addr,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    };
+    println!("local_port_forwarding local_addr:{local_addr}");
+
+    for i in 0..3 {
+        __run__echo(local_addr, i).await?;
+    }
+
+    // Concurrent connections
+    let mut streams = vec![];
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(local_addr).await?;
+        stream.write_all(b"foo").await?;
+        let mut buf = [0; 3];
+        stream.read_exact(&mut buf).await?;
+        streams.push(stream);
+    }
+    assert_eq!(forward.active_connections(), 3);
+
+    // Shutdown closes the active ones and stops listening
+    forward.shutdown();
+    for mut stream in streams {
+        let mut buf = vec![];
+        assert!(matches!(stream.read_to_end(&mut buf).await, Ok(0) | Err(_)));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    assert!(TcpStream::connect(local_addr).await.is_err());
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn refused_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    use std::sync::{Arc, Mutex};
+
+    use async_ssh2_lite::{forward::LocalForwardConfiguration, ErrorKind};
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    let errors = Arc::new(Mutex::new(vec![]));
+    let mut configuration = LocalForwardConfiguration::new();
+    {
+        let errors = errors.clone();
+        configuration.set_on_error(move |err| {
+            errors
+                .lock()
+                .unwrap()
+                .push((err.target.clone(), err.error.kind()));
+        });
+    }
+
+    // Nothing listens on port 1 of the ssh server
+    let forward = session
+        .local_port_forwarding(
+            ConnectInfo::with_tcp(([127, 0, 0, 1], 0)),
+            "127.0.0.1",
+            1,
+            configuration,
+        )
+        .await?;
+    let local_addr = match forward.local_addr() {
+        ConnectInfo::Tcp(addr) => *addr,
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    };
+
+    let mut stream = TcpStream::connect(local_addr).await?;
+    let mut buf = vec![];
+    assert!(matches!(stream.read_to_end(&mut buf).await, Ok(0) | Err(_)));
+
+    let errors = errors.lock().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, Some(("127.0.0.1".to_owned(), 1)));
+    assert!(matches!(errors[0].1, ErrorKind::ChannelOpenFailed(_)));
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+async fn __run__echo(local_addr: SocketAddr, i: usize) -> Result<(), Box<dyn error::Error>> {
+    let mut stream = TcpStream::connect(local_addr).await?;
+    let data = format!("foo {i}\n").repeat(10000);
+    stream.write_all(data.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut s = String::new();
+    stream.read_to_string(&mut s).await?;
+    assert_eq!(s, data);
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    use std::{io::copy, net::TcpListener, thread};
+
+    use async_ssh2_lite::{async_io::Async, AsyncIoTcpStream};
+    use futures_util::{AsyncReadExt as _, AsyncWriteExt as _};
+
+    // An echo server, reached from the ssh server
+    let echo_server_listen_addr = get_listen_addr();
+    let echo_server_port = echo_server_listen_addr.port();
+    let echo_server_host = if is_internal_test_openssh_server() {
+        "172.17.0.1"
+    } else {
+        "127.0.0.1"
+    };
+    let listener = TcpListener::bind(echo_server_listen_addr)?;
+    thread::spawn(move || {
+        while let Ok((stream, _)) = listener.accept() {
+            thread::spawn(move || {
+                let _ = copy(&mut &stream, &mut &stream);
+            });
+        }
+    });
+
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<AsyncIoTcpStream>::connect(get_connect_addr()?, None).await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+
+        let forward = session
+            .local_port_forwarding(
+                ConnectInfo::with_tcp(([127, 0, 0, 1], 0)),
+                echo_server_host,
+                echo_server_port,
+                None,
+            )
+            .await?;
+        let local_addr = match forward.local_addr() {
+            ConnectInfo::Tcp(addr) => *addr,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        };
+
+        for i in 0..3 {
+            let mut stream = Async::<std::net::TcpStream>::connect(local_addr).await?;
+            let data = format!("foo {i}\n").repeat(10000);
+            stream.write_all(data.as_bytes()).await?;
+            stream.get_ref().shutdown(std::net::Shutdown::Write)?;
+
+            let mut s = String::new();
+            stream.read_to_string(&mut s).await?;
+            assert_eq!(s, data);
+        }
+
+        forward.shutdown();
+
+        Ok(())
+    })
+}
@@ -0,0 +1,60 @@
+#![cfg(all(unix, any(feature = "async-io", feature = "tokio")))]
+
+use std::error;
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::AsyncReadExt as _;
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::AsyncReadExt as _;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    // Any tokio I/O object, buffered to rule out the raw fd
+    let io = tokio::io::BufStream::new(tokio::net::TcpStream::connect(get_connect_addr()?).await?);
+
+    let mut session = AsyncSession::<async_ssh2_lite::TokioUnixStream>::new_with_io(io, None)?;
+    __run__session__new_with_io(&mut session).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let io = futures_util::io::BufReader::new(
+            async_ssh2_lite::AsyncIoTcpStream::connect(get_connect_addr()?).await?,
+        );
+
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoUnixStream>::new_with_io(io, None)?;
+        __run__session__new_with_io(&mut session).await?;
+
+        Ok(())
+    })
+}
+
+async fn __run__session__new_with_io<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    __run__session__userauth_pubkey_file(session).await?;
+
+    let mut channel = session.channel_session().await?;
+    channel.exec("head -c 16354 /dev/random").await?;
+    let mut b = vec![];
+    channel.read_to_end(&mut b).await?;
+    assert_eq!(b.len(), 16354);
+    channel.close().await?;
+    assert_eq!(channel.exit_status()?, 0);
+
+    session.disconnect(None, "foo", None).await?;
+
+    Ok(())
+}
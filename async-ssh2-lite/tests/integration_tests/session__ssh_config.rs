@@ -0,0 +1,123 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::error;
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream, ResolvedSshConfig, SshConfig};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::AsyncReadExt as _;
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::AsyncReadExt as _;
+
+use super::helpers::{
+    get_connect_addr, get_privatekey_path, get_username, is_internal_test_openssh_server,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let resolved = __resolved_ssh_config()?;
+
+    let session =
+        AsyncSession::<async_ssh2_lite::TokioSshConfigStream>::connect_with_ssh_config(&resolved)
+            .await?;
+    __run__session__ssh_config(session, &resolved).await?;
+
+    #[cfg(unix)]
+    {
+        let resolved = __with_proxy_jump(__resolved_ssh_config()?);
+        let session =
+            AsyncSession::<async_ssh2_lite::TokioSshConfigStream>::connect_with_ssh_config(
+                &resolved,
+            )
+            .await?;
+        __run__session__ssh_config(session, &resolved).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let resolved = __resolved_ssh_config()?;
+
+        let session =
+            AsyncSession::<async_ssh2_lite::AsyncIoSshConfigStream>::connect_with_ssh_config(
+                &resolved,
+            )
+            .await?;
+        __run__session__ssh_config(session, &resolved).await?;
+
+        #[cfg(unix)]
+        {
+            let resolved = __with_proxy_jump(__resolved_ssh_config()?);
+            let session =
+                AsyncSession::<async_ssh2_lite::AsyncIoSshConfigStream>::connect_with_ssh_config(
+                    &resolved,
+                )
+                .await?;
+            __run__session__ssh_config(session, &resolved).await?;
+        }
+
+        Ok(())
+    })
+}
+
+fn __resolved_ssh_config() -> Result<ResolvedSshConfig, Box<dyn error::Error>> {
+    let addr = get_connect_addr()?;
+
+    let config: SshConfig = format!(
+        r#"
+Host test-server
+    HostName {}
+    Port {}
+    User {}
+    IdentityFile {}
+    IdentitiesOnly yes
+    ConnectTimeout 10
+
+Host *
+    StrictHostKeyChecking no
+"#,
+        addr.ip(),
+        addr.port(),
+        get_username(),
+        get_privatekey_path().display(),
+    )
+    .parse()?;
+
+    let resolved = config.resolve("test-server");
+    println!("ssh_config resolved:{resolved:?}");
+    Ok(resolved)
+}
+
+// Through the server to itself, like `ProxyJump test-server`
+#[cfg(unix)]
+fn __with_proxy_jump(mut resolved: ResolvedSshConfig) -> ResolvedSshConfig {
+    resolved.proxy_jump_configs = vec![resolved.clone()];
+    resolved.host_name = "127.0.0.1".into();
+    if is_internal_test_openssh_server() {
+        resolved.port = 2222;
+    }
+    resolved
+}
+
+async fn __run__session__ssh_config<S: AsyncSessionStream + Send + Sync + 'static>(
+    mut session: AsyncSession<S>,
+    resolved: &ResolvedSshConfig,
+) -> Result<(), Box<dyn error::Error>> {
+    session.handshake().await?;
+    session.userauth_with_ssh_config(resolved).await?;
+    assert!(session.authenticated());
+
+    let mut channel = session.channel_session().await?;
+    channel.exec("hostname").await?;
+    let mut s = String::new();
+    channel.read_to_string(&mut s).await?;
+    println!("ssh_config exec hostname output:{s}");
+    channel.close().await?;
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use core::time::Duration;
+use std::{error, io::ErrorKind as IoErrorKind, path::Path};
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream, Error, ErrorKind, SessionConfiguration};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::AsyncReadExt as _;
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::AsyncReadExt as _;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    #[cfg(unix)]
+    {
+        // A peer that never answers
+        let (stream, _peer) = async_ssh2_lite::TokioUnixStream::pair()?;
+        let mut session = AsyncSession::new(stream, __configuration())?;
+        __run__session__timeout_with_stalled_peer(&mut session).await?;
+    }
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__timeout(&mut session).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        #[cfg(unix)]
+        {
+            let (stream, _peer) = async_ssh2_lite::AsyncIoUnixStream::pair()?;
+            let mut session = AsyncSession::new(stream, __configuration())?;
+            __run__session__timeout_with_stalled_peer(&mut session).await?;
+        }
+
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__timeout(&mut session).await?;
+
+        Ok(())
+    })
+}
+
+fn __configuration() -> SessionConfiguration {
+    let mut configuration = SessionConfiguration::new();
+    configuration.set_timeout(300);
+    configuration
+}
+
+async fn __run__session__timeout_with_stalled_peer<
+    S: AsyncSessionStream + Send + Sync + 'static,
+>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    match session.handshake().await {
+        Err(err) if err.kind() == ErrorKind::Timeout => {
+            println!("timeout handshake err:{err}");
+            assert_eq!(err.context().map(|ctx| ctx.op), Some("handshake"));
+            assert!(matches!(
+                err.without_context(),
+                Error::Timeout(dur) if *dur == Duration::from_millis(300)
+            ));
+        }
+        ret => panic!("unexpected ret:{ret:?}"),
+    }
+
+    Ok(())
+}
+
+async fn __run__session__timeout<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &mut AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error>> {
+    __run__session__userauth_pubkey_file(session).await?;
+
+    //
+    let mut channel = session
+        .channel_session_with_timeout(Duration::from_secs(5))
+        .await?;
+    channel
+        .exec_with_timeout("sleep 3; echo foo", Duration::from_secs(5))
+        .await?;
+    channel.set_timeout(Some(Duration::from_millis(500)));
+    let mut b = vec![];
+    let err = channel.read_to_end(&mut b).await.unwrap_err();
+    println!("timeout read_to_end err:{err}");
+    assert_eq!(err.kind(), IoErrorKind::TimedOut);
+
+    // Reads continue after a timeout
+    channel.set_timeout(None);
+    channel.read_to_end(&mut b).await?;
+    assert_eq!(b, b"foo\n");
+    channel.close().await?;
+
+    //
+    let sftp = session.sftp().await?;
+    let mut file = sftp
+        .open_with_timeout(Path::new("/etc/hostname"), Duration::from_secs(5))
+        .await?;
+    let stat = file.stat().await?;
+    println!("timeout /etc/hostname stat:{stat:?}");
+
+    session.disconnect(None, "foo", None).await?;
+
+    Ok(())
+}
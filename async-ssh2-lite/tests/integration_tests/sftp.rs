@@ -1,8 +1,12 @@
 #![cfg(any(feature = "async-io", feature = "tokio"))]
 
-use std::{error, path::PathBuf};
+use std::{error, io::SeekFrom, path::PathBuf};
 
 use async_ssh2_lite::{AsyncSession, AsyncSessionStream};
+#[cfg(not(feature = "_integration_tests_tokio_ext"))]
+use futures_util::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _};
+#[cfg(feature = "_integration_tests_tokio_ext")]
+use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _, AsyncWriteExt as _};
 use uuid::Uuid;
 
 use super::{
@@ -53,6 +57,21 @@ async fn __run__session__sftp<S: AsyncSessionStream + Send + Sync + 'static>(
     sftp_file.close().await?;
     assert_eq!(file_stat, file_stat_for_file);
 
+    let mut sftp_file = sftp.create(&remote_path).await?;
+    sftp_file.write_all(b"foobar").await?;
+    // Closes the handle
+    #[cfg(not(feature = "_integration_tests_tokio_ext"))]
+    sftp_file.close().await?;
+    #[cfg(feature = "_integration_tests_tokio_ext")]
+    sftp_file.shutdown().await?;
+
+    let mut sftp_file = sftp.open(&remote_path).await?;
+    assert_eq!(sftp_file.seek(SeekFrom::Start(3)).await?, 3);
+    let mut s = String::new();
+    sftp_file.read_to_string(&mut s).await?;
+    assert_eq!(s, "bar");
+    assert_eq!(sftp_file.seek(SeekFrom::Current(-6)).await?, 0);
+
     sftp.unlink(&remote_path).await?;
 
     let list = sftp.readdir(&PathBuf::from("/")).await?;
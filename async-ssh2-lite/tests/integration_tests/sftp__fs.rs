@@ -0,0 +1,92 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, path::PathBuf};
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream};
+use uuid::Uuid;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+    __run__sftp__fs(&session)
+        .await
+        .map_err(|err| err as Box<dyn error::Error>)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__sftp__fs(&session)
+            .await
+            .map_err(|err| err as Box<dyn error::Error>)?;
+
+        Ok(())
+    })
+}
+
+async fn __run__sftp__fs<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let sftp = session.sftp().await?;
+
+    let dir = PathBuf::from("/tmp").join(format!("sftp_fs_{}", Uuid::new_v4()));
+    assert!(!sftp.exists(&dir).await);
+
+    sftp.create_dir_all(&dir.join("a/b")).await?;
+    // Already there
+    sftp.create_dir_all(&dir.join("a")).await?;
+    assert!(sftp.metadata(&dir.join("a/b")).await?.is_dir());
+
+    let path = dir.join("a/b/f");
+    sftp.write(&path, "foo").await?;
+    sftp.append(&path, "bar").await?;
+    assert_eq!(sftp.read_to_string(&path).await?, "foobar");
+    let metadata = sftp.metadata(&path).await?;
+    assert!(metadata.is_file());
+    assert_eq!(metadata.len(), 6);
+    assert!(metadata.modified().is_some());
+
+    sftp.write(&path, b"baz").await?;
+    assert_eq!(sftp.read(&path).await?, b"baz");
+
+    let copy_path = dir.join("a/copy");
+    assert_eq!(sftp.copy(&path, &copy_path).await?, 3);
+    assert_eq!(sftp.read(&copy_path).await?, b"baz");
+    assert_eq!(
+        sftp.metadata(&copy_path).await?.permissions(),
+        metadata.permissions()
+    );
+
+    let link = dir.join("a/l");
+    sftp.symlink(&dir.join("missing"), &link).await?;
+    assert!(sftp.symlink_metadata(&link).await?.is_symlink());
+    assert!(!sftp.try_exists(&link).await?);
+
+    assert_eq!(
+        sftp.canonicalize(&dir.join("a/b/..")).await?,
+        sftp.canonicalize(&dir.join("a")).await?
+    );
+
+    sftp.remove_file(&copy_path).await?;
+    assert!(!sftp.exists(&copy_path).await);
+
+    sftp.remove_dir_all(&dir).await?;
+    assert!(!sftp.try_exists(&dir).await?);
+
+    Ok(())
+}
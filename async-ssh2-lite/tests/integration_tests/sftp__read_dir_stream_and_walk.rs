@@ -0,0 +1,127 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, fs, path::PathBuf};
+
+use async_ssh2_lite::{
+    AsyncSession, AsyncSessionStream, DirTransferConfiguration, WalkConfiguration,
+};
+use futures_util::{future, StreamExt as _, TryStreamExt as _};
+use uuid::Uuid;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    // The streams are Send
+    tokio::spawn(async move {
+        __run__sftp__read_dir_stream_and_walk(&session)
+            .await
+            .unwrap()
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__sftp__read_dir_stream_and_walk(&session)
+            .await
+            .map_err(|err| err as Box<dyn error::Error>)?;
+
+        Ok(())
+    })
+}
+
+async fn __run__sftp__read_dir_stream_and_walk<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let sftp = session.sftp().await?;
+
+    let local_dir = tempfile::tempdir()?;
+    fs::create_dir_all(local_dir.path().join("a/b/c"))?;
+    fs::create_dir_all(local_dir.path().join("skip"))?;
+    for i in 0..100 {
+        fs::write(local_dir.path().join(format!("a/f{i}")), b"")?;
+    }
+    fs::write(local_dir.path().join("a/b/c/f"), b"")?;
+    fs::write(local_dir.path().join("skip/f"), b"")?;
+
+    let remote_dir = PathBuf::from("/tmp").join(format!("sftp_walk_{}", Uuid::new_v4()));
+    let report = sftp.upload_dir(local_dir.path(), &remote_dir, None).await?;
+    assert!(report.is_success());
+    // A loop, only descended into once when followed
+    sftp.symlink(&remote_dir, &remote_dir.join("a/b/loop"))
+        .await?;
+
+    let mut entries = sftp.read_dir_stream(&remote_dir.join("a")).await?;
+    let mut n = 0;
+    while let Some((path, stat)) = entries.try_next().await? {
+        assert!(path.starts_with(remote_dir.join("a")));
+        if stat.is_file() {
+            n += 1;
+        }
+    }
+    assert_eq!(n, 100);
+
+    let mut configuration = WalkConfiguration::new();
+    configuration.set_min_depth(1);
+    configuration.set_filter_entry(|entry| !entry.path.ends_with("skip"));
+    let paths = sftp
+        .walk(&remote_dir, configuration.clone())
+        .map_ok(|entry| entry.path)
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert!(!paths.contains(&remote_dir));
+    assert!(paths.contains(&remote_dir.join("a/b/c/f")));
+    assert!(paths.contains(&remote_dir.join("a/b/loop")));
+    assert!(!paths
+        .iter()
+        .any(|path| path.starts_with(remote_dir.join("skip"))));
+
+    configuration.set_max_depth(2);
+    let n = sftp
+        .walk(&remote_dir, configuration.clone())
+        .try_filter(|entry| future::ready(entry.depth == 2))
+        .count()
+        .await;
+    // a/b and the files of a
+    assert_eq!(n, 101);
+
+    let mut configuration = WalkConfiguration::new();
+    configuration.set_follow_symlinks(true);
+    let paths = sftp
+        .walk(&remote_dir, configuration)
+        .map_ok(|entry| entry.path)
+        .try_collect::<Vec<_>>()
+        .await?;
+    assert!(paths.contains(&remote_dir.join("a/b/loop")));
+    assert!(!paths.contains(&remote_dir.join("a/b/loop/a")));
+
+    let mut configuration = DirTransferConfiguration::new();
+    configuration.set_sync(true);
+    configuration.set_delete(true);
+    let empty_dir = tempfile::tempdir()?;
+    sftp.unlink(&remote_dir.join("a/b/loop")).await?;
+    let report = sftp
+        .upload_dir(empty_dir.path(), &remote_dir, configuration)
+        .await?;
+    assert!(report.is_success());
+    sftp.rmdir(&remote_dir).await?;
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{
+    error, fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use async_ssh2_lite::{ssh2::FileStat, AsyncSession, AsyncSessionStream, TransferConfiguration};
+use rand::RngCore as _;
+use uuid::Uuid;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    // The transfer futures are Send
+    tokio::spawn(async move { __run__sftp__upload_and_download(&session).await.unwrap() }).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn changed_remote_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    use std::sync::atomic::AtomicBool;
+
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+    let sftp = session.sftp().await?;
+
+    let dir = tempfile::tempdir()?;
+    let download_path = dir.path().join("download");
+    let remote_path = PathBuf::from("/tmp").join(format!("sftp_download_{}", Uuid::new_v4()));
+    let len = 1024 * 1024;
+
+    // Grows, then shrinks, while the download is under way
+    for shrink in [false, true] {
+        sftp.write(&remote_path, vec![0; len]).await?;
+
+        let started = Arc::new(AtomicBool::new(false));
+        let mut configuration = TransferConfiguration::new();
+        configuration.set_chunk_size(4 * 1024);
+        configuration.set_concurrency(1);
+        configuration.set_on_progress({
+            let started = started.clone();
+            move |_| started.store(true, Ordering::SeqCst)
+        });
+
+        let download = sftp.download(&remote_path, &download_path, configuration);
+        let change = async {
+            while !started.load(Ordering::SeqCst) {
+                tokio::time::sleep(tokio::time::Duration::from_millis(1)).await;
+            }
+            if shrink {
+                sftp.setstat(
+                    &remote_path,
+                    FileStat {
+                        size: Some(len as u64 / 4),
+                        uid: None,
+                        gid: None,
+                        perm: None,
+                        atime: None,
+                        mtime: None,
+                    },
+                )
+                .await
+            } else {
+                sftp.append(&remote_path, b"more").await
+            }
+        };
+        let (ret, changed) = tokio::join!(download, change);
+        changed?;
+        let err = ret.expect_err("the remote file changed");
+        assert!(
+            err.to_string().contains("changed during the transfer"),
+            "{err}"
+        );
+    }
+
+    sftp.unlink(&remote_path).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__sftp__upload_and_download(&session)
+            .await
+            .map_err(|err| err as Box<dyn error::Error>)?;
+
+        Ok(())
+    })
+}
+
+async fn __run__sftp__upload_and_download<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let sftp = session.sftp().await?;
+
+    let dir = tempfile::tempdir()?;
+    let local_path = dir.path().join("upload");
+    let mut content = vec![0; 3 * 1024 * 1024 + 1];
+    rand::thread_rng().fill_bytes(&mut content);
+    fs::write(&local_path, &content)?;
+    let len = content.len() as u64;
+
+    let remote_path = PathBuf::from("/tmp").join(format!("sftp_upload_{}", Uuid::new_v4()));
+
+    let transferred = Arc::new(AtomicU64::new(0));
+    let mut configuration = TransferConfiguration::new();
+    configuration.set_chunk_size(64 * 1024);
+    configuration.set_on_progress({
+        let transferred = transferred.clone();
+        move |progress| {
+            transferred.store(progress.transferred, Ordering::SeqCst);
+            assert_eq!(progress.total, len);
+        }
+    });
+
+    let n = sftp
+        .upload(&local_path, &remote_path, configuration.clone())
+        .await?;
+    assert_eq!(n, len);
+    assert_eq!(transferred.load(Ordering::SeqCst), n);
+    let remote_stat = sftp.stat(&remote_path).await?;
+    assert_eq!(
+        remote_stat.mtime,
+        Some(
+            fs::metadata(&local_path)?
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs()
+        )
+    );
+
+    let download_path = dir.path().join("download");
+    let n = sftp
+        .download(&remote_path, &download_path, configuration)
+        .await?;
+    assert_eq!(n, len);
+    assert_eq!(fs::read(&download_path)?, fs::read(&local_path)?);
+
+    // Resuming after a transfer that failed half way
+    let mut configuration = TransferConfiguration::new();
+    configuration.set_resume(true);
+    configuration.set_resume_verify_len(4096);
+
+    fs::OpenOptions::new()
+        .write(true)
+        .open(&download_path)?
+        .set_len(len / 2)?;
+    let n = sftp
+        .download(&remote_path, &download_path, configuration.clone())
+        .await?;
+    assert_eq!(n, len);
+    assert_eq!(fs::read(&download_path)?, fs::read(&local_path)?);
+
+    sftp.setstat(
+        &remote_path,
+        FileStat {
+            size: Some(len / 3),
+            uid: None,
+            gid: None,
+            perm: None,
+            atime: None,
+            mtime: None,
+        },
+    )
+    .await?;
+    let n = sftp
+        .upload(&local_path, &remote_path, configuration)
+        .await?;
+    assert_eq!(n, len);
+    assert_eq!(sftp.stat(&remote_path).await?.size, Some(len));
+
+    sftp.unlink(&remote_path).await?;
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+#![cfg(any(feature = "async-io", feature = "tokio"))]
+
+use std::{error, fs, path::PathBuf};
+
+use async_ssh2_lite::{AsyncSession, AsyncSessionStream, DirTransferConfiguration};
+use uuid::Uuid;
+
+use super::{
+    helpers::get_connect_addr, session__userauth_pubkey::__run__session__userauth_pubkey_file,
+};
+
+//
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn simple_with_tokio() -> Result<(), Box<dyn error::Error>> {
+    let mut session =
+        AsyncSession::<async_ssh2_lite::TokioTcpStream>::connect(get_connect_addr()?, None).await?;
+    __run__session__userauth_pubkey_file(&mut session).await?;
+
+    // The transfer futures are Send
+    tokio::spawn(async move {
+        __run__sftp__upload_dir_and_download_dir(&session)
+            .await
+            .unwrap()
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "async-io")]
+#[test]
+fn simple_with_async_io() -> Result<(), Box<dyn error::Error>> {
+    futures_lite::future::block_on(async {
+        let mut session =
+            AsyncSession::<async_ssh2_lite::AsyncIoTcpStream>::connect(get_connect_addr()?, None)
+                .await?;
+        __run__session__userauth_pubkey_file(&mut session).await?;
+        __run__sftp__upload_dir_and_download_dir(&session)
+            .await
+            .map_err(|err| err as Box<dyn error::Error>)?;
+
+        Ok(())
+    })
+}
+
+async fn __run__sftp__upload_dir_and_download_dir<S: AsyncSessionStream + Send + Sync + 'static>(
+    session: &AsyncSession<S>,
+) -> Result<(), Box<dyn error::Error + Send + Sync>> {
+    let sftp = session.sftp().await?;
+
+    let local_dir = tempfile::tempdir()?;
+    fs::create_dir_all(local_dir.path().join("a/b"))?;
+    fs::write(local_dir.path().join("a/f1"), b"foo")?;
+    fs::write(local_dir.path().join("a/b/f2"), vec![1; 1024 * 1024])?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("f1", local_dir.path().join("a/l"))?;
+
+    let remote_dir = PathBuf::from("/tmp").join(format!("sftp_upload_dir_{}", Uuid::new_v4()));
+
+    let report = sftp.upload_dir(local_dir.path(), &remote_dir, None).await?;
+    println!("sftp__upload_dir_and_download_dir upload_dir report:{report:?}");
+    assert!(report.is_success());
+    assert!(report.copied.contains(&PathBuf::from("a/b/f2")));
+
+    let download_dir = tempfile::tempdir()?;
+    let report = sftp
+        .download_dir(&remote_dir, download_dir.path(), None)
+        .await?;
+    assert!(report.is_success());
+    assert_eq!(fs::read(download_dir.path().join("a/f1"))?, b"foo");
+    assert_eq!(
+        fs::read(download_dir.path().join("a/b/f2"))?,
+        vec![1; 1024 * 1024]
+    );
+    #[cfg(unix)]
+    assert_eq!(
+        fs::read_link(download_dir.path().join("a/l"))?,
+        PathBuf::from("f1")
+    );
+
+    // Syncing copies only what changed
+    let mut configuration = DirTransferConfiguration::new();
+    configuration.set_sync(true);
+    configuration.set_delete(true);
+
+    fs::remove_file(local_dir.path().join("a/f1"))?;
+    fs::write(local_dir.path().join("a/f3"), b"bar")?;
+    let report = sftp
+        .upload_dir(local_dir.path(), &remote_dir, configuration.clone())
+        .await?;
+    println!("sftp__upload_dir_and_download_dir sync report:{report:?}");
+    assert!(report.is_success());
+    assert_eq!(report.copied, vec![PathBuf::from("a/f3")]);
+    assert_eq!(report.deleted, vec![PathBuf::from("a/f1")]);
+    assert!(report.skipped.contains(&PathBuf::from("a/b/f2")));
+
+    // Syncing an empty directory removes everything
+    let empty_dir = tempfile::tempdir()?;
+    let report = sftp
+        .upload_dir(empty_dir.path(), &remote_dir, configuration)
+        .await?;
+    assert!(report.is_success());
+    sftp.rmdir(&remote_dir).await?;
+
+    Ok(())
+}
@@ -16,7 +16,7 @@ use super::{
 /*
 tokio 1 thread, one by one channel_session and close => no limit
 tokio 1 thread, spawn channel_session => limit by MaxSessions
-tokio n threads, spawn channel_session => not support
+tokio n threads, spawn channel_session => limit by MaxSessions
 */
 
 //
@@ -108,11 +108,7 @@ async fn concurrently_with_tokio() -> Result<(), Box<dyn error::Error>> {
     let session = Arc::new(session);
 
     let mut handles = vec![];
-    /*
-    if `for i in 0..3 {` Maybe
-    Ssh2(Error { code: Session(-4), msg: "Unexpected error" })
-    */
-    for i in 0..1 {
+    for i in 0..8 {
         let session = session.clone();
         let handle = tokio::spawn(async move {
             match __run__session__channel_session__exec(&session, i, "concurrently_with_tokio")
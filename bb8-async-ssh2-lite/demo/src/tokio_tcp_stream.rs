@@ -1,5 +1,5 @@
 /*
-RUST_BACKTRACE=1 RUST_LOG=trace cargo run -p bb8-async-ssh2-lite-demo --bin bb8_asl_demo_tokio_tcp_stream -- 127.0.0.1:22 root '~/.ssh/id_rsa'
+RUST_BACKTRACE=1 RUST_LOG=trace cargo run -p bb8-async-ssh2-lite-demo --bin bb8_asl_demo_tokio_tcp_stream -- localhost:22 root '~/.ssh/id_rsa'
 */
 
 use std::{env, path::PathBuf};
@@ -12,13 +12,13 @@ use futures_util::{future::join_all, AsyncReadExt as _};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let socket_addr = env::args().nth(1).ok_or("socket_addr missing")?.parse()?;
+    let host = env::args().nth(1).ok_or("host missing")?;
     let username = env::args().nth(2).ok_or("username missing")?;
     let privatekey: PathBuf = env::args().nth(3).ok_or("privatekey missing")?.parse()?;
 
     {
-        let mut mgr = AsyncSessionManagerWithTokioTcpStream::new(
-            socket_addr,
+        let mut mgr = AsyncSessionManagerWithTokioTcpStream::new_with_host(
+            &host,
             None,
             &username,
             AsyncSessionUserauthType::PubkeyFile {
@@ -56,8 +56,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     {
-        let mut mgr = AsyncSessionManagerWithTokioTcpStream::new(
-            socket_addr,
+        let mut mgr = AsyncSessionManagerWithTokioTcpStream::new_with_host(
+            &host,
             None,
             &username,
             AsyncSessionUserauthType::PubkeyFile {
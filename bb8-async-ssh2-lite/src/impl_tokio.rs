@@ -8,24 +8,37 @@ use tokio_crate::sync::Semaphore;
 
 use crate::{AsyncSessionManagerError, AsyncSessionUserauthType, AsyncSftpManagerError};
 
+//
+//
+//
+/// Where `AsyncSessionManagerWithTokioTcpStream` connects to.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Addr(SocketAddr),
+    /// `host:port`, `[v6]:port` or `host`, resolved on every connect, see
+    /// `AsyncSession::connect_host`.
+    Host(String),
+}
+
 //
 //
 //
 #[derive(Debug)]
 #[non_exhaustive]
 pub struct AsyncSessionManagerWithTokioTcpStream {
-    pub socket_addr: SocketAddr,
+    pub target: Target,
     pub configuration: Option<SessionConfiguration>,
     pub username: String,
     pub userauth_type: AsyncSessionUserauthType,
     //
     max_number_of_unauthenticated_conns: Option<Semaphore>,
+    keepalive_count_max: Option<u32>,
 }
 
 impl Clone for AsyncSessionManagerWithTokioTcpStream {
     fn clone(&self) -> Self {
         Self {
-            socket_addr: self.socket_addr,
+            target: self.target.clone(),
             configuration: self.configuration.clone(),
             username: self.username.clone(),
             userauth_type: self.userauth_type.clone(),
@@ -36,6 +49,7 @@ impl Clone for AsyncSessionManagerWithTokioTcpStream {
                 .map(|max_number_of_unauthenticated_conns| {
                     Semaphore::new(max_number_of_unauthenticated_conns.available_permits())
                 }),
+            keepalive_count_max: self.keepalive_count_max,
         }
     }
 }
@@ -46,14 +60,44 @@ impl AsyncSessionManagerWithTokioTcpStream {
         configuration: impl Into<Option<SessionConfiguration>>,
         username: impl AsRef<str>,
         userauth_type: AsyncSessionUserauthType,
+    ) -> Self {
+        Self::new_with_target(
+            Target::Addr(socket_addr),
+            configuration,
+            username,
+            userauth_type,
+        )
+    }
+
+    /// `host` is `host:port`, `[v6]:port` or `host`, see `AsyncSession::connect_host`.
+    pub fn new_with_host(
+        host: impl AsRef<str>,
+        configuration: impl Into<Option<SessionConfiguration>>,
+        username: impl AsRef<str>,
+        userauth_type: AsyncSessionUserauthType,
+    ) -> Self {
+        Self::new_with_target(
+            Target::Host(host.as_ref().into()),
+            configuration,
+            username,
+            userauth_type,
+        )
+    }
+
+    pub fn new_with_target(
+        target: Target,
+        configuration: impl Into<Option<SessionConfiguration>>,
+        username: impl AsRef<str>,
+        userauth_type: AsyncSessionUserauthType,
     ) -> Self {
         Self {
-            socket_addr,
+            target,
             configuration: configuration.into(),
             username: username.as_ref().into(),
             userauth_type,
             //
             max_number_of_unauthenticated_conns: None,
+            keepalive_count_max: None,
         }
     }
 
@@ -70,6 +114,16 @@ impl AsyncSessionManagerWithTokioTcpStream {
             .as_ref()
             .map(|x| x.available_permits())
     }
+
+    /// Runs a keepalive task per connection so `has_broken` notices dead peers, the configuration
+    /// needs `set_keepalive`. See `AsyncSession::spawn_keepalive_with_tokio`.
+    pub fn set_keepalive_count_max(&mut self, count_max: u32) {
+        self.keepalive_count_max = Some(count_max);
+    }
+
+    pub fn get_keepalive_count_max(&self) -> Option<u32> {
+        self.keepalive_count_max
+    }
 }
 
 #[async_trait]
@@ -91,10 +145,11 @@ impl bb8::ManageConnection for AsyncSessionManagerWithTokioTcpStream {
 
         //
         match connect_inner(
-            self.socket_addr,
+            &self.target,
             self.configuration.to_owned(),
             &self.username,
             &self.userauth_type,
+            self.keepalive_count_max,
         )
         .await
         {
@@ -115,8 +170,8 @@ impl bb8::ManageConnection for AsyncSessionManagerWithTokioTcpStream {
         Ok(())
     }
 
-    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
-        false
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.is_broken()
     }
 }
 
@@ -156,8 +211,8 @@ impl bb8::ManageConnection for AsyncSftpManagerWithTokioTcpStream {
         Ok(())
     }
 
-    fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
-        false
+    fn has_broken(&self, conn: &mut Self::Connection) -> bool {
+        conn.is_broken()
     }
 }
 
@@ -170,14 +225,21 @@ Ssh2(Error { code: Session(-39), msg: "Channel can not be reused" })
 //
 //
 async fn connect_inner(
-    socket_addr: SocketAddr,
+    target: &Target,
     configuration: Option<SessionConfiguration>,
     username: &str,
     userauth_type: &AsyncSessionUserauthType,
+    keepalive_count_max: Option<u32>,
 ) -> Result<AsyncSession<TokioTcpStream>, AsyncSessionManagerError> {
-    let mut session = AsyncSession::<TokioTcpStream>::connect(socket_addr, configuration)
-        .await
-        .map_err(AsyncSessionManagerError::ConnectError)?;
+    let mut session = match target {
+        Target::Addr(socket_addr) => {
+            AsyncSession::<TokioTcpStream>::connect(*socket_addr, configuration).await
+        }
+        Target::Host(host) => {
+            AsyncSession::<TokioTcpStream>::connect_host(host, configuration).await
+        }
+    }
+    .map_err(AsyncSessionManagerError::ConnectError)?;
 
     session
         .handshake()
@@ -218,6 +280,13 @@ async fn connect_inner(
         return Err(AsyncSessionManagerError::AssertAuthenticated);
     }
 
+    if let Some(count_max) = keepalive_count_max {
+        session
+            .spawn_keepalive_with_tokio(count_max)
+            .map_err(AsyncSessionManagerError::KeepaliveError)?
+            .detach();
+    }
+
     Ok(session)
 }
 
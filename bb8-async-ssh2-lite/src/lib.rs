@@ -4,7 +4,9 @@ pub use bb8;
 #[cfg(feature = "tokio")]
 mod impl_tokio;
 #[cfg(feature = "tokio")]
-pub use impl_tokio::{AsyncSessionManagerWithTokioTcpStream, AsyncSftpManagerWithTokioTcpStream};
+pub use impl_tokio::{
+    AsyncSessionManagerWithTokioTcpStream, AsyncSftpManagerWithTokioTcpStream, Target,
+};
 
 //
 //
@@ -26,11 +28,13 @@ pub enum AsyncSessionUserauthType {
 //
 //
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum AsyncSessionManagerError {
     ConnectError(async_ssh2_lite::Error),
     HandshakeError(async_ssh2_lite::Error),
     UserauthError(async_ssh2_lite::Error),
     AssertAuthenticated,
+    KeepaliveError(async_ssh2_lite::Error),
     Unknown(String),
 }
 impl core::fmt::Display for AsyncSessionManagerError {